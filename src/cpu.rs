@@ -1,23 +1,34 @@
 pub mod registers;
 pub mod instructions;
+pub mod history;
+pub mod bus;
 
 use std::fmt::Debug;
 use registers::Registers;
 use crate::memory_bus::MemoryBus;
+use bus::{CpuBus, M_CYCLE};
 use instructions::Instruction;
+use history::ExecutionHistory;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CpuState{
-	Running, Halted
+	Running, Halted, Locked, Stopped, SwitchingSpeed(u16)
 }
 
+const SPEED_SWITCH_NB_M_CYCLES: u16 = 2050;
+
 pub struct Cpu {
 	pub registers: Registers,
 	pub current_op: Option<Instruction>,
 	pub next_op: Option<Instruction>,
+	pub next_op_address: u16,
+	pub history: ExecutionHistory,
 	ime_scheduled: bool,
 	ime_set: bool,
-	state: CpuState
+	is_ime_just_enabled: bool,
+	is_halt_bug_pending: bool,
+	state: CpuState,
+	illegal_opcode: Option<(u16, u8)>
 }
 
 impl Cpu {
@@ -26,54 +37,156 @@ impl Cpu {
 			registers: Registers::new(),
     		current_op: None,
 			next_op: Some(Instruction::NOP(1, 1)),				// Fake 'execute' of first tick which is just a 'fetch' 
+			next_op_address: 0x0000,
+			history: ExecutionHistory::new(),
 			ime_scheduled: false,
 			ime_set: false,
-			state: CpuState::Running
+			is_ime_just_enabled: false,
+			is_halt_bug_pending: false,
+			state: CpuState::Running,
+			illegal_opcode: None
 		};
 		// cpu.registers.init();
 		cpu
 	}
-	pub fn tick(&mut self, memory_bus: &mut MemoryBus) -> u8 {
+	/* Runs one instruction, `clock` advancing the rest of the system by one M-cycle ahead of each of its bus accesses.
+	Returns the number of cycles elapsed, including the prefetch of the next opcode and the interrupt check */
+	pub fn tick(&mut self, memory_bus: &mut MemoryBus, clock: &mut dyn FnMut(&mut MemoryBus)) -> u8 {
+		let memory_bus = &mut CpuBus::new(memory_bus, clock);
 		self.current_op = self.next_op;								// Account for Sharp SM83 fetch/execute overlap
+		if self.current_op.is_none_or(|instruction| instruction.length() != 0) {
+			self.history.record_pc(self.next_op_address);
+		}
+		self.is_ime_just_enabled = self.ime_scheduled;
 		if self.ime_scheduled {
 			self.ime_set = true;
 			self.ime_scheduled = false;
 		}
 		self.exec_current_op(memory_bus);
+		let nb_execute_cycles = self.get_nb_clock_current_op().saturating_sub(self.get_nb_fetch_cycles_current_op());
+		while memory_bus.nb_cycles < nb_execute_cycles {			// Internal cycles not modelled by the instruction itself
+			memory_bus.cycle();
+		}
 		self.fetch_next_opcode(memory_bus);							// Account for Sharp SM83 fetch/execute overlap
-		self.get_nb_clock_current_op()
+		self.tick_low_power_state(memory_bus);
+		self.check_interrupts(memory_bus);
+		memory_bus.nb_cycles
+	}
+	pub fn jump_to(&mut self, memory_bus: &mut MemoryBus, address: u16) {
+		self.registers.program_counter = address;
+		self.fetch_next_opcode(&mut CpuBus::new(memory_bus, &mut |_| {}));
 	}
-	fn fetch_next_opcode(&mut self, memory_bus: &MemoryBus) {
-		if memory_bus.read_byte(0xFFFF) & memory_bus.read_byte(0xFF0F) & 0x1F != 0 && (self.ime_set || self.state == CpuState::Halted) 
-		{
+	/* Address and value of the illegal opcode that hard-locked the CPU */
+	pub fn locked_at(&self) -> Option<(u16, u8)> {
+		self.illegal_opcode
+	}
+	/* Sampled at the end of the instruction, once the prefetch M-cycle has elapsed, so that interrupts raised
+	during its execution are taken into account; the prefetched opcode gets replaced by the dispatch */
+	fn check_interrupts(&mut self, memory_bus: &mut CpuBus) {
+		if matches!(self.state, CpuState::Locked | CpuState::Stopped | CpuState::SwitchingSpeed(_)) || memory_bus.read_byte_untimed(0xFFFF) & memory_bus.read_byte_untimed(0xFF0F) & 0x1F == 0 {
+			return;
+		}
+		if self.ime_set {
+			self.next_op = Some(Instruction::ISR(0, if self.state == CpuState::Halted {24} else {20}));
+			self.registers.program_counter = self.next_op_address;
+			self.state = CpuState::Running;
+		} else if self.state == CpuState::Halted {
 			self.state = CpuState::Running;
-			if self.ime_set {
-				self.next_op = Some(Instruction::ISR(0, 20));
-			} else {
-				self.next_op = Instruction::from_opcode(self.fetch_pc(memory_bus), self, memory_bus);
+			self.fetch_next_opcode(memory_bus);
+		}
+	}
+	/* Executing HALT while an interrupt is already pending doesn't halt; with IME unset the following
+	opcode is then read twice, and right after EI the dispatched interrupt returns to the HALT itself */
+	pub(crate) fn halt(&mut self, memory_bus: &mut CpuBus) {
+		if memory_bus.read_byte_untimed(0xFFFF) & memory_bus.read_byte_untimed(0xFF0F) & 0x1F == 0 {
+			self.state = CpuState::Halted;
+		} else if !self.ime_set {
+			self.is_halt_bug_pending = true;
+		} else if self.is_ime_just_enabled {
+			self.registers.program_counter = self.next_op_address;
+		}
+	}
+	pub fn state(&self) -> CpuState {
+		self.state
+	}
+	/* Without a button held, STOP resets DIV then either switches the speed, pausing the CPU for 2050 M-cycles, or enters STOP mode
+	until a joypad line goes low. Its second byte gets skipped unless an interrupt is pending */
+	pub(crate) fn stop(&mut self, memory_bus: &mut CpuBus) {
+		let is_interrupt_pending = memory_bus.read_byte_untimed(0xFFFF) & memory_bus.read_byte_untimed(0xFF0F) & 0x1F != 0;
+		if memory_bus.input_memory.read() & 0x0F != 0x0F {
+			if !is_interrupt_pending {
+				self.fetch_pc(memory_bus);
+				self.state = CpuState::Halted;
 			}
-		} else {
-			if self.state == CpuState::Halted {
-				self.next_op = Some(Instruction::NOP(0, if memory_bus.is_double_speed {2} else {1}));
-			} else {
-				self.next_op = Instruction::from_opcode(self.fetch_pc(memory_bus), self, memory_bus);
+			return;
+		}
+		memory_bus.write_byte_untimed(0xFF04, 0x00);
+		if memory_bus.is_cgb && memory_bus.speed_chg_scheduled {
+			memory_bus.is_double_speed = !memory_bus.is_double_speed;
+			memory_bus.speed_chg_scheduled = false;
+			if !is_interrupt_pending {
+				self.state = CpuState::SwitchingSpeed(SPEED_SWITCH_NB_M_CYCLES);
 			}
+		} else {
+			self.state = CpuState::Stopped;
+		}
+		if !is_interrupt_pending {
+			self.fetch_pc(memory_bus);
 		}
 	}
-	fn exec_current_op(&mut self, memory_bus: &mut MemoryBus) {
-		self.execute_op(memory_bus,
-			if let Some(instruction) = self.current_op {
-				instruction
-			} else {
-				panic!("Unknown opcode 0x{:x} at location 0x{:x}!\n{:?}", memory_bus.read_byte(self.registers.program_counter - 1), self.registers.program_counter - 1, self);
-			}
-		)
+	/* Interrupts don't wake the CPU up from STOP mode nor from a speed switch */
+	fn tick_low_power_state(&mut self, memory_bus: &mut CpuBus) {
+		match self.state {
+			CpuState::Stopped if memory_bus.input_memory.read() & 0x0F == 0x0F => {},
+			CpuState::SwitchingSpeed(count) if count != 0 => self.state = CpuState::SwitchingSpeed(count - 1),
+			CpuState::Stopped | CpuState::SwitchingSpeed(_) => {
+				self.state = CpuState::Running;
+				self.fetch_next_opcode(memory_bus);
+			},
+			_ => {}
+		}
+	}
+	fn fetch_next_opcode(&mut self, memory_bus: &mut CpuBus) {
+		self.next_op_address = self.registers.program_counter;
+		if self.state != CpuState::Running {						// Only a reset gets the CPU out of a lockup, interrupts included
+			self.next_op = Some(Instruction::NOP(0, M_CYCLE));
+			return;
+		}
+		let opcode = if self.is_halt_bug_pending {
+			self.is_halt_bug_pending = false;
+			memory_bus.read_byte(self.registers.program_counter)
+		} else {
+			self.fetch_pc(memory_bus)
+		};
+		self.next_op = Instruction::from_opcode(opcode, || self.fetch_pc(memory_bus));
+	}
+	fn exec_current_op(&mut self, memory_bus: &mut CpuBus) {
+		if let Some(instruction) = self.current_op {
+			self.execute_op(memory_bus, instruction)
+		} else {
+			let opcode = memory_bus.read_byte_untimed(self.next_op_address);
+			self.state = CpuState::Locked;
+			self.illegal_opcode = Some((self.next_op_address, opcode));
+			self.current_op = Some(Instruction::NOP(0, 4));
+			println!("CPU locked up: illegal opcode 0x{:02X} at 0x{:04X}\n{:?}\n{}", opcode, self.next_op_address, self, self.history);
+		}
 	}
-	fn fetch_pc(&mut self, memory_bus: &MemoryBus) -> u8 {
+	fn fetch_pc(&mut self, memory_bus: &mut CpuBus) -> u8 {
 		let data = memory_bus.read_byte(self.registers.program_counter);
 		self.registers.program_counter = self.registers.program_counter.overflowing_add(1).0;
 		data
 	}
+	/* Cycles of the current instruction already spent by the previous one prefetching it: the opcode fetch(es),
+	or the discarded fetch an interrupt dispatch replaces. Halted and locked pseudo-NOPs don't fetch anything */
+	fn get_nb_fetch_cycles_current_op(&self) -> u8 {
+		match self.current_op.unwrap() {
+			Instruction::ISR(_, _) => M_CYCLE,
+			Instruction::RLC(..) | Instruction::RL(..) | Instruction::RRC(..) | Instruction::RR(..) | Instruction::SLA(..) | Instruction::SWAP(..)
+				| Instruction::SRA(..) | Instruction::SRL(..) | Instruction::BIT(..) | Instruction::SET(..) | Instruction::RES(..) => 2 * M_CYCLE,
+			instruction if instruction.length() == 0 => 0,
+			_ => M_CYCLE
+		}
+	}
 	fn get_nb_clock_current_op(&mut self) -> u8 {
 		match self.current_op.unwrap() {
 			Instruction::LD(_, nb_cycles, _, _) => nb_cycles,
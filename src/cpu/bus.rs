@@ -0,0 +1,71 @@
+use std::ops::{Deref, DerefMut};
+use crate::memory_bus::MemoryBus;
+use crate::memory_bus::ppu_memory::OamCorruption;
+
+pub const M_CYCLE: u8 = 4;
+
+/* CPU side of the memory bus: every access takes one M-cycle, during which the rest of the system is clocked
+before the access happens, so that the timer, the PPU and DMAs are observed and altered on the right cycle */
+pub struct CpuBus<'a> {
+	memory_bus: &'a mut MemoryBus,
+	clock: &'a mut dyn FnMut(&mut MemoryBus),
+	pub nb_cycles: u8
+}
+
+impl<'a> CpuBus<'a> {
+	pub fn new(memory_bus: &'a mut MemoryBus, clock: &'a mut dyn FnMut(&mut MemoryBus)) -> Self {
+		CpuBus {
+			memory_bus,
+			clock,
+			nb_cycles: 0
+		}
+	}
+	/* Internal M-cycle, without any bus access */
+	pub fn cycle(&mut self) {
+		(self.clock)(self.memory_bus);
+		self.nb_cycles += M_CYCLE;
+	}
+	/* Internal M-cycle in which the IDU increments or decrements a register pair, which counts as a write for the OAM bug */
+	pub fn idu_cycle(&mut self, address: u16) {
+		self.cycle();
+		self.memory_bus.trigger_oam_bug(address, OamCorruption::Write);
+	}
+	pub fn read_byte(&mut self, address: u16) -> u8 {
+		self.cycle();
+		self.memory_bus.trigger_oam_bug(address, OamCorruption::Read);
+		self.memory_bus.oam_dma_conflict(address).unwrap_or_else(|| self.memory_bus.read_byte(address))
+	}
+	/* Read whose address gets incremented or decremented by the IDU in the same M-cycle (LD A,[HL+], POP) */
+	pub fn read_byte_idu(&mut self, address: u16) -> u8 {
+		self.cycle();
+		self.memory_bus.trigger_oam_bug(address, OamCorruption::ReadIncrease);
+		self.memory_bus.oam_dma_conflict(address).unwrap_or_else(|| self.memory_bus.read_byte(address))
+	}
+	pub fn write_byte(&mut self, address: u16, data: u8) {
+		self.cycle();
+		self.memory_bus.trigger_oam_bug(address, OamCorruption::Write);
+		if self.memory_bus.oam_dma_conflict(address).is_none() {
+			self.memory_bus.write_byte(address, data);
+		}
+	}
+	/* Accesses made by the CPU core itself (interrupt logic), which don't go through the bus */
+	pub fn read_byte_untimed(&self, address: u16) -> u8 {
+		self.memory_bus.read_byte(address)
+	}
+	pub fn write_byte_untimed(&mut self, address: u16, data: u8) {
+		self.memory_bus.write_byte(address, data);
+	}
+}
+
+impl Deref for CpuBus<'_> {
+	type Target = MemoryBus;
+	fn deref(&self) -> &MemoryBus {
+		self.memory_bus
+	}
+}
+
+impl DerefMut for CpuBus<'_> {
+	fn deref_mut(&mut self) -> &mut MemoryBus {
+		self.memory_bus
+	}
+}
@@ -0,0 +1,103 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+
+pub const PC_HISTORY_LEN: usize = 64;
+pub const INTERRUPT_HISTORY_LEN: usize = 32;
+pub const CALL_STACK_MAX_DEPTH: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallKind {
+	Call, Rst, Interrupt
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+	pub kind: CallKind,
+	pub call_site: u16,
+	pub target: u16,
+	pub stack_pointer: u16				// SP once the return address is pushed
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptDispatch {
+	pub interrupt_index: u8,
+	pub interrupt_flag: u8,				// IF when dispatched, before acknowledgement
+	pub interrupt_enable: u8,
+	pub return_address: u16
+}
+
+/* Shadow call stack and ring buffers of the last executed instructions and dispatched interrupts */
+pub struct ExecutionHistory {
+	pub call_stack: Vec<CallFrame>,
+	pub pcs: VecDeque<u16>,
+	pub interrupts: VecDeque<InterruptDispatch>
+}
+
+impl ExecutionHistory {
+	pub fn new() -> Self {
+		ExecutionHistory {
+			call_stack: Vec::new(),
+			pcs: VecDeque::with_capacity(PC_HISTORY_LEN),
+			interrupts: VecDeque::with_capacity(INTERRUPT_HISTORY_LEN)
+		}
+	}
+	pub fn record_pc(&mut self, address: u16) {
+		if self.pcs.len() == PC_HISTORY_LEN {
+			self.pcs.pop_front();
+		}
+		self.pcs.push_back(address);
+	}
+	pub fn record_interrupt(&mut self, dispatch: InterruptDispatch) {
+		if self.interrupts.len() == INTERRUPT_HISTORY_LEN {
+			self.interrupts.pop_front();
+		}
+		self.interrupts.push_back(dispatch);
+	}
+	pub fn push_call(&mut self, frame: CallFrame) {
+		if self.call_stack.len() == CALL_STACK_MAX_DEPTH {
+			self.call_stack.remove(0);
+		}
+		self.call_stack.push(frame);
+	}
+	/* Frames are matched on SP rather than popped blindly, so that code dropping its return address doesn't desync the stack */
+	pub fn pop_return(&mut self, stack_pointer: u16) {
+		while self.call_stack.last().is_some_and(|frame| frame.stack_pointer <= stack_pointer) {
+			self.call_stack.pop();
+		}
+	}
+}
+
+impl Display for CallFrame {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		let kind = match self.kind {
+			CallKind::Call		=> "CALL",
+			CallKind::Rst		=> "RST",
+			CallKind::Interrupt	=> "INT"
+		};
+		write!(f, "{:<4} 0x{:04X} from 0x{:04X} (SP 0x{:04X})", kind, self.target, self.call_site, self.stack_pointer)
+	}
+}
+
+impl Display for InterruptDispatch {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		write!(f, "INT 0x{:04X} IF:{:05b} IE:{:05b}, return to 0x{:04X}", 0x0040 + self.interrupt_index as u16 * 8, self.interrupt_flag & 0x1F, self.interrupt_enable & 0x1F, self.return_address)
+	}
+}
+
+impl Display for ExecutionHistory {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		writeln!(f, "Call stack (innermost last):")?;
+		for frame in &self.call_stack {
+			writeln!(f, "  {}", frame)?;
+		}
+		writeln!(f, "Last interrupts (latest last):")?;
+		for dispatch in &self.interrupts {
+			writeln!(f, "  {}", dispatch)?;
+		}
+		write!(f, "Last PCs (latest last):")?;
+		for (i, address) in self.pcs.iter().enumerate() {
+			write!(f, "{}0x{:04X}", if i % 8 == 0 {"\n  "} else {" "}, address)?;
+		}
+		Ok(())
+	}
+}
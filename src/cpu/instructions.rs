@@ -1,6 +1,4 @@
-use crate::memory_bus::MemoryBus;
-
-use super::{Cpu, CpuState};
+use super::{Cpu, bus::CpuBus, history::{CallFrame, CallKind, InterruptDispatch}};
 
 type InstrLength = u8;
 type InstrCycles = u8;
@@ -86,7 +84,69 @@ pub enum Instruction {
 }
 
 impl Instruction {
-	pub fn from_opcode(opcode: u8, cpu: &mut Cpu, memory_bus: &MemoryBus) -> Option<Instruction> {
+	pub fn length(&self) -> u8 {
+		match *self {
+			Instruction::LD(length, _, _, _) => length,
+			Instruction::LDI(length, _, _, _) => length,
+			Instruction::LDD(length, _, _, _) => length,
+			Instruction::LD16(length, _, _, _) => length,
+			Instruction::PUSH(length, _, _) => length,
+			Instruction::POP(length, _, _) => length,
+			Instruction::ADDAs(length, _, _) => length,
+			Instruction::ADCAs(length, _, _) => length,
+			Instruction::SUBs(length, _, _) => length,
+			Instruction::SBCAs(length, _, _) => length,
+			Instruction::ANDs(length, _, _) => length,
+			Instruction::XORs(length, _, _) => length,
+			Instruction::ORs(length, _, _) => length,
+			Instruction::CPs(length, _, _) => length,
+			Instruction::INCs(length, _, _) => length,
+			Instruction::DECs(length, _, _) => length,
+			Instruction::DAA(length, _) => length,
+			Instruction::CPL(length, _) => length,
+			Instruction::ADDHLss(length, _, _) => length,
+			Instruction::INCss(length, _, _) => length,
+			Instruction::DECss(length, _, _) => length,
+			Instruction::ADDSPe(length, _) => length,
+			Instruction::LDHLSPe(length, _) => length,
+			Instruction::RLCA(length, _) => length,
+			Instruction::RLA(length, _) => length,
+			Instruction::RRCA(length, _) => length,
+			Instruction::RRA(length, _) => length,
+			Instruction::RLC(length, _, _) => length,
+			Instruction::RL(length, _, _) => length,
+			Instruction::RRC(length, _, _) => length,
+			Instruction::RR(length, _, _) => length,
+			Instruction::SLA(length, _, _) => length,
+			Instruction::SWAP(length, _, _) => length,
+			Instruction::SRA(length, _, _) => length,
+			Instruction::SRL(length, _, _) => length,
+			Instruction::BIT(length, _, _, _) => length,
+			Instruction::SET(length, _, _, _) => length,
+			Instruction::RES(length, _, _, _) => length,
+			Instruction::CCF(length, _) => length,
+			Instruction::SCF(length, _) => length,
+			Instruction::NOP(length, _) => length,
+			Instruction::HALT(length, _) => length,
+			Instruction::STOP(length, _) => length,
+			Instruction::DI(length, _) => length,
+			Instruction::EI(length, _) => length,
+			Instruction::JPnn(length, _) => length,
+			Instruction::JPHL(length, _) => length,
+			Instruction::JPfnn(length, _, _) => length,
+			Instruction::JR(length, _) => length,
+			Instruction::JRf(length, _, _) => length,
+			Instruction::CALL(length, _) => length,
+			Instruction::CALLf(length, _, _) => length,
+			Instruction::ISR(length, _) => length,
+			Instruction::RET(length, _) => length,
+			Instruction::RETf(length, _, _) => length,
+			Instruction::RETI(length, _) => length,
+			Instruction::RST(length, _, _) => length
+		}
+	}
+	/* Prefixed opcodes are completed by the byte `fetch_cb_opcode` reads */
+	pub fn from_opcode(opcode: u8, fetch_cb_opcode: impl FnOnce() -> u8) -> Option<Instruction> {
 		match opcode {
 			0x00 => Some(Instruction::NOP(1, 4)),
 			0x01 => Some(Instruction::LD16(3, 12, RegPairs::RegsBC, RegPairs::BytesFromPC)),
@@ -291,7 +351,7 @@ impl Instruction {
 			0xC8 => Some(Instruction::RETf(1, 8, JumpCondition::Zero)),
 			0xC9 => Some(Instruction::RET(1, 16)),
 			0xCA => Some(Instruction::JPfnn(3, 12, JumpCondition::Zero)),
-			0xCB => Self::from_cb_opcode(cpu.fetch_pc(memory_bus)),
+			0xCB => Self::from_cb_opcode(fetch_cb_opcode()),
 			0xCC => Some(Instruction::CALLf(3, 12, JumpCondition::Zero)),
 			0xCD => Some(Instruction::CALL(3, 24)),
 			0xCE => Some(Instruction::ADCAs(2, 8, Regs::ByteFromPC)),
@@ -431,7 +491,7 @@ impl Instruction {
 }
 
 impl Cpu {
-	pub fn get_reg_value(&mut self, memory_bus: &MemoryBus, reg: Regs) -> u8 {
+	pub fn get_reg_value(&mut self, memory_bus: &mut CpuBus, reg: Regs) -> u8 {
 		match reg {
 			Regs::RegA => self.registers.a,
 			Regs::RegB => self.registers.b,
@@ -455,7 +515,7 @@ impl Cpu {
 			Regs::ByteFromPC => self.fetch_pc(memory_bus)
 		}
 	}
-	pub fn set_reg_value(&mut self, memory_bus: &mut MemoryBus, reg: Regs, data: u8) {
+	pub fn set_reg_value(&mut self, memory_bus: &mut CpuBus, reg: Regs, data: u8) {
 		match reg {
 			Regs::RegA => {self.registers.a = data}
 			Regs::RegB => {self.registers.b = data}
@@ -479,7 +539,7 @@ impl Cpu {
 			Regs::ByteFromPC => {println!("SHOULD NEVER HAPPEN")}
 		}
 	}
-	pub fn get_reg_pair_big_endian_value(&mut self, memory_bus: &MemoryBus, reg_pair: RegPairs) -> u16 {
+	pub fn get_reg_pair_big_endian_value(&mut self, memory_bus: &mut CpuBus, reg_pair: RegPairs) -> u16 {
 		match reg_pair {
 			RegPairs::RegsAF => self.registers.get_af_big_endian(),
 			RegPairs::RegsBC => self.registers.get_bc_big_endian(),
@@ -494,7 +554,7 @@ impl Cpu {
 			},
 		}
 	}
-	pub fn set_reg_pair_big_endian_value(&mut self, memory_bus: &mut MemoryBus, reg_pair: RegPairs, big_endian_value: u16) {
+	pub fn set_reg_pair_big_endian_value(&mut self, memory_bus: &mut CpuBus, reg_pair: RegPairs, big_endian_value: u16) {
 		match reg_pair {
 			RegPairs::RegsAF => {self.registers.set_af_big_endian(big_endian_value)}
 			RegPairs::RegsBC => {self.registers.set_bc_big_endian(big_endian_value)}
@@ -509,7 +569,7 @@ impl Cpu {
 			RegPairs::BytesFromPC => {println!("SHOULD NEVER HAPPEN")},
 		}
 	}
-	pub fn execute_op(&mut self, memory_bus: &mut MemoryBus, instruction: Instruction) {
+	pub fn execute_op(&mut self, memory_bus: &mut CpuBus, instruction: Instruction) {
 		match instruction {
 			Instruction::NOP(_, _) => {}
 			Instruction::LD(_, _, target, src) => {
@@ -517,12 +577,18 @@ impl Cpu {
 				self.set_reg_value(memory_bus, target, data);
 			}
 			Instruction::LDI(_, _, target, src) => {
-				let data = self.get_reg_value(memory_bus, src);
+				let data = match src {
+					Regs::HLPointee => memory_bus.read_byte_idu(self.registers.get_hl_big_endian()),
+					_ => self.get_reg_value(memory_bus, src)
+				};
 				self.set_reg_value(memory_bus, target, data);
 				self.registers.set_hl_big_endian(self.registers.get_hl_big_endian().overflowing_add(1).0);
 			}
 			Instruction::LDD(_, _, target, src) => {
-				let data = self.get_reg_value(memory_bus, src);
+				let data = match src {
+					Regs::HLPointee => memory_bus.read_byte_idu(self.registers.get_hl_big_endian()),
+					_ => self.get_reg_value(memory_bus, src)
+				};
 				self.set_reg_value(memory_bus, target, data);
 				self.registers.set_hl_big_endian(self.registers.get_hl_big_endian().overflowing_sub(1).0);
 			}
@@ -542,13 +608,14 @@ impl Cpu {
 			}
 			Instruction::PUSH(_, _, target) => {
 				let reg_content = self.get_reg_pair_big_endian_value(memory_bus, target);
+				memory_bus.idu_cycle(self.registers.stack_pointer);
 				memory_bus.write_byte(self.registers.stack_pointer.overflowing_sub(1).0, (reg_content >> 8) as u8);
 				memory_bus.write_byte(self.registers.stack_pointer.overflowing_sub(2).0, reg_content as u8);
 				self.registers.stack_pointer = self.registers.stack_pointer.overflowing_sub(2).0;
 			}
 			Instruction::POP(_, _, target) => {
 				let mut reg_content = 0x0000 as u16;
-				reg_content |= memory_bus.read_byte(self.registers.stack_pointer) as u16;
+				reg_content |= memory_bus.read_byte_idu(self.registers.stack_pointer) as u16;
 				reg_content |= (memory_bus.read_byte(self.registers.stack_pointer.overflowing_add(1).0) as u16) << 8;
 				self.registers.stack_pointer = self.registers.stack_pointer.overflowing_add(2).0;
 				self.set_reg_pair_big_endian_value(memory_bus, target, reg_content);
@@ -623,11 +690,15 @@ impl Cpu {
 				self.set_reg_value(memory_bus, _target, target_value.overflowing_sub(1).0);
 			}
 			Instruction::INCss(_, _, _target) => {
-				let data = self.get_reg_pair_big_endian_value(memory_bus, _target).overflowing_add(1).0;
+				let value = self.get_reg_pair_big_endian_value(memory_bus, _target);
+				memory_bus.idu_cycle(value);
+				let data = value.overflowing_add(1).0;
 				self.set_reg_pair_big_endian_value(memory_bus, _target, data);
 			}
 			Instruction::DECss(_, _, _target) => {
-				let data = self.get_reg_pair_big_endian_value(memory_bus, _target).overflowing_sub(1).0;
+				let value = self.get_reg_pair_big_endian_value(memory_bus, _target);
+				memory_bus.idu_cycle(value);
+				let data = value.overflowing_sub(1).0;
 				self.set_reg_pair_big_endian_value(memory_bus, _target, data);
 			}
 			Instruction::ADDHLss(_, _, _operand) => {
@@ -860,10 +931,12 @@ impl Cpu {
 			}
 			Instruction::CALL(_, _) => {
 				let address = self.get_reg_pair_big_endian_value(memory_bus, RegPairs::BytesFromPC);
+				memory_bus.idu_cycle(self.registers.stack_pointer);
 				memory_bus.write_byte(self.registers.stack_pointer.overflowing_sub(1).0, (self.registers.program_counter >> 8) as u8);
 				memory_bus.write_byte(self.registers.stack_pointer.overflowing_sub(2).0, self.registers.program_counter as u8);
 				self.registers.stack_pointer = self.registers.stack_pointer.overflowing_sub(2).0;
 				self.registers.program_counter = address;
+				self.history.push_call(CallFrame { kind: CallKind::Call, call_site: self.next_op_address, target: address, stack_pointer: self.registers.stack_pointer });
 			}
 			Instruction::CALLf(len, _, condition) => {
 				let address = self.get_reg_pair_big_endian_value(memory_bus, RegPairs::BytesFromPC);
@@ -876,36 +949,46 @@ impl Cpu {
 				}
 				if do_call {
 					self.current_op = Some(Instruction::CALLf(len, 24, condition));
+					memory_bus.idu_cycle(self.registers.stack_pointer);
 					memory_bus.write_byte(self.registers.stack_pointer.overflowing_sub(1).0, (self.registers.program_counter >> 8) as u8);
 					memory_bus.write_byte(self.registers.stack_pointer.overflowing_sub(2).0, self.registers.program_counter as u8);
 					self.registers.stack_pointer = self.registers.stack_pointer.overflowing_sub(2).0;
 					self.registers.program_counter = address;
+					self.history.push_call(CallFrame { kind: CallKind::Call, call_site: self.next_op_address, target: address, stack_pointer: self.registers.stack_pointer });
 				}
 			}
 			Instruction::ISR(_, _) => {
-				let interrupt_enable = memory_bus.read_byte(0xFFFF);
-				let interrupt_flag = memory_bus.read_byte(0xFF0F);
-				let effective_interrupts = interrupt_enable & interrupt_flag;
-				let address =
-					if effective_interrupts & (1 << 0) != 0			{memory_bus.write_byte(0xFF0F, interrupt_flag & !(1 << 0)); 0x0040}
-					else if effective_interrupts & (1 << 1) != 0	{memory_bus.write_byte(0xFF0F, interrupt_flag & !(1 << 1)); 0x0048}
-					else if effective_interrupts & (1 << 2) != 0	{memory_bus.write_byte(0xFF0F, interrupt_flag & !(1 << 2)); 0x0050}
-					else if effective_interrupts & (1 << 3) != 0	{memory_bus.write_byte(0xFF0F, interrupt_flag & !(1 << 3)); 0x0058}
-					else 											{memory_bus.write_byte(0xFF0F, interrupt_flag & !(1 << 4)); 0x0060};
+				memory_bus.idu_cycle(self.registers.stack_pointer);
 				memory_bus.write_byte(self.registers.stack_pointer.overflowing_sub(1).0, (self.registers.program_counter >> 8) as u8);
+				/* The vector is picked after the upper byte push, which may overwrite IE: the dispatch then jumps to 0x0000 */
+				let interrupt_enable = memory_bus.read_byte_untimed(0xFFFF);
+				let interrupt_flag = memory_bus.read_byte_untimed(0xFF0F);
+				let effective_interrupts = interrupt_enable & interrupt_flag & 0x1F;
+				let address = if effective_interrupts != 0 {
+					memory_bus.write_byte_untimed(0xFF0F, interrupt_flag & !(1 << effective_interrupts.trailing_zeros()));
+					0x0040 + effective_interrupts.trailing_zeros() as u16 * 8
+				} else {
+					0x0000
+				};
 				memory_bus.write_byte(self.registers.stack_pointer.overflowing_sub(2).0, self.registers.program_counter as u8);
 				self.registers.stack_pointer = self.registers.stack_pointer.overflowing_sub(2).0;
+				if effective_interrupts != 0 {
+					self.history.record_interrupt(InterruptDispatch { interrupt_index: effective_interrupts.trailing_zeros() as u8, interrupt_flag, interrupt_enable, return_address: self.registers.program_counter });
+				}
+				self.history.push_call(CallFrame { kind: CallKind::Interrupt, call_site: self.registers.program_counter, target: address, stack_pointer: self.registers.stack_pointer });
 				self.registers.program_counter = address;
 				self.ime_set = false;
 			}
 			Instruction::RET(_, _) => {
+				self.history.pop_return(self.registers.stack_pointer);
 				let mut ret_pc = 0x0000 as u16;
-				ret_pc |= memory_bus.read_byte(self.registers.stack_pointer) as u16;
+				ret_pc |= memory_bus.read_byte_idu(self.registers.stack_pointer) as u16;
 				ret_pc |= (memory_bus.read_byte(self.registers.stack_pointer.overflowing_add(1).0) as u16) << 8;
 				self.registers.stack_pointer = self.registers.stack_pointer.overflowing_add(2).0;
 				self.registers.program_counter = ret_pc;
 			}
 			Instruction::RETf(len, _, condition) => {
+				memory_bus.cycle();								// Condition check
 				let mut do_call = false;
 				match condition {
 					JumpCondition::NotZero => {if !self.registers.f.zero {do_call = true};}
@@ -915,16 +998,18 @@ impl Cpu {
 				}
 				if do_call {
 					self.current_op = Some(Instruction::RETf(len, 20, condition));
+					self.history.pop_return(self.registers.stack_pointer);
 					let mut ret_pc = 0x0000 as u16;
-					ret_pc |= memory_bus.read_byte(self.registers.stack_pointer) as u16;
+					ret_pc |= memory_bus.read_byte_idu(self.registers.stack_pointer) as u16;
 					ret_pc |= (memory_bus.read_byte(self.registers.stack_pointer.overflowing_add(1).0) as u16) << 8;
 					self.registers.stack_pointer = self.registers.stack_pointer.overflowing_add(2).0;
 					self.registers.program_counter = ret_pc;
 				}
 			}
 			Instruction::RETI(_, _) => {
+				self.history.pop_return(self.registers.stack_pointer);
 				let mut ret_pc = 0x0000 as u16;
-				ret_pc |= memory_bus.read_byte(self.registers.stack_pointer) as u16;
+				ret_pc |= memory_bus.read_byte_idu(self.registers.stack_pointer) as u16;
 				ret_pc |= (memory_bus.read_byte(self.registers.stack_pointer.overflowing_add(1).0) as u16) << 8;
 				self.registers.stack_pointer = self.registers.stack_pointer.overflowing_add(2).0;
 				self.registers.program_counter = ret_pc;
@@ -941,19 +1026,15 @@ impl Cpu {
 					ResetLocation::Hex30 => 0x30 as u16,
 					ResetLocation::Hex38 => 0x38 as u16,
 				};
+				memory_bus.idu_cycle(self.registers.stack_pointer);
 				memory_bus.write_byte(self.registers.stack_pointer.overflowing_sub(1).0, (self.registers.program_counter >> 8) as u8);
 				memory_bus.write_byte(self.registers.stack_pointer.overflowing_sub(2).0, self.registers.program_counter as u8);
 				self.registers.stack_pointer = self.registers.stack_pointer.overflowing_sub(2).0;
 				self.registers.program_counter = location;
+				self.history.push_call(CallFrame { kind: CallKind::Rst, call_site: self.next_op_address, target: location, stack_pointer: self.registers.stack_pointer });
 			}
-			Instruction::HALT(_, _) => {self.state = CpuState::Halted}
-			Instruction::STOP(_, _) => {
-				if memory_bus.is_cgb && memory_bus.speed_chg_scheduled {
-					memory_bus.is_double_speed = !memory_bus.is_double_speed;
-					memory_bus.speed_chg_scheduled = false;
-				}
-				self.fetch_pc(memory_bus);
-			}
+			Instruction::HALT(_, _) => {self.halt(memory_bus)}
+			Instruction::STOP(_, _) => {self.stop(memory_bus)}
 			Instruction::DI(_, _) => {self.ime_set = false}
 			Instruction::EI(_, _) => {self.ime_scheduled = true}
 		}
@@ -962,13 +1043,13 @@ impl Cpu {
 
 #[cfg(test)]
 mod tests {
-	use crate::{memory_bus::MemoryBus, cpu::{Cpu, {registers::FlagsRegister, instructions::RegPairs}}};
+	use crate::{memory_bus::MemoryBus, cpu::{Cpu, CpuState, bus::CpuBus, {registers::FlagsRegister, instructions::RegPairs}}};
 	use super::{Instruction, Regs};
 
 	fn test_adds(cpu: &mut Cpu, memory_bus: &mut MemoryBus, init_a_value: u8, expected_res: u8, expected_flag_reg: FlagsRegister) {
 		cpu.current_op = Some(Instruction::ADDAs(1, 4, Regs::RegA));
 		cpu.registers.a = init_a_value;
-		cpu.exec_current_op(memory_bus);
+		cpu.exec_current_op(&mut CpuBus::new(memory_bus, &mut |_| {}));
 		assert_eq!(cpu.registers.a, expected_res);
 		assert_eq!(cpu.registers.f, expected_flag_reg);
 	}
@@ -976,7 +1057,7 @@ mod tests {
 		cpu.current_op = Some(Instruction::SUBs(1, 4, Regs::RegB));
 		cpu.registers.a = init_a_value;
 		cpu.registers.b = operand;
-		cpu.exec_current_op(memory_bus);
+		cpu.exec_current_op(&mut CpuBus::new(memory_bus, &mut |_| {}));
 		assert_eq!(cpu.registers.a, expected_res);
 		assert_eq!(cpu.registers.f, expected_flag_reg);
 	}
@@ -984,13 +1065,13 @@ mod tests {
 		cpu.current_op = Some(Instruction::CPs(1, 4, Regs::RegB));
 		cpu.registers.a = init_a_value;
 		cpu.registers.b = operand;
-		cpu.exec_current_op(memory_bus);
+		cpu.exec_current_op(&mut CpuBus::new(memory_bus, &mut |_| {}));
 		assert_eq!(cpu.registers.f, expected_flag_reg);
 	}
 	fn test_addhlss(cpu: &mut Cpu, memory_bus: &mut MemoryBus, init_hl_value: u16, expected_res: u16, expected_flag_reg: FlagsRegister) {
 		cpu.current_op = Some(Instruction::ADDHLss(1, 8, RegPairs::RegsHL));
 		cpu.registers.set_hl_big_endian(init_hl_value);
-		cpu.exec_current_op(memory_bus);
+		cpu.exec_current_op(&mut CpuBus::new(memory_bus, &mut |_| {}));
 		assert_eq!(cpu.registers.get_hl_big_endian(), expected_res);
 		assert_eq!(cpu.registers.f, expected_flag_reg);
 	}
@@ -998,19 +1079,19 @@ mod tests {
 		cpu.current_op = Some(Instruction::ADDSPe(2, 16));
 		cpu.registers.stack_pointer = init_sp_value;
 		memory_bus.write_byte(cpu.registers.program_counter, operand as u8);
-		cpu.exec_current_op(memory_bus);
+		cpu.exec_current_op(&mut CpuBus::new(memory_bus, &mut |_| {}));
 		assert_eq!(cpu.registers.stack_pointer, expected_res);
 		assert_eq!(cpu.registers.f, expected_flag_reg);
 	}
 	fn test_daa(cpu: &mut Cpu, memory_bus: &mut MemoryBus, expected_res: u8, expected_flag_reg: FlagsRegister) {
 		cpu.current_op = Some(Instruction::DAA(1, 4));
-		cpu.exec_current_op(memory_bus);
+		cpu.exec_current_op(&mut CpuBus::new(memory_bus, &mut |_| {}));
 		assert_eq!(cpu.registers.a, expected_res);
 		assert_eq!(cpu.registers.f, expected_flag_reg);
 	}
 	#[test]
 	fn test_arith() {
-		let mut memory_bus = MemoryBus::new(None, false);
+		let mut memory_bus = MemoryBus::new(None, None, false).unwrap();
 		let mut my_cpu = Cpu::new();
 		my_cpu.registers.program_counter = 0xC000;
 		test_adds(&mut my_cpu, &mut memory_bus, 0x12, 0x24, 0x00.into());
@@ -1042,4 +1123,97 @@ mod tests {
 		test_sub(&mut my_cpu, &mut memory_bus, 0x83, 0x38, 0x4B, FlagsRegister{ zero: false, substract: true, half_carry: true, carry: false });
 		test_daa(&mut my_cpu, &mut memory_bus, 0x45, FlagsRegister{ zero: false, substract: true, half_carry: false, carry: false });
 	}
-}
\ No newline at end of file
+	#[test]
+	fn test_access_timing() {
+		let mut memory_bus = MemoryBus::new(None, None, false).unwrap();
+		let mut my_cpu = Cpu::new();
+		my_cpu.registers.program_counter = 0xC000;
+		my_cpu.registers.stack_pointer = 0xD000;
+		my_cpu.registers.set_bc_big_endian(0x1234);
+		memory_bus.write_byte(0xC000, 0x00);
+		memory_bus.write_byte(0xCFFF, 0x00);
+		memory_bus.write_byte(0xCFFE, 0x00);
+		my_cpu.next_op = Some(Instruction::PUSH(1, 16, RegPairs::RegsBC));
+		// Stack as seen at the start of each M-cycle: internal delay, upper byte push, lower byte push, next opcode fetch
+		let mut stack_per_m_cycle = Vec::new();
+		let nb_cycles = my_cpu.tick(&mut memory_bus, &mut |memory_bus| stack_per_m_cycle.push((memory_bus.read_byte(0xCFFF), memory_bus.read_byte(0xCFFE))));
+		assert_eq!(nb_cycles, 16);
+		assert_eq!(stack_per_m_cycle, vec![(0x00, 0x00), (0x00, 0x00), (0x12, 0x00), (0x12, 0x34)]);
+	}
+	#[test]
+	fn test_speed_switch() {
+		let mut memory_bus = MemoryBus::new(None, None, false).unwrap();
+		memory_bus.is_cgb = true;
+		let mut my_cpu = Cpu::new();
+		my_cpu.registers.program_counter = 0xC000;
+		memory_bus.write_byte(0xC000, 0x00);
+		memory_bus.write_byte(0xC001, 0x00);
+		memory_bus.write_byte(0xFF4D, 0x01);
+		memory_bus.timer_memory.system_counter = 0x1234;
+		my_cpu.next_op = Some(Instruction::STOP(2, 4));
+		my_cpu.tick(&mut memory_bus, &mut |_| {});
+		assert_eq!(memory_bus.read_byte(0xFF4D), 0xFE);
+		assert_eq!(memory_bus.timer_memory.system_counter, 0x0000);
+		let mut nb_paused_m_cycles = 0;
+		while my_cpu.state() != CpuState::Running {
+			my_cpu.tick(&mut memory_bus, &mut |_| {});
+			nb_paused_m_cycles += 1;
+		}
+		assert_eq!(nb_paused_m_cycles, 2050);
+		assert_eq!(my_cpu.registers.program_counter, 0xC002);			// STOP's second byte skipped
+	}
+	#[test]
+	fn test_oam_bug() {
+		const ROW: usize = 5;
+		let oam_words = |memory_bus: &MemoryBus| -> Vec<[u16; 4]> {
+			(0..0x14).map(|row| [0, 1, 2, 3].map(|word| u16::from_le_bytes([0, 1].map(|i| memory_bus.ppu_memory.peek(0xFE00 + row * 8 + word * 2 + i, false))))).collect()
+		};
+		/* Runs an instruction during the OAM search of row 5 */
+		let corrupted_oam = |force_dmg: bool, instruction: Instruction, address: u16| {
+			let mut memory_bus = MemoryBus::new(None, None, force_dmg).unwrap();
+			for i in 0..0xA0 {
+				memory_bus.ppu_memory.poke(0xFE00 + i, (i as u8).wrapping_mul(0x3B) ^ 0x5A, false);
+			}
+			let oam = oam_words(&memory_bus);
+			memory_bus.ppu_memory.is_oam_locked = true;
+			memory_bus.ppu_memory.oam_scan_row = Some(ROW);
+			let mut my_cpu = Cpu::new();
+			my_cpu.registers.program_counter = 0xC000;
+			my_cpu.registers.set_hl_big_endian(address);
+			my_cpu.registers.stack_pointer = address;
+			my_cpu.current_op = Some(instruction);
+			my_cpu.exec_current_op(&mut CpuBus::new(&mut memory_bus, &mut |_| {}));
+			(oam, oam_words(&memory_bus))
+		};
+		/* The first word of the row gets glitched with the preceding row, whose other words get copied */
+		let glitch_row = |oam: &mut Vec<[u16; 4]>, glitch: fn(u16, u16, u16) -> u16| {
+			oam[ROW] = [glitch(oam[ROW][0], oam[ROW - 1][0], oam[ROW - 1][2]), oam[ROW - 1][1], oam[ROW - 1][2], oam[ROW - 1][3]];
+		};
+		let write_glitch: fn(u16, u16, u16) -> u16 = |a, b, c| ((a ^ c) & (b ^ c)) ^ c;
+		let read_glitch: fn(u16, u16, u16) -> u16 = |a, b, c| b | (a & c);
+		let inc_hl = Instruction::INCss(1, 8, RegPairs::RegsHL);
+		let ld_a_hl = Instruction::LD(1, 8, Regs::RegA, Regs::HLPointee);
+		let ld_a_hl_plus = Instruction::LDI(1, 8, Regs::RegA, Regs::HLPointee);
+		let push_bc = Instruction::PUSH(1, 16, RegPairs::RegsBC);
+		for (instruction, glitch) in [(inc_hl, write_glitch), (push_bc, write_glitch), (ld_a_hl, read_glitch)] {
+			let (mut expected_oam, oam) = corrupted_oam(true, instruction, 0xFE10);
+			glitch_row(&mut expected_oam, glitch);
+			assert_eq!(oam, expected_oam);
+		}
+		/* Read while increasing: the preceding row gets glitched with its neighbours and copied around it, before the read glitch */
+		let (mut expected_oam, oam) = corrupted_oam(true, ld_a_hl_plus, 0xFE10);
+		let (a, b, c, d) = (expected_oam[ROW - 2][0], expected_oam[ROW - 1][0], expected_oam[ROW][0], expected_oam[ROW - 1][2]);
+		expected_oam[ROW - 1][0] = (b & (a | c | d)) | (a & c & d);
+		expected_oam[ROW - 2] = expected_oam[ROW - 1];
+		expected_oam[ROW] = expected_oam[ROW - 1];
+		glitch_row(&mut expected_oam, read_glitch);
+		assert_eq!(oam, expected_oam);
+		/* Neither outside of 0xFE00-0xFEFF nor on CGB */
+		for (force_dmg, address) in [(true, 0xFF80), (false, 0xFE10)] {
+			for instruction in [inc_hl, ld_a_hl, ld_a_hl_plus, push_bc] {
+				let (oam, corrupted_oam) = corrupted_oam(force_dmg, instruction, address);
+				assert_eq!(corrupted_oam, oam);
+			}
+		}
+	}
+}
@@ -1,4 +1,4 @@
-use crate::memory_bus::MemoryBus;
+use super::bus::CpuBus;
 use std::{fmt::Debug, ops::Sub};
 
 const FLAG_Z_BYTE_POS:	u8 = 7;
@@ -89,22 +89,22 @@ impl Registers {
 		self.h = (big_endian_value >> 8) as u8;
 		self.l = big_endian_value as u8;
 	}
-	pub fn get_bc_pointee(&self, memory_bus: &MemoryBus) -> u8 {
+	pub fn get_bc_pointee(&self, memory_bus: &mut CpuBus) -> u8 {
 		memory_bus.read_byte(self.get_bc_big_endian())
 	}
-	pub fn get_de_pointee(&self, memory_bus: &MemoryBus) -> u8 {
+	pub fn get_de_pointee(&self, memory_bus: &mut CpuBus) -> u8 {
 		memory_bus.read_byte(self.get_de_big_endian())
 	}
-	pub fn get_hl_pointee(&self, memory_bus: &MemoryBus) -> u8 {
+	pub fn get_hl_pointee(&self, memory_bus: &mut CpuBus) -> u8 {
 		memory_bus.read_byte(self.get_hl_big_endian())
 	}
-	pub fn set_bc_pointee(&self, memory_bus: &mut MemoryBus, data: u8) {
+	pub fn set_bc_pointee(&self, memory_bus: &mut CpuBus, data: u8) {
 		memory_bus.write_byte(self.get_bc_big_endian(), data)
 	}
-	pub fn set_de_pointee(&self, memory_bus: &mut MemoryBus, data: u8) {
+	pub fn set_de_pointee(&self, memory_bus: &mut CpuBus, data: u8) {
 		memory_bus.write_byte(self.get_de_big_endian(), data)
 	}
-	pub fn set_hl_pointee(&self, memory_bus: &mut MemoryBus, data: u8) {
+	pub fn set_hl_pointee(&self, memory_bus: &mut CpuBus, data: u8) {
 		memory_bus.write_byte(self.get_hl_big_endian(), data)
 	}
 }
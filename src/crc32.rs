@@ -0,0 +1,56 @@
+const CRC32_POLYNOMIAL: u32 = 0xEDB88320;
+
+const CRC32_TABLE: [u32; 256] = {
+	let mut table = [0u32; 256];
+	let mut i = 0;
+	while i < 256 {
+		let mut crc = i as u32;
+		let mut bit = 0;
+		while bit < 8 {
+			crc = if crc & 1 != 0 {(crc >> 1) ^ CRC32_POLYNOMIAL} else {crc >> 1};
+			bit += 1;
+		}
+		table[i] = crc;
+		i += 1;
+	}
+	table
+};
+
+pub struct Crc32 {
+	state: u32
+}
+
+impl Crc32 {
+	pub fn new() -> Self {
+		Crc32 { state: 0xFFFFFFFF }
+	}
+	pub fn update(&mut self, data: &[u8]) {
+		for byte in data {
+			self.state = CRC32_TABLE[((self.state ^ *byte as u32) & 0xFF) as usize] ^ (self.state >> 8);
+		}
+	}
+	pub fn finish(&self) -> u32 {
+		!self.state
+	}
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+	let mut crc = Crc32::new();
+	crc.update(data);
+	crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{crc32, Crc32};
+
+	#[test]
+	fn test_crc32() {
+		assert_eq!(crc32(b""), 0x00000000);
+		assert_eq!(crc32(b"123456789"), 0xCBF43926);
+		let mut crc = Crc32::new();
+		crc.update(b"1234");
+		crc.update(b"56789");
+		assert_eq!(crc.finish(), 0xCBF43926);
+	}
+}
@@ -0,0 +1,375 @@
+use crate::cpu::{Cpu, instructions::Instruction};
+use crate::memory_bus::{MemoryBus, watchpoints::{Watchpoint, WatchHit, WatchKind, WatchSource}};
+
+pub const INTERRUPT_NAMES: [&str; 5] = ["VBlank", "STAT", "Timer", "Serial", "Joypad"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CondReg {
+	A, F, B, C, D, E, H, L, AF, BC, DE, HL, SP, PC
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+	Or, And, Eq, Ne, Lt, Le, Gt, Ge, BitOr, BitAnd, Add, Sub
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+	Number(i64),
+	Register(CondReg),
+	Memory(Box<Expr>),
+	Not(Box<Expr>),
+	Binary(BinaryOp, Box<Expr>, Box<Expr>)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+	Number(i64), Ident(String), Op(&'static str)
+}
+
+/* Break conditions, such as "a == 0x42 && [hl] != 0" or "[$C000] & 0x80" */
+#[derive(Debug, Clone)]
+pub struct Condition {
+	pub source: String,
+	expr: Expr
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+	const OPERATORS: [&str; 17] = ["||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "&", "+", "-", "!", "(", ")", "[", "]"];
+	let mut tokens = Vec::new();
+	let mut rest = source.trim_start();
+	while !rest.is_empty() {
+		if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+			tokens.push(Token::Op(op));
+			rest = &rest[op.len()..];
+		} else if rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '$') {
+			let len = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '$')).unwrap_or(rest.len());
+			let word = &rest[..len];
+			let number = if let Some(hex) = word.strip_prefix("0x").or_else(|| word.strip_prefix('$')) {
+				Some(i64::from_str_radix(hex, 16).map_err(|_| format!("invalid number '{}'", word))?)
+			} else if word.starts_with(|c: char| c.is_ascii_digit()) {
+				Some(word.parse().map_err(|_| format!("invalid number '{}'", word))?)
+			} else {
+				None
+			};
+			tokens.push(match number {
+				Some(number) => Token::Number(number),
+				None => Token::Ident(word.to_ascii_lowercase())
+			});
+			rest = &rest[len..];
+		} else {
+			return Err(format!("unexpected character '{}'", rest.chars().next().unwrap_or_default()));
+		}
+		rest = rest.trim_start();
+	}
+	Ok(tokens)
+}
+
+struct Parser {
+	tokens: Vec<Token>,
+	pos: usize
+}
+
+impl Parser {
+	fn peek_op(&self) -> Option<&'static str> {
+		match self.tokens.get(self.pos) {
+			Some(Token::Op(op)) => Some(op),
+			_ => None
+		}
+	}
+	fn expect(&mut self, op: &str) -> Result<(), String> {
+		if self.peek_op() == Some(op) {
+			self.pos += 1;
+			Ok(())
+		} else {
+			Err(format!("expected '{}'", op))
+		}
+	}
+	fn binary(&mut self, ops: &[(&str, BinaryOp)], next: fn(&mut Self) -> Result<Expr, String>) -> Result<Expr, String> {
+		let mut lhs = next(self)?;
+		while let Some((_, op)) = ops.iter().find(|(token, _)| Some(*token) == self.peek_op()) {
+			self.pos += 1;
+			lhs = Expr::Binary(*op, Box::new(lhs), Box::new(next(self)?));
+		}
+		Ok(lhs)
+	}
+	fn or(&mut self) -> Result<Expr, String> {
+		self.binary(&[("||", BinaryOp::Or)], Self::and)
+	}
+	fn and(&mut self) -> Result<Expr, String> {
+		self.binary(&[("&&", BinaryOp::And)], Self::comparison)
+	}
+	fn comparison(&mut self) -> Result<Expr, String> {
+		self.binary(&[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne), ("<=", BinaryOp::Le), (">=", BinaryOp::Ge), ("<", BinaryOp::Lt), (">", BinaryOp::Gt)], Self::bit_or)
+	}
+	fn bit_or(&mut self) -> Result<Expr, String> {
+		self.binary(&[("|", BinaryOp::BitOr)], Self::bit_and)
+	}
+	fn bit_and(&mut self) -> Result<Expr, String> {
+		self.binary(&[("&", BinaryOp::BitAnd)], Self::sum)
+	}
+	fn sum(&mut self) -> Result<Expr, String> {
+		self.binary(&[("+", BinaryOp::Add), ("-", BinaryOp::Sub)], Self::unary)
+	}
+	fn unary(&mut self) -> Result<Expr, String> {
+		if self.peek_op() == Some("!") {
+			self.pos += 1;
+			return Ok(Expr::Not(Box::new(self.unary()?)));
+		}
+		let token = self.tokens.get(self.pos).cloned().ok_or("unexpected end of condition")?;
+		self.pos += 1;
+		match token {
+			Token::Number(number) => Ok(Expr::Number(number)),
+			Token::Ident(name) => {
+				let reg = match name.as_str() {
+					"a" => CondReg::A, "f" => CondReg::F, "b" => CondReg::B, "c" => CondReg::C,
+					"d" => CondReg::D, "e" => CondReg::E, "h" => CondReg::H, "l" => CondReg::L,
+					"af" => CondReg::AF, "bc" => CondReg::BC, "de" => CondReg::DE, "hl" => CondReg::HL,
+					"sp" => CondReg::SP, "pc" => CondReg::PC,
+					_ => return Err(format!("unknown register '{}'", name))
+				};
+				Ok(Expr::Register(reg))
+			}
+			Token::Op("(") => {
+				let expr = self.or()?;
+				self.expect(")")?;
+				Ok(expr)
+			}
+			Token::Op("[") => {
+				let expr = self.or()?;
+				self.expect("]")?;
+				Ok(Expr::Memory(Box::new(expr)))
+			}
+			Token::Op(op) => Err(format!("unexpected '{}'", op))
+		}
+	}
+}
+
+impl Expr {
+	fn eval(&self, cpu: &Cpu, memory_bus: &MemoryBus) -> i64 {
+		match self {
+			Expr::Number(number) => *number,
+			Expr::Register(reg) => match reg {
+				CondReg::A => cpu.registers.a as i64,
+				CondReg::F => u8::from(cpu.registers.f) as i64,
+				CondReg::B => cpu.registers.b as i64,
+				CondReg::C => cpu.registers.c as i64,
+				CondReg::D => cpu.registers.d as i64,
+				CondReg::E => cpu.registers.e as i64,
+				CondReg::H => cpu.registers.h as i64,
+				CondReg::L => cpu.registers.l as i64,
+				CondReg::AF => cpu.registers.get_af_big_endian() as i64,
+				CondReg::BC => cpu.registers.get_bc_big_endian() as i64,
+				CondReg::DE => cpu.registers.get_de_big_endian() as i64,
+				CondReg::HL => cpu.registers.get_hl_big_endian() as i64,
+				CondReg::SP => cpu.registers.stack_pointer as i64,
+				CondReg::PC => cpu.next_op_address as i64
+			},
+			Expr::Memory(address) => memory_bus.read_byte(address.eval(cpu, memory_bus) as u16) as i64,
+			Expr::Not(expr) => (expr.eval(cpu, memory_bus) == 0) as i64,
+			Expr::Binary(op, lhs, rhs) => {
+				let lhs = lhs.eval(cpu, memory_bus);
+				match op {
+					BinaryOp::Or => (lhs != 0 || rhs.eval(cpu, memory_bus) != 0) as i64,
+					BinaryOp::And => (lhs != 0 && rhs.eval(cpu, memory_bus) != 0) as i64,
+					_ => {
+						let rhs = rhs.eval(cpu, memory_bus);
+						match op {
+							BinaryOp::Eq => (lhs == rhs) as i64,
+							BinaryOp::Ne => (lhs != rhs) as i64,
+							BinaryOp::Lt => (lhs < rhs) as i64,
+							BinaryOp::Le => (lhs <= rhs) as i64,
+							BinaryOp::Gt => (lhs > rhs) as i64,
+							BinaryOp::Ge => (lhs >= rhs) as i64,
+							BinaryOp::BitOr => lhs | rhs,
+							BinaryOp::BitAnd => lhs & rhs,
+							BinaryOp::Add => lhs.wrapping_add(rhs),
+							_ => lhs.wrapping_sub(rhs)
+						}
+					}
+				}
+			}
+		}
+	}
+}
+
+impl Condition {
+	pub fn parse(source: &str) -> Result<Self, String> {
+		let mut parser = Parser { tokens: tokenize(source)?, pos: 0 };
+		let expr = parser.or()?;
+		if parser.pos != parser.tokens.len() {
+			return Err("unexpected trailing tokens".to_string());
+		}
+		Ok(Condition { source: source.trim().to_string(), expr })
+	}
+	pub fn is_met(&self, cpu: &Cpu, memory_bus: &MemoryBus) -> bool {
+		self.expr.eval(cpu, memory_bus) != 0
+	}
+}
+
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+	pub address: u16,
+	pub bank: Option<usize>,
+	pub condition: Option<Condition>,
+	pub is_enabled: bool
+}
+
+#[derive(Debug, Clone)]
+pub struct DebugWatchpoint {
+	pub watchpoint: Watchpoint,
+	pub condition: Option<Condition>,
+	pub is_enabled: bool
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct OpcodeBreak {
+	pub opcode: u8,
+	pub is_enabled: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakReason {
+	Breakpoint(u16), Watchpoint(WatchHit), Interrupt(usize), Opcode(u16, u8), IllegalOpcode(u16, u8)
+}
+
+impl std::fmt::Display for BreakReason {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			BreakReason::Breakpoint(address) => write!(f, "Breakpoint at 0x{:04X}", address),
+			BreakReason::Watchpoint(hit) => write!(f, "Watchpoint: {} at 0x{:04X}", if hit.is_write {"write"} else {"read"}, hit.address),
+			BreakReason::Interrupt(interrupt) => write!(f, "{} interrupt dispatch", INTERRUPT_NAMES[*interrupt]),
+			BreakReason::Opcode(address, opcode) => write!(f, "Opcode 0x{:02X} at 0x{:04X}", opcode, address),
+			BreakReason::IllegalOpcode(address, opcode) => write!(f, "Illegal opcode 0x{:02X} at 0x{:04X}, the CPU locks up if resumed", opcode, address)
+		}
+	}
+}
+
+pub struct Debugger {
+	pub breakpoints: Vec<Breakpoint>,
+	pub watchpoints: Vec<DebugWatchpoint>,
+	pub opcode_breaks: Vec<OpcodeBreak>,
+	pub break_on_interrupts: [bool; 5],
+	pub break_on_illegal_opcode: bool,
+	pub last_break: Option<BreakReason>,
+	pub are_watchpoints_updated: bool
+}
+
+impl Debugger {
+	pub fn new() -> Self {
+		Debugger {
+			breakpoints: Vec::new(),
+			watchpoints: Vec::new(),
+			opcode_breaks: vec![OpcodeBreak { opcode: 0x40, is_enabled: false }],
+			break_on_interrupts: [false; 5],
+			break_on_illegal_opcode: false,
+			last_break: None,
+			are_watchpoints_updated: true
+		}
+	}
+	/* Pushes the enabled watchpoints to the MemoryBus, once after each change or when a new MemoryBus was created */
+	pub fn bind_memory_bus(&mut self, memory_bus: &mut MemoryBus) {
+		memory_bus.watchpoints.list.retain(|watchpoint| watchpoint.source != WatchSource::Debugger);
+		memory_bus.watchpoints.list.extend(self.watchpoints.iter().filter(|watchpoint| watchpoint.is_enabled).map(|watchpoint| watchpoint.watchpoint));
+		self.are_watchpoints_updated = false;
+	}
+	/* Called after each executed instruction, before the next one is executed */
+	pub fn check(&mut self, cpu: &Cpu, memory_bus: &MemoryBus) -> Option<BreakReason> {
+		let reason = self.find_break(cpu, memory_bus);
+		if reason.is_some() {
+			self.last_break = reason;
+		}
+		reason
+	}
+	fn find_break(&self, cpu: &Cpu, memory_bus: &MemoryBus) -> Option<BreakReason> {
+		let watch_hit = memory_bus.watchpoints.take_hits(WatchSource::Debugger).into_iter().find(|hit| {
+			self.watchpoints.iter()
+				.filter(|watchpoint| watchpoint.is_enabled && watchpoint.watchpoint == hit.watchpoint)
+				.any(|watchpoint| watchpoint.condition.as_ref().is_none_or(|condition| condition.is_met(cpu, memory_bus)))
+		});
+		if let Some(hit) = watch_hit {
+			return Some(BreakReason::Watchpoint(hit));
+		}
+		if cpu.locked_at().is_some() {
+			return None;
+		}
+		if let Some(Instruction::ISR(_, _)) = cpu.next_op {
+			let interrupts = memory_bus.read_byte(0xFFFF) & memory_bus.read_byte(0xFF0F) & 0x1F;
+			let interrupt = interrupts.trailing_zeros() as usize;
+			if interrupt < INTERRUPT_NAMES.len() && self.break_on_interrupts[interrupt] {
+				return Some(BreakReason::Interrupt(interrupt));
+			}
+			return None;
+		}
+		let address = cpu.next_op_address;
+		let is_breakpoint_met = self.breakpoints.iter()
+			.filter(|breakpoint| breakpoint.is_enabled && breakpoint.address == address)
+			.filter(|breakpoint| !(0x4000..=0x7FFF).contains(&address) || breakpoint.bank.is_none_or(|bank| bank == memory_bus.cartridge.rom_bank_at(address)))
+			.any(|breakpoint| breakpoint.condition.as_ref().is_none_or(|condition| condition.is_met(cpu, memory_bus)));
+		if is_breakpoint_met {
+			return Some(BreakReason::Breakpoint(address));
+		}
+		if cpu.next_op.is_none() && self.break_on_illegal_opcode {
+			return Some(BreakReason::IllegalOpcode(address, memory_bus.read_byte(address)));
+		}
+		self.opcode_breaks.iter()
+			.find(|opcode_break| opcode_break.is_enabled && opcode_break.opcode == memory_bus.read_byte(address))
+			.map(|opcode_break| BreakReason::Opcode(address, opcode_break.opcode))
+	}
+	pub fn add_watchpoint(&mut self, address: u16, len: u16, kind: WatchKind, condition: Option<Condition>) {
+		self.watchpoints.push(DebugWatchpoint {
+			watchpoint: Watchpoint { address, len, kind, source: WatchSource::Debugger },
+			condition,
+			is_enabled: true
+		});
+		self.are_watchpoints_updated = true;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn eval(source: &str, cpu: &Cpu, memory_bus: &MemoryBus) -> i64 {
+		Condition::parse(source).unwrap().expr.eval(cpu, memory_bus)
+	}
+
+	#[test]
+	fn test_condition() {
+		let mut cpu = Cpu::new();
+		let mut memory_bus = MemoryBus::new(None, None, true).unwrap();
+		cpu.registers.a = 0x42;
+		cpu.registers.set_hl_big_endian(0xC010);
+		memory_bus.write_byte(0xC010, 0x80);
+		assert_eq!(eval("a == 0x42", &cpu, &memory_bus), 1);
+		assert_eq!(eval("A != $42 || hl == 49168", &cpu, &memory_bus), 1);
+		assert_eq!(eval("[hl] & 0x80 && !(a < 2)", &cpu, &memory_bus), 1);
+		assert_eq!(eval("[hl - 0x10 + 16] + 1", &cpu, &memory_bus), 0x81);
+		assert_eq!(eval("a > 0x42 | 0", &cpu, &memory_bus), 0);
+		assert!(Condition::parse("a ==").is_err());
+		assert!(Condition::parse("x == 1").is_err());
+		assert!(Condition::parse("[hl").is_err());
+		assert!(Condition::parse("a == 1 b").is_err());
+	}
+
+	#[test]
+	fn test_overlapping_watchpoints() {
+		let cpu = Cpu::new();
+		let mut memory_bus = MemoryBus::new(None, None, true).unwrap();
+		let mut debugger = Debugger::new();
+		let gdb_watchpoint = Watchpoint { address: 0xC000, len: 1, kind: WatchKind::Write, source: WatchSource::Gdb };
+		debugger.add_watchpoint(0xC000, 1, WatchKind::Write, Some(Condition::parse("a == 0x42").unwrap()));
+		debugger.add_watchpoint(0xC000, 2, WatchKind::Access, None);
+		debugger.bind_memory_bus(&mut memory_bus);
+		memory_bus.watchpoints.list.insert(0, gdb_watchpoint);
+		memory_bus.watchpoints.is_armed = true;
+		memory_bus.write_byte(0xC000, 0x01);
+		memory_bus.watchpoints.is_armed = false;
+		let Some(BreakReason::Watchpoint(hit)) = debugger.check(&cpu, &memory_bus) else {panic!("watchpoint not hit")};
+		assert_eq!(hit.watchpoint.len, 2);
+		let gdb_hits = memory_bus.watchpoints.take_hits(WatchSource::Gdb);
+		assert_eq!(gdb_hits.len(), 1);
+		assert_eq!(gdb_hits[0].watchpoint, gdb_watchpoint);
+		assert!(debugger.check(&cpu, &memory_bus).is_none());
+	}
+}
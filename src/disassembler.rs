@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::{fs, path::Path};
+use crate::cpu::{instructions::{Instruction, Regs, RegPairs, JumpCondition, ResetLocation}};
+use crate::memory_bus::MemoryBus;
+
+/* Labels from RGBDS/no$gmb .sym files: one "BB:AAAA Label" per line, ';' starts a comment */
+pub struct Symbols {
+	labels: HashMap<(usize, u16), String>,
+	unbanked_labels: HashMap<u16, String>
+}
+
+impl Symbols {
+	pub fn load_from_path(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+		Self::parse(&fs::read_to_string(path)?)
+	}
+	pub fn parse(contents: &str) -> Result<Self, Box<dyn std::error::Error>> {
+		let mut labels = HashMap::new();
+		let mut unbanked_labels = HashMap::new();
+		for (line_nb, line) in contents.lines().enumerate() {
+			let line = line.split(';').next().unwrap_or_default().trim();
+			if line.is_empty() {
+				continue;
+			}
+			let (location, label) = line.split_once(char::is_whitespace).ok_or_else(|| format!("line {}: missing label", line_nb + 1))?;
+			let (bank, address) = location.split_once(':').ok_or_else(|| format!("line {}: expected BB:AAAA address", line_nb + 1))?;
+			let bank = usize::from_str_radix(bank, 16).map_err(|_| format!("line {}: invalid bank '{}'", line_nb + 1, bank))?;
+			let address = u16::from_str_radix(address, 16).map_err(|_| format!("line {}: invalid address '{}'", line_nb + 1, address))?;
+			let label = label.trim().to_string();
+			unbanked_labels.entry(address).or_insert_with(|| label.clone());
+			labels.insert((bank, address), label);
+		}
+		Ok(Symbols { labels, unbanked_labels })
+	}
+	pub fn label_at(&self, memory_bus: &MemoryBus, address: u16) -> Option<&str> {
+		match address {
+			0x0000..=0x7FFF => self.labels.get(&(memory_bus.cartridge.rom_bank_at(address), address)),
+			_ => self.unbanked_labels.get(&address)
+		}.map(|label| label.as_str())
+	}
+	pub fn find_label(&self, label: &str) -> Option<(usize, u16)> {
+		self.labels.iter().find(|(_, name)| name.as_str() == label).map(|(location, _)| *location)
+	}
+}
+
+pub struct DisassembledLine {
+	pub address: u16,
+	pub bank: Option<usize>,
+	pub bytes: Vec<u8>,
+	pub text: String
+}
+
+fn format_address(memory_bus: &MemoryBus, symbols: Option<&Symbols>, address: u16) -> String {
+	match symbols.and_then(|symbols| symbols.label_at(memory_bus, address)) {
+		Some(label) => label.to_string(),
+		None => format!("${:04X}", address)
+	}
+}
+
+fn format_reg(memory_bus: &MemoryBus, symbols: Option<&Symbols>, reg: Regs, operand: u16) -> String {
+	match reg {
+		Regs::RegA => "a".to_string(),
+		Regs::RegB => "b".to_string(),
+		Regs::RegC => "c".to_string(),
+		Regs::RegD => "d".to_string(),
+		Regs::RegE => "e".to_string(),
+		Regs::RegH => "h".to_string(),
+		Regs::RegL => "l".to_string(),
+		Regs::HLPointee => "[hl]".to_string(),
+		Regs::BCPointee => "[bc]".to_string(),
+		Regs::DEPointee => "[de]".to_string(),
+		Regs::BytesFromPCPointee => format!("[{}]", format_address(memory_bus, symbols, operand)),
+		Regs::UpperRamOffsetFromPC => format!("[{}]", format_address(memory_bus, symbols, 0xFF00 | operand & 0xFF)),
+		Regs::UpperRamOffsetFromRegC => "[c]".to_string(),
+		Regs::ByteFromPC => format!("${:02X}", operand as u8)
+	}
+}
+
+fn format_reg_pair(memory_bus: &MemoryBus, symbols: Option<&Symbols>, reg_pair: RegPairs, operand: u16) -> String {
+	match reg_pair {
+		RegPairs::RegsAF => "af".to_string(),
+		RegPairs::RegsBC => "bc".to_string(),
+		RegPairs::RegsDE => "de".to_string(),
+		RegPairs::RegsHL => "hl".to_string(),
+		RegPairs::RegSP => "sp".to_string(),
+		RegPairs::BytesFromPCPointee => format!("[{}]", format_address(memory_bus, symbols, operand)),
+		RegPairs::BytesFromPC => format_address(memory_bus, symbols, operand)
+	}
+}
+
+fn format_condition(condition: JumpCondition) -> &'static str {
+	match condition {
+		JumpCondition::NotZero => "nz",
+		JumpCondition::Zero => "z",
+		JumpCondition::NotCarry => "nc",
+		JumpCondition::Carry => "c"
+	}
+}
+
+fn format_signed(value: u8) -> String {
+	let value = value as i8;
+	if value < 0 {format!("-{}", value.unsigned_abs())} else {format!("+{}", value)}
+}
+
+/* Renders an instruction in RGBDS syntax; `operand` holds the bytes following the opcode, little-endian */
+fn format_instruction(memory_bus: &MemoryBus, symbols: Option<&Symbols>, instruction: Instruction, address: u16, operand: u16) -> String {
+	let reg = |reg| format_reg(memory_bus, symbols, reg, operand);
+	let reg_pair = |reg_pair| format_reg_pair(memory_bus, symbols, reg_pair, operand);
+	let relative_target = || format_address(memory_bus, symbols, address.wrapping_add(2).wrapping_add(operand as u8 as i8 as u16));
+	match instruction {
+		Instruction::LD(_, _, target, src) => {
+			let is_ldh = matches!(target, Regs::UpperRamOffsetFromPC | Regs::UpperRamOffsetFromRegC) || matches!(src, Regs::UpperRamOffsetFromPC | Regs::UpperRamOffsetFromRegC);
+			format!("{} {}, {}", if is_ldh {"ldh"} else {"ld"}, reg(target), reg(src))
+		}
+		Instruction::LDI(_, _, Regs::HLPointee, src) => format!("ld [hl+], {}", reg(src)),
+		Instruction::LDI(_, _, target, _) => format!("ld {}, [hl+]", reg(target)),
+		Instruction::LDD(_, _, Regs::HLPointee, src) => format!("ld [hl-], {}", reg(src)),
+		Instruction::LDD(_, _, target, _) => format!("ld {}, [hl-]", reg(target)),
+		Instruction::LD16(_, _, target, src) => format!("ld {}, {}", reg_pair(target), reg_pair(src)),
+		Instruction::PUSH(_, _, target) => format!("push {}", reg_pair(target)),
+		Instruction::POP(_, _, target) => format!("pop {}", reg_pair(target)),
+		Instruction::ADDAs(_, _, operand) => format!("add a, {}", reg(operand)),
+		Instruction::ADCAs(_, _, operand) => format!("adc a, {}", reg(operand)),
+		Instruction::SUBs(_, _, operand) => format!("sub a, {}", reg(operand)),
+		Instruction::SBCAs(_, _, operand) => format!("sbc a, {}", reg(operand)),
+		Instruction::ANDs(_, _, operand) => format!("and a, {}", reg(operand)),
+		Instruction::XORs(_, _, operand) => format!("xor a, {}", reg(operand)),
+		Instruction::ORs(_, _, operand) => format!("or a, {}", reg(operand)),
+		Instruction::CPs(_, _, operand) => format!("cp a, {}", reg(operand)),
+		Instruction::INCs(_, _, target) => format!("inc {}", reg(target)),
+		Instruction::DECs(_, _, target) => format!("dec {}", reg(target)),
+		Instruction::DAA(_, _) => "daa".to_string(),
+		Instruction::CPL(_, _) => "cpl".to_string(),
+		Instruction::ADDHLss(_, _, operand) => format!("add hl, {}", reg_pair(operand)),
+		Instruction::INCss(_, _, target) => format!("inc {}", reg_pair(target)),
+		Instruction::DECss(_, _, target) => format!("dec {}", reg_pair(target)),
+		Instruction::ADDSPe(_, _) => format!("add sp, {}", format_signed(operand as u8)),
+		Instruction::LDHLSPe(_, _) => format!("ld hl, sp{}", format_signed(operand as u8)),
+		Instruction::RLCA(_, _) => "rlca".to_string(),
+		Instruction::RLA(_, _) => "rla".to_string(),
+		Instruction::RRCA(_, _) => "rrca".to_string(),
+		Instruction::RRA(_, _) => "rra".to_string(),
+		Instruction::RLC(_, _, target) => format!("rlc {}", reg(target)),
+		Instruction::RL(_, _, target) => format!("rl {}", reg(target)),
+		Instruction::RRC(_, _, target) => format!("rrc {}", reg(target)),
+		Instruction::RR(_, _, target) => format!("rr {}", reg(target)),
+		Instruction::SLA(_, _, target) => format!("sla {}", reg(target)),
+		Instruction::SWAP(_, _, target) => format!("swap {}", reg(target)),
+		Instruction::SRA(_, _, target) => format!("sra {}", reg(target)),
+		Instruction::SRL(_, _, target) => format!("srl {}", reg(target)),
+		Instruction::BIT(_, _, bit, target) => format!("bit {}, {}", bit, reg(target)),
+		Instruction::SET(_, _, bit, target) => format!("set {}, {}", bit, reg(target)),
+		Instruction::RES(_, _, bit, target) => format!("res {}, {}", bit, reg(target)),
+		Instruction::CCF(_, _) => "ccf".to_string(),
+		Instruction::SCF(_, _) => "scf".to_string(),
+		Instruction::NOP(_, _) => "nop".to_string(),
+		Instruction::HALT(_, _) => "halt".to_string(),
+		Instruction::STOP(_, _) => "stop".to_string(),
+		Instruction::DI(_, _) => "di".to_string(),
+		Instruction::EI(_, _) => "ei".to_string(),
+		Instruction::JPnn(_, _) => format!("jp {}", format_address(memory_bus, symbols, operand)),
+		Instruction::JPHL(_, _) => "jp hl".to_string(),
+		Instruction::JPfnn(_, _, condition) => format!("jp {}, {}", format_condition(condition), format_address(memory_bus, symbols, operand)),
+		Instruction::JR(_, _) => format!("jr {}", relative_target()),
+		Instruction::JRf(_, _, condition) => format!("jr {}, {}", format_condition(condition), relative_target()),
+		Instruction::CALL(_, _) => format!("call {}", format_address(memory_bus, symbols, operand)),
+		Instruction::CALLf(_, _, condition) => format!("call {}, {}", format_condition(condition), format_address(memory_bus, symbols, operand)),
+		Instruction::ISR(_, _) => "; interrupt dispatch".to_string(),
+		Instruction::RET(_, _) => "ret".to_string(),
+		Instruction::RETf(_, _, condition) => format!("ret {}", format_condition(condition)),
+		Instruction::RETI(_, _) => "reti".to_string(),
+		Instruction::RST(_, _, location) => format!("rst ${:02X}", match location {
+			ResetLocation::Hex00 => 0x00,
+			ResetLocation::Hex08 => 0x08,
+			ResetLocation::Hex10 => 0x10,
+			ResetLocation::Hex18 => 0x18,
+			ResetLocation::Hex20 => 0x20,
+			ResetLocation::Hex28 => 0x28,
+			ResetLocation::Hex30 => 0x30,
+			ResetLocation::Hex38 => 0x38
+		})
+	}
+}
+
+pub fn disassemble(memory_bus: &MemoryBus, symbols: Option<&Symbols>, address: u16) -> DisassembledLine {
+	let opcode = memory_bus.read_byte(address);
+	let instruction = Instruction::from_opcode(opcode, || memory_bus.read_byte(address.wrapping_add(1)));
+	let length = instruction.map_or(1, |instruction| instruction.length().max(1));
+	let bytes: Vec<u8> = (0..length as u16).map(|i| memory_bus.read_byte(address.wrapping_add(i))).collect();
+	let text = match instruction {
+		Some(instruction) => {
+			let operand = if opcode == 0xCB {0} else {bytes.get(1).map_or(0, |low| *low as u16) | bytes.get(2).map_or(0, |high| (*high as u16) << 8)};
+			format_instruction(memory_bus, symbols, instruction, address, operand)
+		}
+		None => format!("db ${:02X}", opcode)
+	};
+	DisassembledLine {
+		address,
+		bank: (address < 0x8000).then(|| memory_bus.cartridge.rom_bank_at(address)),
+		bytes,
+		text
+	}
+}
+
+/* Best guess of where the instruction preceding `address` starts, as code can't be reliably disassembled backwards */
+pub fn previous_instruction_address(memory_bus: &MemoryBus, address: u16) -> u16 {
+	for length in (1..=3).rev() {
+		let candidate = address.wrapping_sub(length);
+		if disassemble(memory_bus, None, candidate).bytes.len() == length as usize {
+			return candidate;
+		}
+	}
+	address.wrapping_sub(1)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_disassemble() {
+		let mut memory_bus = MemoryBus::new(None, None, true).unwrap();
+		let program = [0xFA, 0x00, 0xC0, 0xE0, 0x44, 0x22, 0x18, 0xFE, 0xCB, 0x7C, 0xF8, 0xFB, 0xD3, 0xCD, 0x10, 0xC0];
+		for (i, byte) in program.iter().enumerate() {
+			memory_bus.write_byte(0xC000 + i as u16, *byte);
+		}
+		let symbols = Symbols::parse("; comment\n00:C000 wCounter\n00:C010 Func ; trailing\n").unwrap();
+		let mut address = 0xC000;
+		let mut lines = Vec::new();
+		while address < 0xC000 + program.len() as u16 {
+			let line = disassemble(&memory_bus, Some(&symbols), address);
+			address += line.bytes.len() as u16;
+			lines.push(line.text);
+		}
+		assert_eq!(lines, ["ld a, [wCounter]", "ldh [$FF44], a", "ld [hl+], a", "jr $C006", "bit 7, h", "ld hl, sp-5", "db $D3", "call Func"]);
+		assert!(Symbols::parse("C000 NoBank").is_err());
+	}
+}
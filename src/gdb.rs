@@ -0,0 +1,361 @@
+use std::collections::HashSet;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use crate::cpu::Cpu;
+use crate::memory_bus::{MemoryBus, watchpoints::{Watchpoint, WatchKind, WatchSource}};
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+	<feature name="org.gnu.gdb.z80.cpu">
+		<reg name="af" bitsize="16" type="int"/>
+		<reg name="bc" bitsize="16" type="data_ptr"/>
+		<reg name="de" bitsize="16" type="data_ptr"/>
+		<reg name="hl" bitsize="16" type="data_ptr"/>
+		<reg name="sp" bitsize="16" type="data_ptr"/>
+		<reg name="pc" bitsize="16" type="code_ptr"/>
+	</feature>
+</target>
+"#;
+const NB_REGISTERS: usize = 6;
+
+enum GdbInput {
+	Interrupt, Packet(String)
+}
+
+pub struct GdbStub {
+	listener: TcpListener,
+	client: Option<TcpStream>,
+	buffer: Vec<u8>,
+	sw_breakpoints: HashSet<u16>,
+	hw_breakpoints: HashSet<u16>,
+	watchpoints: Vec<Watchpoint>,
+	is_stepping: bool,
+	pub is_halted: bool
+}
+
+fn parse_hex(hex: &str) -> Option<u16> {
+	u16::from_str_radix(hex, 16).ok()
+}
+
+fn parse_address_len(args: &str) -> Option<(u16, u16)> {
+	let (address, len) = args.split_once(',')?;
+	Some((parse_hex(address)?, parse_hex(len)?))
+}
+
+fn read_register(cpu: &Cpu, reg_nb: usize) -> Option<u16> {
+	match reg_nb {
+		0 => Some(cpu.registers.get_af_big_endian()),
+		1 => Some(cpu.registers.get_bc_big_endian()),
+		2 => Some(cpu.registers.get_de_big_endian()),
+		3 => Some(cpu.registers.get_hl_big_endian()),
+		4 => Some(cpu.registers.stack_pointer),
+		5 => Some(cpu.next_op_address),
+		_ => None
+	}
+}
+
+fn write_register(cpu: &mut Cpu, memory_bus: &mut MemoryBus, reg_nb: usize, value: u16) -> bool {
+	match reg_nb {
+		0 => cpu.registers.set_af_big_endian(value),
+		1 => cpu.registers.set_bc_big_endian(value),
+		2 => cpu.registers.set_de_big_endian(value),
+		3 => cpu.registers.set_hl_big_endian(value),
+		4 => cpu.registers.stack_pointer = value,
+		5 => cpu.jump_to(memory_bus, value),
+		_ => return false
+	}
+	true
+}
+
+/* Registers are sent little-endian, as 16-bit values in the order af, bc, de, hl, sp, pc */
+fn encode_register(value: u16) -> String {
+	format!("{:02x}{:02x}", value as u8, (value >> 8) as u8)
+}
+
+fn decode_register(hex: &str) -> Option<u16> {
+	let value = parse_hex(hex.get(0..4)?)?;
+	Some(value.swap_bytes())
+}
+
+impl GdbStub {
+	pub fn bind(port: u16) -> std::io::Result<Self> {
+		let listener = TcpListener::bind(("127.0.0.1", port))?;
+		listener.set_nonblocking(true)?;
+		Ok(GdbStub {
+			listener,
+			client: None,
+			buffer: Vec::new(),
+			sw_breakpoints: HashSet::new(),
+			hw_breakpoints: HashSet::new(),
+			watchpoints: Vec::new(),
+			is_stepping: false,
+			is_halted: false
+		})
+	}
+	pub fn is_connected(&self) -> bool {
+		self.client.is_some()
+	}
+	/* Re-applies the watchpoints to a freshly created MemoryBus (after a reset or a ROM load) */
+	pub fn bind_memory_bus(&self, memory_bus: &mut MemoryBus) {
+		memory_bus.watchpoints.list.retain(|watchpoint| !self.watchpoints.contains(watchpoint));
+		memory_bus.watchpoints.list.extend_from_slice(&self.watchpoints);
+	}
+	pub fn poll(&mut self, cpu: &mut Cpu, memory_bus: &mut MemoryBus) {
+		if self.client.is_none() && (self.is_halted || !self.watchpoints.is_empty()) {
+			self.disconnect(memory_bus);
+		}
+		if self.client.is_none() {
+			match self.listener.accept() {
+				Ok((stream, address)) => {
+					if let Err(err) = stream.set_nonblocking(true) {
+						println!("GDB: unable to configure connection: {}", err);
+						return;
+					}
+					println!("GDB: client connected from {}", address);
+					self.client = Some(stream);
+					self.is_halted = true;
+				}
+				Err(err) if err.kind() == ErrorKind::WouldBlock => return,
+				Err(err) => {
+					println!("GDB: unable to accept connection: {}", err);
+					return;
+				}
+			}
+		}
+		let mut chunk = [0x00; 0x400];
+		loop {
+			let Some(client) = &mut self.client else {return};
+			match client.read(&mut chunk) {
+				Ok(0) => {
+					self.disconnect(memory_bus);
+					return;
+				}
+				Ok(nb_bytes) => self.buffer.extend_from_slice(&chunk[..nb_bytes]),
+				Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+				Err(err) => {
+					println!("GDB: connection error: {}", err);
+					self.disconnect(memory_bus);
+					return;
+				}
+			}
+		}
+		while let Some(input) = self.next_input() {
+			match input {
+				GdbInput::Interrupt => {
+					if !self.is_halted {
+						self.halt("S02");
+					}
+				}
+				GdbInput::Packet(packet) => {
+					self.send_raw(b"+");
+					if let Some(reply) = self.handle_packet(&packet, cpu, memory_bus) {
+						self.send_packet(&reply);
+					}
+				}
+			}
+			if self.client.is_none() {
+				return;
+			}
+		}
+	}
+	/* Called after each executed instruction while the target is running */
+	pub fn check_stop(&mut self, cpu: &Cpu, memory_bus: &MemoryBus) {
+		if self.client.is_none() || self.is_halted {
+			return;
+		}
+		if let Some(hit) = memory_bus.watchpoints.take_hits(WatchSource::Gdb).first() {
+			let kind = match hit.watchpoint.kind {
+				WatchKind::Write => "watch",
+				WatchKind::Read => "rwatch",
+				WatchKind::Access => "awatch"
+			};
+			self.halt(&format!("T05{}:{:04x};", kind, hit.address));
+		} else if self.is_stepping {
+			self.halt("S05");
+		} else if self.sw_breakpoints.contains(&cpu.next_op_address) {
+			self.halt("T05swbreak:;");
+		} else if self.hw_breakpoints.contains(&cpu.next_op_address) {
+			self.halt("T05hwbreak:;");
+		}
+	}
+	fn halt(&mut self, reason: &str) {
+		self.is_halted = true;
+		self.is_stepping = false;
+		self.send_packet(reason);
+	}
+	fn resume(&mut self, cpu: &mut Cpu, memory_bus: &mut MemoryBus, args: &str, is_stepping: bool) {
+		if let Some(address) = parse_hex(args) {
+			cpu.jump_to(memory_bus, address);
+		}
+		self.is_stepping = is_stepping;
+		self.is_halted = false;
+	}
+	fn disconnect(&mut self, memory_bus: &mut MemoryBus) {
+		println!("GDB: client disconnected");
+		memory_bus.watchpoints.list.retain(|watchpoint| !self.watchpoints.contains(watchpoint));
+		self.client = None;
+		self.buffer.clear();
+		self.sw_breakpoints.clear();
+		self.hw_breakpoints.clear();
+		self.watchpoints.clear();
+		self.is_stepping = false;
+		self.is_halted = false;
+	}
+	fn next_input(&mut self) -> Option<GdbInput> {
+		loop {
+			match self.buffer.first()? {
+				0x03 => {
+					self.buffer.remove(0);
+					return Some(GdbInput::Interrupt);
+				}
+				b'$' => {
+					let end = self.buffer.iter().position(|byte| *byte == b'#')?;
+					if self.buffer.len() < end + 3 {
+						return None;
+					}
+					let packet = String::from_utf8_lossy(&self.buffer[1..end]).into_owned();
+					self.buffer.drain(..end + 3);
+					return Some(GdbInput::Packet(packet));
+				}
+				_ => {self.buffer.remove(0);}
+			}
+		}
+	}
+	fn send_raw(&mut self, data: &[u8]) {
+		if let Some(client) = &mut self.client {
+			let res = client.set_nonblocking(false)
+				.and_then(|_| client.write_all(data))
+				.and_then(|_| client.set_nonblocking(true));
+			if let Err(err) = res {
+				println!("GDB: connection error: {}", err);
+				self.client = None;
+			}
+		}
+	}
+	fn send_packet(&mut self, packet: &str) {
+		let checksum = packet.bytes().fold(0u8, |checksum, byte| checksum.wrapping_add(byte));
+		self.send_raw(format!("${}#{:02x}", packet, checksum).as_bytes());
+	}
+	fn handle_packet(&mut self, packet: &str, cpu: &mut Cpu, memory_bus: &mut MemoryBus) -> Option<String> {
+		let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+		let reply = match command {
+			"?" => "S05".to_string(),
+			"g" => (0..NB_REGISTERS).filter_map(|reg_nb| read_register(cpu, reg_nb)).map(encode_register).collect(),
+			"G" => {
+				for reg_nb in 0..NB_REGISTERS {
+					if let Some(value) = args.get(reg_nb * 4..).and_then(decode_register) {
+						write_register(cpu, memory_bus, reg_nb, value);
+					}
+				}
+				"OK".to_string()
+			}
+			"p" => match usize::from_str_radix(args, 16).ok().and_then(|reg_nb| read_register(cpu, reg_nb)) {
+				Some(value) => encode_register(value),
+				None => "E01".to_string()
+			},
+			"P" => match args.split_once('=').and_then(|(reg_nb, value)| Some((usize::from_str_radix(reg_nb, 16).ok()?, decode_register(value)?))) {
+				Some((reg_nb, value)) if write_register(cpu, memory_bus, reg_nb, value) => "OK".to_string(),
+				_ => "E01".to_string()
+			},
+			"m" => match parse_address_len(args) {
+				Some((address, len)) => (0..len).map(|i| format!("{:02x}", memory_bus.read_byte(address.wrapping_add(i)))).collect(),
+				None => "E01".to_string()
+			},
+			"M" => self.write_memory(memory_bus, args).unwrap_or_else(|| "E01".to_string()),
+			"c" => {
+				self.resume(cpu, memory_bus, args, false);
+				return None;
+			}
+			"s" => {
+				self.resume(cpu, memory_bus, args, true);
+				return None;
+			}
+			"Z" | "z" => self.update_breakpoint(memory_bus, args, command == "Z").unwrap_or_else(|| "E01".to_string()),
+			"H" | "T" => "OK".to_string(),
+			"D" => {
+				self.send_packet("OK");
+				self.disconnect(memory_bus);
+				return None;
+			}
+			"k" => {
+				self.disconnect(memory_bus);
+				return None;
+			}
+			"q" => self.handle_query(args),
+			_ => String::new()
+		};
+		Some(reply)
+	}
+	fn handle_query(&self, query: &str) -> String {
+		if query.starts_with("Supported") {
+			"PacketSize=1000;qXfer:features:read+;swbreak+;hwbreak+".to_string()
+		} else if let Some(args) = query.strip_prefix("Xfer:features:read:target.xml:") {
+			match parse_address_len(args) {
+				Some((offset, len)) => {
+					let contents = TARGET_XML.get(offset as usize..).unwrap_or("");
+					if contents.len() <= len as usize {
+						format!("l{}", contents)
+					} else {
+						format!("m{}", &contents[..len as usize])
+					}
+				}
+				None => "E01".to_string()
+			}
+		} else {
+			match query {
+				"Attached" => "1".to_string(),
+				"C" => "QC1".to_string(),
+				"fThreadInfo" => "m1".to_string(),
+				"sThreadInfo" => "l".to_string(),
+				_ => String::new()
+			}
+		}
+	}
+	fn write_memory(&self, memory_bus: &mut MemoryBus, args: &str) -> Option<String> {
+		let (address_len, data) = args.split_once(':')?;
+		let (address, len) = parse_address_len(address_len)?;
+		if data.len() != len as usize * 2 {
+			return None;
+		}
+		// Writes to the ROM area would be interpreted as mapper commands
+		if (0..len).any(|i| address.wrapping_add(i) < 0x8000) {
+			return None;
+		}
+		for i in 0..len {
+			let byte = u8::from_str_radix(data.get(i as usize * 2..i as usize * 2 + 2)?, 16).ok()?;
+			memory_bus.write_byte(address.wrapping_add(i), byte);
+		}
+		Some("OK".to_string())
+	}
+	fn update_breakpoint(&mut self, memory_bus: &mut MemoryBus, args: &str, is_insertion: bool) -> Option<String> {
+		let mut args = args.split(',');
+		let kind = args.next()?;
+		let address = parse_hex(args.next()?)?;
+		let len = parse_hex(args.next()?)?;
+		let watch_kind = match kind {
+			"0" | "1" => {
+				let breakpoints = if kind == "0" {&mut self.sw_breakpoints} else {&mut self.hw_breakpoints};
+				if is_insertion {breakpoints.insert(address);} else {breakpoints.remove(&address);}
+				return Some("OK".to_string());
+			}
+			"2" => WatchKind::Write,
+			"3" => WatchKind::Read,
+			"4" => WatchKind::Access,
+			_ => return Some(String::new())
+		};
+		let watchpoint = Watchpoint { address, len, kind: watch_kind, source: WatchSource::Gdb };
+		if is_insertion {
+			self.watchpoints.push(watchpoint);
+			memory_bus.watchpoints.list.push(watchpoint);
+		} else {
+			if let Some(pos) = self.watchpoints.iter().position(|w| *w == watchpoint) {
+				self.watchpoints.remove(pos);
+			}
+			if let Some(pos) = memory_bus.watchpoints.list.iter().position(|w| *w == watchpoint) {
+				memory_bus.watchpoints.list.remove(pos);
+			}
+		}
+		Some("OK".to_string())
+	}
+}
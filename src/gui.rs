@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use egui::{ClippedPrimitive, Context, TexturesDelta, ColorImage, TextureOptions, Button};
+use egui::{ClippedPrimitive, Context, TexturesDelta, ColorImage, TextureOptions, Button, TextEdit, RichText, Color32};
 use egui_file::FileDialog;
 use egui_wgpu::renderer::{Renderer, ScreenDescriptor};
 use pixels::{wgpu, PixelsContext};
@@ -8,7 +8,15 @@ use winit::event::WindowEvent;
 use winit::event_loop::EventLoopWindowTarget;
 use winit::window::Window;
 
-use crate::cpu::Cpu;
+use crate::cpu::{Cpu, history::CallKind};
+use crate::movie::MovieMode;
+use crate::debugger::{Debugger, Breakpoint, Condition, OpcodeBreak, INTERRUPT_NAMES};
+use crate::memory_bus::{MemoryBus, watchpoints::WatchKind, memory_region::MemoryRegion};
+use crate::disassembler::{self, Symbols};
+
+const DISASSEMBLY_NB_LINES: usize = 16;
+const MEMORY_VIEWER_ROW_LEN: usize = 16;
+const MEMORY_VIEWER_HIGHLIGHT_FRAMES: u8 = 30;
 
 pub(crate) struct Framework {
 	// State for egui.
@@ -72,12 +80,12 @@ impl Framework {
 	}
 
 	/// Prepare egui.
-	pub(crate) fn prepare(&mut self, window: &Window, cpu: &Cpu) {
+	pub(crate) fn prepare(&mut self, window: &Window, cpu: &Cpu, memory_bus: Option<&MemoryBus>) {
 		// Run the egui frame and create all paint jobs to prepare for rendering.
 		let raw_input = self.egui_state.take_egui_input(window);
 		let output = self.egui_ctx.run(raw_input, |egui_ctx| {
 			// Draw the demo application.
-			self.gui.ui(egui_ctx, cpu);
+			self.gui.ui(egui_ctx, cpu, memory_bus);
 		});
 
 		self.textures.append(output.textures_delta);
@@ -133,6 +141,216 @@ impl Framework {
 	}
 }
 
+struct DebuggerForm {
+	breakpoint_address: String,
+	breakpoint_condition: String,
+	watchpoint_address: String,
+	watchpoint_kind: WatchKind,
+	watchpoint_condition: String,
+	opcode: String,
+	disassembly_goto: String,
+	error: Option<String>
+}
+
+impl DebuggerForm {
+	fn new() -> Self {
+		DebuggerForm {
+			breakpoint_address: String::new(),
+			breakpoint_condition: String::new(),
+			watchpoint_address: String::new(),
+			watchpoint_kind: WatchKind::Write,
+			watchpoint_condition: String::new(),
+			opcode: String::new(),
+			disassembly_goto: String::new(),
+			error: None
+		}
+	}
+}
+
+struct MemoryViewer {
+	region: MemoryRegion,
+	snapshot: Vec<u8>,
+	highlights: Vec<u8>,
+	selected: Option<usize>,
+	edit_value: String,
+	is_edit_focus_requested: bool,
+	goto_address: String,
+	search_value: String,
+	scroll_to: Option<usize>,
+	error: Option<String>
+}
+
+impl MemoryViewer {
+	fn new() -> Self {
+		MemoryViewer {
+			region: MemoryRegion::Bus,
+			snapshot: Vec::new(),
+			highlights: Vec::new(),
+			selected: None,
+			edit_value: String::new(),
+			is_edit_focus_requested: false,
+			goto_address: String::new(),
+			search_value: String::new(),
+			scroll_to: None,
+			error: None
+		}
+	}
+	fn select(&mut self, memory_bus: &MemoryBus, offset: usize) {
+		self.selected = Some(offset);
+		self.edit_value = format!("{:02X}", memory_bus.peek(self.region, offset));
+		self.is_edit_focus_requested = true;
+		self.scroll_to = Some(offset);
+	}
+	/* Marks the bytes that changed since the last refresh, fading out after a while */
+	fn refresh(&mut self, memory_bus: &MemoryBus) {
+		let contents: Vec<u8> = (0..self.region.size()).map(|offset| memory_bus.peek(self.region, offset)).collect();
+		if self.snapshot.len() != contents.len() {
+			self.highlights = vec![0; contents.len()];
+		} else {
+			for (offset, highlight) in self.highlights.iter_mut().enumerate() {
+				if contents[offset] != self.snapshot[offset] {
+					*highlight = MEMORY_VIEWER_HIGHLIGHT_FRAMES;
+				} else {
+					*highlight = highlight.saturating_sub(1);
+				}
+			}
+		}
+		self.snapshot = contents;
+	}
+	fn goto(&mut self, memory_bus: &MemoryBus) -> Result<(), String> {
+		let (bank, address) = parse_address_input(&self.goto_address)?;
+		if let Some(bank) = bank {
+			let region = MemoryRegion::from_bank_address(bank, address).filter(|region| memory_bus.regions().contains(region)).ok_or_else(|| format!("No bank {:02X} at 0x{:04X}", bank, address))?;
+			self.set_region(region);
+		}
+		let offset = (address as usize).wrapping_sub(self.region.base_address() as usize);
+		if offset >= self.region.size() {
+			return Err(format!("0x{:04X} is outside of {}", address, self.region));
+		}
+		self.select(memory_bus, offset);
+		Ok(())
+	}
+	/* Looks for a sequence of hex bytes after the selection, wrapping around the region */
+	fn search(&mut self, memory_bus: &MemoryBus) -> Result<(), String> {
+		let digits: String = self.search_value.chars().filter(|c| !c.is_whitespace()).collect();
+		if digits.is_empty() || !digits.is_ascii() || !digits.len().is_multiple_of(2) {
+			return Err("Search for whole bytes, e.g. \"3E 42\"".to_string());
+		}
+		let pattern = (0..digits.len()).step_by(2).map(|i| u8::from_str_radix(&digits[i..i + 2], 16)).collect::<Result<Vec<u8>, _>>().map_err(|_| format!("Invalid hexadecimal value '{}'", self.search_value.trim()))?;
+		let size = self.region.size();
+		let start = self.selected.map_or(0, |selected| selected + 1);
+		let found = (0..size).map(|i| (start + i) % size).find(|&offset| {
+			pattern.iter().enumerate().all(|(i, byte)| offset + i < size && memory_bus.peek(self.region, offset + i) == *byte)
+		}).ok_or_else(|| format!("{} not found in {}", self.search_value.trim(), self.region))?;
+		self.select(memory_bus, found);
+		Ok(())
+	}
+	fn set_region(&mut self, region: MemoryRegion) {
+		if self.region != region {
+			self.region = region;
+			self.snapshot.clear();
+			self.selected = None;
+		}
+	}
+	fn ui(&mut self, ui: &mut egui::Ui, memory_bus: &MemoryBus, memory_pokes: &mut Vec<(MemoryRegion, usize, u8)>) {
+		if !memory_bus.regions().contains(&self.region) {
+			self.set_region(MemoryRegion::Bus);
+		}
+		self.refresh(memory_bus);
+		ui.horizontal(|ui| {
+			let mut region = self.region;
+			egui::ComboBox::from_id_source("memory_region")
+			.selected_text(region.to_string())
+			.show_ui(ui, |ui| {
+				for available_region in memory_bus.regions() {
+					ui.selectable_value(&mut region, available_region, available_region.to_string());
+				}
+			});
+			self.set_region(region);
+			let goto = ui.add(TextEdit::singleline(&mut self.goto_address).hint_text("address").desired_width(60.0));
+			if ui.button("Go to").clicked() || (goto.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter))) {
+				self.error = self.goto(memory_bus).err();
+			}
+			let search = ui.add(TextEdit::singleline(&mut self.search_value).hint_text("bytes").desired_width(80.0));
+			if ui.button("Find next").clicked() || (search.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter))) {
+				self.error = self.search(memory_bus).err();
+			}
+		});
+		if let Some(error) = &self.error {
+			ui.colored_label(Color32::RED, error);
+		}
+		let row_height = ui.text_style_height(&egui::TextStyle::Monospace).max(ui.spacing().interact_size.y);
+		let mut scroll_area = egui::ScrollArea::vertical().auto_shrink([false, false]).max_height(320.0);
+		if let Some(offset) = self.scroll_to.take() {
+			scroll_area = scroll_area.vertical_scroll_offset((offset / MEMORY_VIEWER_ROW_LEN) as f32 * (row_height + ui.spacing().item_spacing.y));
+		}
+		scroll_area.show_rows(ui, row_height, self.region.size().div_ceil(MEMORY_VIEWER_ROW_LEN), |ui, rows| {
+			for row in rows {
+				ui.horizontal(|ui| {
+					ui.spacing_mut().item_spacing.x = 4.0;
+					ui.label(RichText::new(format!("{:04X}", self.region.base_address() as usize + row * MEMORY_VIEWER_ROW_LEN)).monospace().color(Color32::GRAY));
+					let offsets = row * MEMORY_VIEWER_ROW_LEN..((row + 1) * MEMORY_VIEWER_ROW_LEN).min(self.region.size());
+					for offset in offsets.clone() {
+						if self.selected == Some(offset) {
+							let edit = ui.add(TextEdit::singleline(&mut self.edit_value).font(egui::TextStyle::Monospace).desired_width(16.0));
+							if std::mem::take(&mut self.is_edit_focus_requested) {
+								edit.request_focus();
+							}
+							if edit.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter)) {
+								match u8::from_str_radix(self.edit_value.trim(), 16) {
+									Ok(value) => {
+										memory_pokes.push((self.region, offset, value));
+										self.error = None;
+										if offset + 1 < self.region.size() {
+											self.select(memory_bus, offset + 1);
+											self.scroll_to = None;
+										}
+									}
+									Err(_) => self.error = Some(format!("Invalid hexadecimal value '{}'", self.edit_value.trim()))
+								}
+							}
+							continue;
+						}
+						let byte = self.snapshot[offset];
+						let text = RichText::new(format!("{:02X}", byte)).monospace();
+						let text = if self.highlights[offset] > 0 {text.color(Color32::RED)} else {text};
+						if ui.add(egui::Label::new(text).sense(egui::Sense::click())).clicked() {
+							self.select(memory_bus, offset);
+							self.scroll_to = None;
+						}
+					}
+					let ascii: String = offsets.map(|offset| self.snapshot[offset]).map(|byte| if byte.is_ascii_graphic() || byte == b' ' {byte as char} else {'.'}).collect();
+					ui.label(RichText::new(ascii).monospace().color(Color32::GRAY));
+				});
+			}
+		});
+	}
+}
+
+fn parse_hex_input(input: &str) -> Result<usize, String> {
+	let digits = input.trim();
+	let digits = digits.strip_prefix("0x").or_else(|| digits.strip_prefix('$')).unwrap_or(digits);
+	usize::from_str_radix(digits, 16).map_err(|_| format!("Invalid hexadecimal value '{}'", input.trim()))
+}
+
+/* Accepts "4567", "$4567" or bank-qualified "03:4567" */
+fn parse_address_input(input: &str) -> Result<(Option<usize>, u16), String> {
+	let (bank, address) = match input.split_once(':') {
+		Some((bank, address)) => (Some(parse_hex_input(bank)?), address),
+		None => (None, input)
+	};
+	let address = parse_hex_input(address)?;
+	u16::try_from(address).map(|address| (bank, address)).map_err(|_| format!("Address 0x{:X} is out of range", address))
+}
+
+fn parse_condition_input(input: &str) -> Result<Option<Condition>, String> {
+	if input.trim().is_empty() {
+		Ok(None)
+	} else {
+		Condition::parse(input).map(Some).map_err(|err| format!("Invalid condition: {}", err))
+	}
+}
+
 pub struct Gui {
 	pub disp_tileset: bool,
 	pub disp_tilemap: bool,
@@ -142,11 +360,41 @@ pub struct Gui {
 	program_icon: Option<egui::TextureHandle>,
 	pub opened_file: Option<PathBuf>,
   	open_file_dialog: Option<FileDialog>,
+	pub opened_patch: Option<PathBuf>,
+	patch_file_dialog: Option<FileDialog>,
 	pub reset_requested: bool,
 	pub force_dmg: bool,
 	pub is_execution_paused: bool,
 	pub is_debugger_stepping_instr: bool,
-	pub is_debugger_stepping_frame: bool
+	pub is_debugger_stepping_frame: bool,
+	pub is_debugger_running: bool,
+	pub debugger: Debugger,
+	debugger_form: DebuggerForm,
+	pub memory_viewer_window_open: bool,
+	memory_viewer: MemoryViewer,
+	pub memory_pokes: Vec<(MemoryRegion, usize, u8)>,
+	pub symbols: Option<Symbols>,
+	symbols_file_dialog: Option<FileDialog>,
+	pub symbols_requested: Option<PathBuf>,
+	disassembly_start: u16,
+	disassembly_end: u16,
+	is_disassembly_following_pc: bool,
+	movie_file_dialog: Option<(FileDialog, MovieMode)>,
+	pub movie_requested: Option<(PathBuf, MovieMode)>,
+	pub movie_stop_requested: bool,
+	pub movie_status: Option<String>,
+	trace_file_dialog: Option<FileDialog>,
+	pub trace_requested: Option<PathBuf>,
+	pub trace_stop_requested: bool,
+	pub is_trace_verbose: bool,
+	pub trace_status: Option<String>,
+	pub cpu_status: Option<String>,
+	pub rom_status: Option<String>,
+	pub is_rumbling: bool,
+	script_file_dialog: Option<FileDialog>,
+	pub script_requested: Option<PathBuf>,
+	pub script_unload_requested: bool,
+	pub script_status: Option<String>
 }
 
 impl Gui {
@@ -160,16 +408,46 @@ impl Gui {
 			program_icon: None,
 			opened_file: None,
 			open_file_dialog: None,
+			opened_patch: None,
+			patch_file_dialog: None,
 			reset_requested: false,
 			force_dmg: false,
 			is_execution_paused: false,
 			is_debugger_stepping_instr: false,
-			is_debugger_stepping_frame: false
+			is_debugger_stepping_frame: false,
+			is_debugger_running: false,
+			debugger: Debugger::new(),
+			debugger_form: DebuggerForm::new(),
+			memory_viewer_window_open: false,
+			memory_viewer: MemoryViewer::new(),
+			memory_pokes: Vec::new(),
+			symbols: None,
+			symbols_file_dialog: None,
+			symbols_requested: None,
+			disassembly_start: 0x0000,
+			disassembly_end: 0x0000,
+			is_disassembly_following_pc: true,
+			movie_file_dialog: None,
+			movie_requested: None,
+			movie_stop_requested: false,
+			movie_status: None,
+			trace_file_dialog: None,
+			trace_requested: None,
+			trace_stop_requested: false,
+			is_trace_verbose: false,
+			trace_status: None,
+			cpu_status: None,
+			rom_status: None,
+			is_rumbling: false,
+			script_file_dialog: None,
+			script_requested: None,
+			script_unload_requested: false,
+			script_status: None
 		}
 	}
 
 	/// Create the UI using egui.
-	fn ui(&mut self, ctx: &Context, cpu: &Cpu) {
+	fn ui(&mut self, ctx: &Context, cpu: &Cpu, memory_bus: Option<&MemoryBus>) {
 		egui::TopBottomPanel::top("menubar_container").show(ctx, |ui| {
 			egui::menu::bar(ui, |ui| {
 				ui.menu_button("File", |ui| {
@@ -179,8 +457,51 @@ impl Gui {
 						self.open_file_dialog = Some(dialog);
 						ui.close_menu();
 					}
+					if ui.button("Apply patch").clicked() {
+						let mut dialog = FileDialog::open_file(self.opened_file.clone());
+						dialog.open();
+						self.patch_file_dialog = Some(dialog);
+						ui.close_menu();
+					}
 					if ui.button("Reset").clicked() {
 						self.reset_requested = true;
+						self.movie_stop_requested = true;
+						ui.close_menu();
+					}
+					ui.separator();
+					if ui.button("Load script").clicked() {
+						let mut dialog = FileDialog::open_file(None);
+						dialog.open();
+						self.script_file_dialog = Some(dialog);
+						ui.close_menu();
+					}
+					if ui.button("Unload script").clicked() {
+						self.script_unload_requested = true;
+						ui.close_menu();
+					}
+				});
+				ui.menu_button("Movie", |ui| {
+					if ui.button("Record movie").clicked() {
+						let mut dialog = FileDialog::save_file(None);
+						dialog.open();
+						self.movie_file_dialog = Some((dialog, MovieMode::Recording));
+						ui.close_menu();
+					}
+					if ui.button("Play movie").clicked() {
+						let mut dialog = FileDialog::open_file(None);
+						dialog.open();
+						self.movie_file_dialog = Some((dialog, MovieMode::Playback));
+						ui.close_menu();
+					}
+					if ui.button("Verify movie").clicked() {
+						let mut dialog = FileDialog::open_file(None);
+						dialog.open();
+						self.movie_file_dialog = Some((dialog, MovieMode::Verification));
+						ui.close_menu();
+					}
+					ui.separator();
+					if ui.button("Stop movie").clicked() {
+						self.movie_stop_requested = true;
 						ui.close_menu();
 					}
 				});
@@ -200,6 +521,25 @@ impl Gui {
 						self.debugger_window_open = true;
 						ui.close_menu();
 					}
+					if ui.button("Open memory viewer").clicked() {
+						self.memory_viewer_window_open = true;
+						ui.close_menu();
+					}
+					ui.separator();
+					if ui.add(Button::new("Start trace").shortcut_text("F9")).clicked() {
+						let mut dialog = FileDialog::save_file(None);
+						dialog.open();
+						self.trace_file_dialog = Some(dialog);
+						ui.close_menu();
+					}
+					if ui.add(Button::new("Verbose trace").shortcut_text(if self.is_trace_verbose {"√"} else {""})).clicked() {
+						self.is_trace_verbose = !self.is_trace_verbose;
+						ui.close_menu();
+					}
+					if ui.button("Stop trace").clicked() {
+						self.trace_stop_requested = true;
+						ui.close_menu();
+					}
 					ui.separator();
 					if ui.button("Open tileset viewer").clicked() {
 						self.disp_tileset = true;
@@ -221,13 +561,78 @@ impl Gui {
 						ui.close_menu();
 					}
 				});
+				if let Some(movie_status) = &self.movie_status {
+					ui.separator();
+					ui.label(movie_status);
+				}
+				if let Some(cpu_status) = &self.cpu_status {
+					ui.separator();
+					ui.colored_label(Color32::RED, cpu_status);
+				}
+				if let Some(rom_status) = &self.rom_status {
+					ui.separator();
+					ui.colored_label(Color32::RED, rom_status);
+				}
+				if self.is_rumbling {
+					ui.separator();
+					ui.colored_label(Color32::YELLOW, "Rumble");
+				}
+				if let Some(trace_status) = &self.trace_status {
+					ui.separator();
+					ui.label(trace_status);
+				}
+				if let Some(script_status) = &self.script_status {
+					ui.separator();
+					ui.label(script_status);
+				}
 			});
 		});
 		if let Some(dialog) = &mut self.open_file_dialog {
 			if dialog.show(ctx).selected() {
 				if let Some(file) = dialog.path() {
+					self.symbols_requested = Some(file.with_extension("sym")).filter(|path| path.exists());
+					self.symbols = None;
 					self.opened_file = Some(file);
+					self.opened_patch = None;
+					self.reset_requested = true;
+					self.movie_stop_requested = true;
+				};
+			}
+		}
+		if let Some(dialog) = &mut self.patch_file_dialog {
+			if dialog.show(ctx).selected() {
+				if let Some(file) = dialog.path() {
+					self.opened_patch = Some(file);
 					self.reset_requested = true;
+					self.movie_stop_requested = true;
+				};
+			}
+		}
+		if let Some(dialog) = &mut self.symbols_file_dialog {
+			if dialog.show(ctx).selected() {
+				if let Some(file) = dialog.path() {
+					self.symbols_requested = Some(file);
+				};
+			}
+		}
+		if let Some(dialog) = &mut self.trace_file_dialog {
+			if dialog.show(ctx).selected() {
+				if let Some(file) = dialog.path() {
+					self.trace_requested = Some(file);
+				};
+			}
+		}
+		if let Some(dialog) = &mut self.script_file_dialog {
+			if dialog.show(ctx).selected() {
+				if let Some(file) = dialog.path() {
+					self.script_requested = Some(file);
+				};
+			}
+		}
+		if let Some((dialog, mode)) = &mut self.movie_file_dialog {
+			if dialog.show(ctx).selected() {
+				if let Some(file) = dialog.path() {
+					self.movie_requested = Some((file, *mode));
 				};
 			}
 		}
@@ -260,11 +665,11 @@ impl Gui {
 			});
 		});
 
+		let mut debugger_window_open = self.debugger_window_open;
 		egui::Window::new("Debugger")
-		.open(&mut self.debugger_window_open)
+		.open(&mut debugger_window_open)
 		.show(ctx, |ui| {
-			ui.label("Next Instruction: ".to_owned() + &format!("{:?}", cpu.next_op));
-			ui.label("PC: ".to_owned() + &format!("0x{:04X}", cpu.registers.program_counter - 1) + " + 1");
+			ui.label(format!("PC: 0x{:04X}", cpu.next_op_address));
 			ui.horizontal(|ui| {
 				if ui.button("Step instruction").clicked() {
 					self.is_debugger_stepping_instr = true;
@@ -272,25 +677,273 @@ impl Gui {
 				if ui.button("Step frame").clicked() {
 					self.is_debugger_stepping_frame = true;
 				}
+				if ui.button(if self.is_debugger_running {"Break"} else {"Continue"}).clicked() {
+					self.is_debugger_running = !self.is_debugger_running;
+				}
 			});
+			if let Some(last_break) = &self.debugger.last_break {
+				ui.label(format!("Stopped: {}", last_break));
+			}
 			ui.separator();
 			ui.horizontal(|ui| {
-				ui.label("A: ".to_owned() + &format!("0x{:02X}", cpu.registers.a));
+				ui.label(format!("A: 0x{:02X}", cpu.registers.a));
 				ui.label("F: ".to_owned() + if cpu.registers.f.zero {"Z "} else {"- "} + if cpu.registers.f.substract {"N "} else {"- "} + if cpu.registers.f.half_carry {"H "} else {"- "} + if cpu.registers.f.carry {"C"} else {"-"});
 			});
 			ui.horizontal(|ui| {
-				ui.label("B: ".to_owned() + &format!("0x{:02X}", cpu.registers.b));
-				ui.label("C: ".to_owned() + &format!("0x{:02X}", cpu.registers.c));
+				ui.label(format!("B: 0x{:02X}", cpu.registers.b));
+				ui.label(format!("C: 0x{:02X}", cpu.registers.c));
 			});
 			ui.horizontal(|ui| {
-				ui.label("D: ".to_owned() + &format!("0x{:02X}", cpu.registers.d));
-				ui.label("E: ".to_owned() + &format!("0x{:02X}", cpu.registers.e));
+				ui.label(format!("D: 0x{:02X}", cpu.registers.d));
+				ui.label(format!("E: 0x{:02X}", cpu.registers.e));
 			});
 			ui.horizontal(|ui| {
-				ui.label("H: ".to_owned() + &format!("0x{:02X}", cpu.registers.h));
-				ui.label("L: ".to_owned() + &format!("0x{:02X}", cpu.registers.l));
+				ui.label(format!("H: 0x{:02X}", cpu.registers.h));
+				ui.label(format!("L: 0x{:02X}", cpu.registers.l));
+			});
+			ui.label(format!("SP: 0x{:04X}", cpu.registers.stack_pointer));
+			if let Some(memory_bus) = memory_bus {
+				ui.separator();
+				self.disassembly_ui(ui, cpu, memory_bus);
+				ui.separator();
+				Self::history_ui(ui, cpu, memory_bus, self.symbols.as_ref());
+			}
+			ui.separator();
+			Self::breakpoints_ui(ui, &mut self.debugger, &mut self.debugger_form);
+		});
+		self.debugger_window_open = debugger_window_open;
+
+		if let Some(memory_bus) = memory_bus {
+			egui::Window::new("Memory viewer")
+			.open(&mut self.memory_viewer_window_open)
+			.show(ctx, |ui| {
+				self.memory_viewer.ui(ui, memory_bus, &mut self.memory_pokes);
 			});
-			ui.label("SP: ".to_owned() + &format!("0x{:04X}", cpu.registers.stack_pointer));
+		}
+	}
+
+	fn disassembly_ui(&mut self, ui: &mut egui::Ui, cpu: &Cpu, memory_bus: &MemoryBus) {
+		let pc = cpu.next_op_address;
+		ui.horizontal(|ui| {
+			ui.checkbox(&mut self.is_disassembly_following_pc, "Follow PC");
+			if ui.small_button("▲").clicked() {
+				self.disassembly_start = disassembler::previous_instruction_address(memory_bus, self.disassembly_start);
+				self.is_disassembly_following_pc = false;
+			}
+			if ui.small_button("▼").clicked() {
+				self.disassembly_start = self.disassembly_start.wrapping_add(disassembler::disassemble(memory_bus, None, self.disassembly_start).bytes.len() as u16);
+				self.is_disassembly_following_pc = false;
+			}
+			let goto = ui.add(TextEdit::singleline(&mut self.debugger_form.disassembly_goto).hint_text("address or label").desired_width(120.0));
+			if ui.button("Go to").clicked() || (goto.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter))) {
+				let label_address = self.symbols.as_ref().and_then(|symbols| symbols.find_label(self.debugger_form.disassembly_goto.trim())).map(|(_, address)| address);
+				match label_address.map_or_else(|| parse_address_input(&self.debugger_form.disassembly_goto).map(|(_, address)| address), Ok) {
+					Ok(address) => {
+						self.disassembly_start = address;
+						self.is_disassembly_following_pc = false;
+						self.debugger_form.error = None;
+					}
+					Err(err) => self.debugger_form.error = Some(err)
+				}
+			}
+			if ui.button("Load symbols").clicked() {
+				let mut dialog = FileDialog::open_file(self.opened_file.clone());
+				dialog.open();
+				self.symbols_file_dialog = Some(dialog);
+			}
 		});
+		if self.is_disassembly_following_pc && (pc < self.disassembly_start || pc >= self.disassembly_end) {
+			self.disassembly_start = pc;
+		}
+		let response = ui.vertical(|ui| {
+			let mut address = self.disassembly_start;
+			for _ in 0..DISASSEMBLY_NB_LINES {
+				if let Some(label) = self.symbols.as_ref().and_then(|symbols| symbols.label_at(memory_bus, address)) {
+					ui.label(RichText::new(format!("{}:", label)).monospace().color(Color32::LIGHT_BLUE));
+				}
+				let line = disassembler::disassemble(memory_bus, self.symbols.as_ref(), address);
+				let location = match line.bank {
+					Some(bank) => format!("{:02X}:{:04X}", bank, line.address),
+					None => format!("   {:04X}", line.address)
+				};
+				let bytes: Vec<String> = line.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+				let text = RichText::new(format!("{} {} {:<8}  {}", if line.address == pc {">"} else {" "}, location, bytes.join(" "), line.text)).monospace();
+				ui.label(if line.address == pc {text.color(Color32::YELLOW)} else {text});
+				address = address.wrapping_add(line.bytes.len() as u16);
+			}
+			self.disassembly_end = address;
+		}).response;
+		if response.hovered() {
+			let scroll = ui.input(|input| input.scroll_delta.y);
+			if scroll != 0.0 {
+				self.is_disassembly_following_pc = false;
+				self.disassembly_start = if scroll > 0.0 {
+					disassembler::previous_instruction_address(memory_bus, self.disassembly_start)
+				} else {
+					self.disassembly_start.wrapping_add(disassembler::disassemble(memory_bus, None, self.disassembly_start).bytes.len() as u16)
+				};
+			}
+		}
+	}
+
+	fn history_ui(ui: &mut egui::Ui, cpu: &Cpu, memory_bus: &MemoryBus, symbols: Option<&Symbols>) {
+		let location = |address: u16| match symbols.and_then(|symbols| symbols.label_at(memory_bus, address)) {
+			Some(label) => format!("0x{:04X} ({})", address, label),
+			None => format!("0x{:04X}", address)
+		};
+		egui::CollapsingHeader::new(format!("Call stack ({})", cpu.history.call_stack.len())).default_open(true).show(ui, |ui| {
+			for frame in cpu.history.call_stack.iter().rev() {
+				let kind = match frame.kind {
+					CallKind::Call		=> "CALL",
+					CallKind::Rst		=> "RST",
+					CallKind::Interrupt	=> "INT"
+				};
+				ui.label(RichText::new(format!("{:<4} {} from {}", kind, location(frame.target), location(frame.call_site))).monospace());
+			}
+		});
+		egui::CollapsingHeader::new("Interrupt history").show(ui, |ui| {
+			for dispatch in cpu.history.interrupts.iter().rev() {
+				ui.label(RichText::new(dispatch.to_string()).monospace());
+			}
+		});
+		egui::CollapsingHeader::new("PC history").show(ui, |ui| {
+			egui::ScrollArea::vertical().max_height(160.0).show(ui, |ui| {
+				for address in cpu.history.pcs.iter().rev() {
+					ui.label(RichText::new(format!("{}  {}", location(*address), disassembler::disassemble(memory_bus, symbols, *address).text)).monospace());
+				}
+			});
+		});
+	}
+
+	fn breakpoints_ui(ui: &mut egui::Ui, debugger: &mut Debugger, form: &mut DebuggerForm) {
+		egui::CollapsingHeader::new("Breakpoints").default_open(true).show(ui, |ui| {
+			let mut removed = None;
+			for (i, breakpoint) in debugger.breakpoints.iter_mut().enumerate() {
+				ui.horizontal(|ui| {
+					let bank = breakpoint.bank.map(|bank| format!("{:02X}:", bank)).unwrap_or_default();
+					let condition = breakpoint.condition.as_ref().map(|condition| format!(" if {}", condition.source)).unwrap_or_default();
+					ui.checkbox(&mut breakpoint.is_enabled, format!("{}{:04X}{}", bank, breakpoint.address, condition));
+					if ui.small_button("x").clicked() {
+						removed = Some(i);
+					}
+				});
+			}
+			if let Some(i) = removed {
+				debugger.breakpoints.remove(i);
+			}
+			ui.horizontal(|ui| {
+				ui.add(TextEdit::singleline(&mut form.breakpoint_address).hint_text("[bank:]address").desired_width(80.0));
+				ui.add(TextEdit::singleline(&mut form.breakpoint_condition).hint_text("condition").desired_width(140.0));
+				if ui.button("Add").clicked() {
+					let breakpoint = parse_address_input(&form.breakpoint_address).and_then(|(bank, address)| {
+						Ok(Breakpoint { address, bank, condition: parse_condition_input(&form.breakpoint_condition)?, is_enabled: true })
+					});
+					match breakpoint {
+						Ok(breakpoint) => {
+							debugger.breakpoints.push(breakpoint);
+							form.breakpoint_address.clear();
+							form.breakpoint_condition.clear();
+							form.error = None;
+						}
+						Err(err) => form.error = Some(err)
+					}
+				}
+			});
+		});
+		egui::CollapsingHeader::new("Watchpoints").default_open(true).show(ui, |ui| {
+			let mut removed = None;
+			for (i, watchpoint) in debugger.watchpoints.iter_mut().enumerate() {
+				ui.horizontal(|ui| {
+					let kind = match watchpoint.watchpoint.kind {
+						WatchKind::Read => "R",
+						WatchKind::Write => "W",
+						WatchKind::Access => "RW"
+					};
+					let end = watchpoint.watchpoint.address.wrapping_add(watchpoint.watchpoint.len.max(1) - 1);
+					let range = if end == watchpoint.watchpoint.address {format!("{:04X}", end)} else {format!("{:04X}-{:04X}", watchpoint.watchpoint.address, end)};
+					let condition = watchpoint.condition.as_ref().map(|condition| format!(" if {}", condition.source)).unwrap_or_default();
+					if ui.checkbox(&mut watchpoint.is_enabled, format!("{} {}{}", kind, range, condition)).changed() {
+						debugger.are_watchpoints_updated = true;
+					}
+					if ui.small_button("x").clicked() {
+						removed = Some(i);
+					}
+				});
+			}
+			if let Some(i) = removed {
+				debugger.watchpoints.remove(i);
+				debugger.are_watchpoints_updated = true;
+			}
+			ui.horizontal(|ui| {
+				ui.add(TextEdit::singleline(&mut form.watchpoint_address).hint_text("address[-end]").desired_width(80.0));
+				egui::ComboBox::from_id_source("watchpoint_kind")
+					.width(50.0)
+					.selected_text(match form.watchpoint_kind {WatchKind::Read => "R", WatchKind::Write => "W", WatchKind::Access => "RW"})
+					.show_ui(ui, |ui| {
+						ui.selectable_value(&mut form.watchpoint_kind, WatchKind::Read, "R");
+						ui.selectable_value(&mut form.watchpoint_kind, WatchKind::Write, "W");
+						ui.selectable_value(&mut form.watchpoint_kind, WatchKind::Access, "RW");
+					});
+				ui.add(TextEdit::singleline(&mut form.watchpoint_condition).hint_text("condition").desired_width(80.0));
+				if ui.button("Add").clicked() {
+					let (start, end) = form.watchpoint_address.split_once('-').unwrap_or((&form.watchpoint_address, &form.watchpoint_address));
+					let watchpoint = parse_address_input(start).and_then(|(_, start)| {
+						let (_, end) = parse_address_input(end)?;
+						if end < start {
+							return Err("Watchpoint range end is before its start".to_string());
+						}
+						Ok((start, end - start + 1, parse_condition_input(&form.watchpoint_condition)?))
+					});
+					match watchpoint {
+						Ok((address, len, condition)) => {
+							debugger.add_watchpoint(address, len, form.watchpoint_kind, condition);
+							form.watchpoint_address.clear();
+							form.watchpoint_condition.clear();
+							form.error = None;
+						}
+						Err(err) => form.error = Some(err)
+					}
+				}
+			});
+		});
+		egui::CollapsingHeader::new("Break on interrupt").show(ui, |ui| {
+			ui.horizontal_wrapped(|ui| {
+				for (is_enabled, name) in debugger.break_on_interrupts.iter_mut().zip(INTERRUPT_NAMES) {
+					ui.checkbox(is_enabled, name);
+				}
+			});
+		});
+		egui::CollapsingHeader::new("Break on opcode").show(ui, |ui| {
+			ui.checkbox(&mut debugger.break_on_illegal_opcode, "Illegal opcodes (before the CPU locks up)");
+			let mut removed = None;
+			for (i, opcode_break) in debugger.opcode_breaks.iter_mut().enumerate() {
+				ui.horizontal(|ui| {
+					let label = if opcode_break.opcode == 0x40 {"0x40 (LD B,B)".to_string()} else {format!("0x{:02X}", opcode_break.opcode)};
+					ui.checkbox(&mut opcode_break.is_enabled, label);
+					if ui.small_button("x").clicked() {
+						removed = Some(i);
+					}
+				});
+			}
+			if let Some(i) = removed {
+				debugger.opcode_breaks.remove(i);
+			}
+			ui.horizontal(|ui| {
+				ui.add(TextEdit::singleline(&mut form.opcode).hint_text("opcode").desired_width(80.0));
+				if ui.button("Add").clicked() {
+					match parse_hex_input(&form.opcode).and_then(|opcode| u8::try_from(opcode).map_err(|_| format!("Opcode 0x{:X} is out of range", opcode))) {
+						Ok(opcode) => {
+							debugger.opcode_breaks.push(OpcodeBreak { opcode, is_enabled: true });
+							form.opcode.clear();
+							form.error = None;
+						}
+						Err(err) => form.error = Some(err)
+					}
+				}
+			});
+		});
+		if let Some(err) = &form.error {
+			ui.colored_label(egui::Color32::RED, err);
+		}
 	}
 }
\ No newline at end of file
@@ -1,8 +1,9 @@
 use winit_input_helper::WinitInputHelper;
 use crate::memory_bus::{MemoryBus, input_memory::InputState};
+use crate::movie::Movie;
 
-pub fn tick(memory_bus: &mut MemoryBus, input: &WinitInputHelper) {
-	let input_state = InputState {
+fn read_keyboard(input: &WinitInputHelper) -> InputState {
+	InputState {
 		is_up_pressed: input.key_held(winit::event::VirtualKeyCode::W),
 		is_down_pressed: input.key_held(winit::event::VirtualKeyCode::S),
 		is_left_pressed: input.key_held(winit::event::VirtualKeyCode::A),
@@ -11,6 +12,73 @@ pub fn tick(memory_bus: &mut MemoryBus, input: &WinitInputHelper) {
 		is_b_pressed: input.key_held(winit::event::VirtualKeyCode::Return),
 		is_start_pressed: input.key_held(winit::event::VirtualKeyCode::LShift),
 		is_select_pressed: input.key_held(winit::event::VirtualKeyCode::Space),
+	}
+}
+
+/* Gamepads supporting force feedback, driven through gilrs by the rumble cartridge motor when built with the "gamepad" feature */
+pub struct RumbleGamepads {
+	#[cfg(feature = "gamepad")]
+	gilrs: Option<gilrs::Gilrs>,
+	#[cfg(feature = "gamepad")]
+	effect: Option<gilrs::ff::Effect>
+}
+
+impl RumbleGamepads {
+	pub fn new() -> Self {
+		RumbleGamepads {
+			#[cfg(feature = "gamepad")]
+			gilrs: gilrs::Gilrs::new().map_err(|err| println!("Unable to initialize gamepads: {}", err)).ok(),
+			#[cfg(feature = "gamepad")]
+			effect: None
+		}
+	}
+	/* Drives the strong motor of the gamepads connected when it starts, for as long as the cartridge one is on */
+	#[cfg(feature = "gamepad")]
+	pub fn set_rumble(&mut self, is_on: bool) {
+		use gilrs::ff::{EffectBuilder, BaseEffect, BaseEffectType, Replay, Ticks};
+		if let Some(effect) = self.effect.take() {
+			let _ = effect.stop();
+		}
+		let Some(gilrs) = &mut self.gilrs else {return};
+		while gilrs.next_event().is_some() {}			// Updates the connected gamepads
+		if !is_on {return}
+		let gamepad_ids: Vec<gilrs::GamepadId> = gilrs.gamepads().filter(|(_, gamepad)| gamepad.is_ff_supported()).map(|(id, _)| id).collect();
+		if gamepad_ids.is_empty() {return}
+		let effect = EffectBuilder::new()
+			.add_effect(BaseEffect {
+				kind: BaseEffectType::Strong {magnitude: 0xC000},
+				scheduling: Replay {play_for: Ticks::from_ms(100), ..Default::default()},
+				..Default::default()
+			})
+			.gamepads(&gamepad_ids)
+			.finish(gilrs);
+		match effect.and_then(|effect| effect.play().map(|_| effect)) {
+			Ok(effect) => self.effect = Some(effect),
+			Err(err) => println!("Unable to start the gamepad rumble: {}", err)
+		}
+	}
+	#[cfg(not(feature = "gamepad"))]
+	pub fn set_rumble(&mut self, _is_on: bool) {}
+}
+
+pub fn tick(memory_bus: &mut MemoryBus, input: &WinitInputHelper, movie: Option<&mut Movie>) {
+	let input_state = match movie {
+		Some(movie) if movie.is_playing() => movie.next_input(),
+		Some(movie) => {
+			/* Buttons held by a script are recorded too, so that the movie plays back without it */
+			let input_state = InputState::from(u8::from(read_keyboard(input)) | u8::from(memory_bus.input_memory.injected_state()));
+			movie.record_input(input_state);
+			input_state
+		}
+		None => read_keyboard(input)
 	};
 	memory_bus.input_memory.update(&input_state)
-}
\ No newline at end of file
+}
+
+/* Forwards the rumble cartridge motor to the gamepads, its state being also kept for the on-screen indicator */
+pub fn tick_rumble(memory_bus: &mut MemoryBus, gamepads: &mut RumbleGamepads, is_motor_on: &mut bool) {
+	if let Some(is_on) = memory_bus.cartridge.take_rumble_event() {
+		gamepads.set_rumble(is_on);
+		*is_motor_on = is_on;
+	}
+}
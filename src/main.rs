@@ -3,10 +3,18 @@ mod cpu;
 mod ppu;
 mod input;
 mod timer;
+mod movie;
+mod crc32;
+mod script;
+mod gdb;
+mod debugger;
+mod disassembler;
+mod tracer;
 
 mod gui;
 
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::time::{Instant, Duration};
 use gui::Framework;
 use pixels::{Error, Pixels, SurfaceTexture};
@@ -16,17 +24,208 @@ use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::{WindowBuilder, Icon};
 use winit_input_helper::WinitInputHelper;
 
-use cpu::Cpu;
+use cpu::{Cpu, CpuState, bus::M_CYCLE};
 use memory_bus::MemoryBus;
 use ppu::{Ppu, VIEWPORT_PX_WIDTH, VIEWPORT_PX_HEIGHT, TILEMAP_PX_HEIGHT, TILEMAP_PX_WIDTH, TILESET_VIEWER_PX_WIDTH, TILESET_VIEWER_PX_HEIGHT};
 use timer::Timer;
+use movie::{Movie, MovieMode};
+use script::ScriptHost;
+use gdb::GdbStub;
+use disassembler::Symbols;
+use tracer::{Tracer, TraceFormat, DEFAULT_TRACE_SIZE_LIMIT};
 
 #[derive(PartialEq, Eq, Hash, Debug, Clone, Copy)]
 enum WindowTypes {
 	Main, Tileset, Tilemap
 }
 
+#[derive(Debug, Default)]
+struct LaunchOptions {
+	rom_path: Option<PathBuf>,
+	patch_path: Option<PathBuf>,
+	script_path: Option<PathBuf>,
+	frame_limit: Option<u64>,
+	gdb_port: Option<u16>,
+	trace_path: Option<PathBuf>,
+	trace_format: TraceFormat,
+	trace_size_limit: u64,
+	is_headless: bool,
+	force_dmg: bool
+}
+
+fn parse_args() -> Result<LaunchOptions, String> {
+	let mut options = LaunchOptions { trace_size_limit: DEFAULT_TRACE_SIZE_LIMIT, ..Default::default() };
+	let mut args = std::env::args().skip(1);
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--headless" => options.is_headless = true,
+			"--dmg" => options.force_dmg = true,
+			"--patch" => options.patch_path = Some(args.next().ok_or("--patch expects a file path")?.into()),
+			"--script" => options.script_path = Some(args.next().ok_or("--script expects a file path")?.into()),
+			"--frames" => options.frame_limit = Some(args.next().and_then(|nb| nb.parse().ok()).ok_or("--frames expects a number of frames")?),
+			"--trace" => options.trace_path = Some(args.next().ok_or("--trace expects a file path")?.into()),
+			"--trace-verbose" => options.trace_format = TraceFormat::Verbose,
+			"--trace-limit" => options.trace_size_limit = args.next().and_then(|nb| nb.parse::<u64>().ok()).ok_or("--trace-limit expects a size in MiB")? * 1024 * 1024,
+			"--gdb" => options.gdb_port = Some(args.next().and_then(|port| port.parse().ok()).ok_or("--gdb expects a TCP port")?),
+			_ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+			_ => options.rom_path = Some(arg.into())
+		}
+	}
+	Ok(options)
+}
+
+fn tick_instruction(cpu: &mut Cpu, ppu: &mut Ppu, timer: &mut Timer, memory_bus: &mut MemoryBus, framebuffer: &mut [u8], tracer: &mut Option<Tracer>) -> bool {
+	memory_bus.watchpoints.clear_hits();
+	memory_bus.watchpoints.is_armed = true;
+	let mut frame_completed = false;
+	let cpu_state = cpu.state();
+	let nb_cycles = cpu.tick(memory_bus, &mut |memory_bus| frame_completed |= tick_m_cycle(ppu, timer, memory_bus, framebuffer, cpu_state));
+	memory_bus.watchpoints.is_armed = false;
+	if let Some(Err(err)) = tracer.as_mut().map(|active_tracer| active_tracer.trace(cpu, memory_bus, nb_cycles)) {
+		println!("Trace stopped: {}", err);
+		*tracer = None;
+	}
+	frame_completed
+}
+
+/* Clocks the rest of the system through one CPU M-cycle, stretched for as long as a VRAM DMA transfer stalls the CPU.
+The timer, serial port and OAM DMA run at the CPU speed, while the PPU and VRAM DMA keep their pace in double speed */
+fn tick_m_cycle(ppu: &mut Ppu, timer: &mut Timer, memory_bus: &mut MemoryBus, framebuffer: &mut [u8], cpu_state: CpuState) -> bool {
+	let is_armed = std::mem::replace(&mut memory_bus.watchpoints.is_armed, false);		// DMA and interrupt requests aren't CPU accesses
+	let is_timer_frozen = matches!(cpu_state, CpuState::Stopped | CpuState::SwitchingSpeed(_));
+	let mut frame_completed = false;
+	loop {
+		let is_cpu_stalled = ppu.tick_vram_dma(memory_bus, matches!(cpu_state, CpuState::Halted | CpuState::Stopped | CpuState::SwitchingSpeed(_)));
+		ppu.tick_oam_dma(memory_bus);
+		for i in 0..M_CYCLE {
+			if !is_timer_frozen {
+				timer.tick(memory_bus);
+			}
+			if !memory_bus.is_double_speed || i.is_multiple_of(2) {
+				frame_completed |= ppu.tick(memory_bus, framebuffer);
+			}
+		}
+		if !is_cpu_stalled {break}
+	}
+	memory_bus.watchpoints.is_armed = is_armed;
+	frame_completed
+}
+
+fn bind_gdb_stub(port: Option<u16>) -> Option<GdbStub> {
+	match GdbStub::bind(port?) {
+		Ok(gdb_stub) => {
+			println!("GDB: listening on 127.0.0.1:{}", port?);
+			Some(gdb_stub)
+		}
+		Err(err) => {
+			println!("GDB: unable to listen on port {}: {}", port?, err);
+			None
+		}
+	}
+}
+
+fn run_headless(options: LaunchOptions) {
+	let Some(rom_path) = &options.rom_path else {
+		println!("Headless mode requires a ROM path");
+		return;
+	};
+	let mut script = match &options.script_path {
+		Some(script_path) => match ScriptHost::load_from_path(script_path) {
+			Ok(script) => Some(script),
+			Err(err) => {
+				println!("Unable to load script {}: {}", script_path.display(), err);
+				return;
+			}
+		},
+		None => None
+	};
+	if script.is_none() && options.frame_limit.is_none() && options.gdb_port.is_none() {
+		println!("Headless mode requires a script, a frame limit or a GDB port");
+		return;
+	}
+	let mut gdb_stub = bind_gdb_stub(options.gdb_port);
+	if options.gdb_port.is_some() && gdb_stub.is_none() {
+		return;
+	}
+	let mut memory_bus = match MemoryBus::new(rom_path.to_str(), options.patch_path.as_ref().and_then(|path| path.to_str()), options.force_dmg) {
+		Ok(memory_bus) => Some(memory_bus),
+		Err(err) => {
+			println!("{}", err);
+			return;
+		}
+	};
+	let mut cpu = Cpu::new();
+	let mut ppu = Ppu::new();
+	let mut timer = Timer::new();
+	let mut framebuffer = vec![0x00; VIEWPORT_PX_WIDTH * VIEWPORT_PX_HEIGHT * 4];
+	let mut tracer = match &options.trace_path {
+		Some(trace_path) => match Tracer::create(trace_path, options.trace_format, options.trace_size_limit) {
+			Ok(tracer) => Some(tracer),
+			Err(err) => {
+				println!("Unable to create trace {}: {}", trace_path.display(), err);
+				return;
+			}
+		},
+		None => None
+	};
+	let mut nb_frames = 0;
+	let mut frame_completed = true;
+	cpu.tick(memory_bus.as_mut().unwrap(), &mut |_| {});
+	loop {
+		if let Some(gdb) = &mut gdb_stub {
+			let was_connected = gdb.is_connected();
+			if frame_completed || gdb.is_halted || !was_connected {
+				gdb.poll(&mut cpu, memory_bus.as_mut().unwrap());
+			}
+			if was_connected && !gdb.is_connected() && script.is_none() && options.frame_limit.is_none() {
+				return;
+			}
+			if gdb.is_halted || !gdb.is_connected() {
+				std::thread::sleep(Duration::from_millis(1));
+				continue;
+			}
+		}
+		frame_completed = tick_instruction(&mut cpu, &mut ppu, &mut timer, memory_bus.as_mut().unwrap(), &mut framebuffer, &mut tracer);
+		if let Some(gdb) = &mut gdb_stub {
+			gdb.check_stop(&cpu, memory_bus.as_ref().unwrap());
+		}
+		if let Some(script) = &mut script {
+			let mut res = Ok(());
+			if script.has_breakpoint(cpu.next_op_address) {
+				res = script.on_breakpoint(&mut memory_bus, &mut cpu);
+			}
+			if frame_completed && res.is_ok() {
+				res = script.on_frame(&mut memory_bus, &mut cpu, &framebuffer);
+			}
+			if let Err(err) = res {
+				println!("Script error: {}", err);
+				return;
+			}
+			if script.is_exit_requested() || script.take_pause_request() {
+				return;
+			}
+		}
+		if frame_completed {
+			nb_frames += 1;
+			if options.frame_limit.is_some_and(|frame_limit| nb_frames >= frame_limit) {
+				return;
+			}
+		}
+	}
+}
+
 fn main() -> Result<(), Error> {
+	let options = match parse_args() {
+		Ok(options) => options,
+		Err(err) => {
+			println!("{}\nUsage: gbmu [--headless] [--dmg] [--patch <file>] [--script <file>] [--frames <nb>] [--gdb <port>] [--trace <file>] [--trace-verbose] [--trace-limit <MiB>] [rom]", err);
+			return Ok(());
+		}
+	};
+	if options.is_headless {
+		run_headless(options);
+		return Ok(());
+	}
 	let project_icon = include_bytes!("../assets/gbmu.bmp");
 	let program_icon_rgba = {
 		let image = image::load_from_memory_with_format(project_icon, image::ImageFormat::Bmp);
@@ -42,6 +241,7 @@ fn main() -> Result<(), Error> {
 	let mut windows = HashMap::new();
 	let mut pixels = HashMap::new();
 	let mut main_input = WinitInputHelper::new();
+	let mut rumble_gamepads = input::RumbleGamepads::new();
 	let window_icon = program_icon_rgba.clone().map(|image| Icon::from_rgba(image.0, image.1, image.2));
 	let window_icon = if let Some(Ok(icon)) = window_icon {Some(icon)} else {None};
 	windows.insert(WindowTypes::Main, 
@@ -78,6 +278,19 @@ fn main() -> Result<(), Error> {
 	let mut cpu = Cpu::new();
 	let mut ppu = Ppu::new();
 	let mut timer = Timer::new();
+	let mut movie: Option<Movie> = None;
+	let mut frame_input_latched = false;
+	let mut script: Option<ScriptHost> = None;
+	let mut tracer: Option<Tracer> = None;
+	let mut gdb_stub = bind_gdb_stub(options.gdb_port);
+	let trace_size_limit = options.trace_size_limit;
+	framework.gui.is_trace_verbose = options.trace_format == TraceFormat::Verbose;
+	framework.gui.trace_requested = options.trace_path;
+	framework.gui.opened_file = options.rom_path;
+	framework.gui.opened_patch = options.patch_path;
+	framework.gui.force_dmg = options.force_dmg;
+	framework.gui.script_requested = options.script_path;
+	framework.gui.symbols_requested = framework.gui.opened_file.as_ref().map(|path| path.with_extension("sym")).filter(|path| path.exists());
 
 	let mut next_redraw = Instant::now() + Duration::from_micros(16665);
 	let mut frame_completed = false;
@@ -121,7 +334,7 @@ fn main() -> Result<(), Error> {
 							render_result = pixels[&win_id].render();
 						}
 						WindowTypes::Main => {
-							framework.prepare(&windows[&WindowTypes::Main], &cpu);
+							framework.prepare(&windows[&WindowTypes::Main], &cpu, memory_bus.as_ref());
 							render_result = pixels[&win_id].render_with(|encoder, render_target, context| {
 								context.scaling_renderer.render(encoder, render_target);
 								framework.render(encoder, render_target, context);
@@ -145,6 +358,12 @@ fn main() -> Result<(), Error> {
                 *control_flow = ControlFlow::Exit;
                 return;
             }
+			if main_input.key_pressed(VirtualKeyCode::F9) {
+				match &mut tracer {
+					Some(active_tracer) => active_tracer.is_enabled = !active_tracer.is_enabled,
+					None => framework.gui.trace_requested = framework.gui.opened_file.as_ref().map(|path| path.with_extension("trace"))
+				}
+			}
 			if let Some(size) = main_input.window_resized() {
                 if let Err(err) = pixels.get_mut(&windows[&WindowTypes::Main].id()).unwrap().resize_surface(size.width, size.height) {
                     println!("pixels.resize_surface: {}", err);
@@ -188,38 +407,155 @@ fn main() -> Result<(), Error> {
 					Pixels::new(TILESET_VIEWER_PX_WIDTH as u32, TILESET_VIEWER_PX_HEIGHT as u32, surface_texture).unwrap()
 				});
 			}
+			if framework.gui.movie_stop_requested {
+				if movie.take().is_some() {
+					framework.gui.movie_status = Some("Movie stopped".to_string());
+				}
+				framework.gui.movie_stop_requested = false;
+			}
+			if let Some((path, mode)) = framework.gui.movie_requested.take() {
+				let new_movie = if mode == MovieMode::Recording {
+					Ok(Movie::new_recording(path, framework.gui.force_dmg))
+				} else {
+					Movie::load_from_path(path, mode)
+				};
+				match new_movie {
+					Ok(new_movie) => {
+						framework.gui.force_dmg = new_movie.header.force_dmg;
+						movie = Some(new_movie);
+						framework.gui.reset_requested = true;
+					}
+					Err(err) => {framework.gui.movie_status = Some(format!("Unable to load movie: {}", err))}
+				}
+			}
+			if let Some(path) = framework.gui.script_requested.take() {
+				match ScriptHost::load_from_path(&path) {
+					Ok(new_script) => {
+						script = Some(new_script);
+						framework.gui.script_status = Some(format!("Script {} loaded", path.display()));
+					}
+					Err(err) => {framework.gui.script_status = Some(format!("Unable to load script: {}", err))}
+				}
+			}
+			if let Some(path) = framework.gui.symbols_requested.take() {
+				match Symbols::load_from_path(&path) {
+					Ok(symbols) => {framework.gui.symbols = Some(symbols)}
+					Err(err) => {println!("Unable to load symbols {}: {}", path.display(), err)}
+				}
+			}
+			if let Some(path) = framework.gui.trace_requested.take() {
+				match Tracer::create(&path, if framework.gui.is_trace_verbose {TraceFormat::Verbose} else {TraceFormat::Doctor}, trace_size_limit) {
+					Ok(new_tracer) => {tracer = Some(new_tracer)}
+					Err(err) => {println!("Unable to create trace {}: {}", path.display(), err)}
+				}
+			}
+			if framework.gui.trace_stop_requested {
+				tracer = None;
+				framework.gui.trace_stop_requested = false;
+			}
+			if framework.gui.script_unload_requested {
+				script = None;
+				framework.gui.script_status = None;
+				framework.gui.script_unload_requested = false;
+			}
 			if framework.gui.reset_requested {
 				memory_bus = None;
 				cpu = Cpu::new();
 				ppu = Ppu::new();
 				timer = Timer::new();
+				frame_input_latched = false;
+				rumble_gamepads.set_rumble(false);
+				framework.gui.is_rumbling = false;
 				framework.gui.reset_requested = false;
 			}
 			if let None = &memory_bus {
-				if let Some(path) = &framework.gui.opened_file {
-					memory_bus = Some(MemoryBus::new(path.to_str(), framework.gui.force_dmg));
-					cpu.tick(memory_bus.as_mut().unwrap());
+				let loaded_memory_bus = framework.gui.opened_file.as_ref().map(|path| MemoryBus::new(path.to_str(), framework.gui.opened_patch.as_ref().and_then(|path| path.to_str()), framework.gui.force_dmg));
+				if let Some(Err(err)) = loaded_memory_bus {
+					framework.gui.rom_status = Some(err);
+					framework.gui.opened_file = None;
+					framework.gui.opened_patch = None;
+				} else if let Some(Ok(loaded_memory_bus)) = loaded_memory_bus {
+					memory_bus = Some(loaded_memory_bus);
+					framework.gui.rom_status = None;
+					if let Some(Err(err)) = movie.as_mut().map(|movie| movie.bind(memory_bus.as_mut().unwrap())) {
+						framework.gui.movie_status = Some(err);
+						movie = None;
+					}
+					if let Some(gdb) = &gdb_stub {
+						gdb.bind_memory_bus(memory_bus.as_mut().unwrap());
+					}
+					framework.gui.debugger.bind_memory_bus(memory_bus.as_mut().unwrap());
+					cpu.tick(memory_bus.as_mut().unwrap(), &mut |_| {});
 				}
 			} else {
-				while !frame_completed && !framework.gui.is_execution_paused && (!framework.gui.debugger_window_open || framework.gui.is_debugger_stepping_instr || framework.gui.is_debugger_stepping_frame ){
+				if let Some(gdb) = &mut gdb_stub {
+					gdb.poll(&mut cpu, memory_bus.as_mut().unwrap());
+				}
+				for (region, offset, data) in framework.gui.memory_pokes.drain(..) {
+					memory_bus.as_mut().unwrap().poke(region, offset, data);
+				}
+				if framework.gui.debugger.are_watchpoints_updated {
+					framework.gui.debugger.bind_memory_bus(memory_bus.as_mut().unwrap());
+				}
+				while !frame_completed && !framework.gui.is_execution_paused && !gdb_stub.as_ref().is_some_and(|gdb| gdb.is_halted) && (!framework.gui.debugger_window_open || framework.gui.is_debugger_running || framework.gui.is_debugger_stepping_instr || framework.gui.is_debugger_stepping_frame ){
 					framework.gui.is_debugger_stepping_instr = false;
-					let nb_cycles = cpu.tick(memory_bus.as_mut().unwrap());
-					input::tick(memory_bus.as_mut().unwrap(), &main_input);
-					let mut i = 0;
-					let mut ppu_is_halting_cpu = false;
-					while i < nb_cycles || ppu_is_halting_cpu {
-						timer.tick(memory_bus.as_mut().unwrap());
-						let res = if !memory_bus.as_ref().unwrap().is_double_speed || i % 2 == 0 {
-													ppu.tick(memory_bus.as_mut().unwrap(), pixels.get_mut(&windows[&WindowTypes::Main].id()).unwrap().frame_mut())
-												} else {(false, ppu_is_halting_cpu)};
-						frame_completed |= res.0;
-						ppu_is_halting_cpu = res.1;
-						i += 1;
+					if !frame_input_latched {
+						input::tick(memory_bus.as_mut().unwrap(), &main_input, movie.as_mut());
+						frame_input_latched = true;
+					}
+					frame_completed |= tick_instruction(&mut cpu, &mut ppu, &mut timer, memory_bus.as_mut().unwrap(), pixels.get_mut(&windows[&WindowTypes::Main].id()).unwrap().frame_mut(), &mut tracer);
+					if let Some(gdb) = &mut gdb_stub {
+						gdb.check_stop(&cpu, memory_bus.as_ref().unwrap());
+					}
+					if framework.gui.debugger.check(&cpu, memory_bus.as_ref().unwrap()).is_some() {
+						framework.gui.debugger_window_open = true;
+						framework.gui.is_debugger_running = false;
+						framework.gui.is_debugger_stepping_frame = false;
+					}
+					if frame_completed {
+						frame_input_latched = false;
+						if let Some(movie) = &mut movie {
+							movie.end_frame(memory_bus.as_mut().unwrap(), pixels[&windows[&WindowTypes::Main].id()].frame());
+						}
+					}
+					if let Some(active_script) = &mut script {
+						let mut res = Ok(());
+						if active_script.has_breakpoint(cpu.next_op_address) {
+							res = active_script.on_breakpoint(&mut memory_bus, &mut cpu);
+						}
+						if frame_completed && res.is_ok() {
+							res = active_script.on_frame(&mut memory_bus, &mut cpu, pixels[&windows[&WindowTypes::Main].id()].frame());
+						}
+						if active_script.take_pause_request() {
+							framework.gui.is_execution_paused = true;
+						}
+						if let Err(err) = res {
+							framework.gui.script_status = Some(format!("Script error: {}", err));
+							framework.gui.is_execution_paused = true;
+							script = None;
+						} else if active_script.is_exit_requested() {
+							framework.gui.script_status = Some("Script exited".to_string());
+							script = None;
+						}
 					}
 				}
 				if frame_completed {
 					framework.gui.is_debugger_stepping_frame = false;
 				}
+				input::tick_rumble(memory_bus.as_mut().unwrap(), &mut rumble_gamepads, &mut framework.gui.is_rumbling);
+				framework.gui.trace_status = tracer.as_ref().map(Tracer::status);
+				framework.gui.cpu_status = cpu.locked_at().map(|(address, opcode)| format!("CPU locked up: illegal opcode 0x{:02X} at 0x{:04X}", opcode, address));
+				if let Some(active_movie) = &movie {
+					framework.gui.movie_status = Some(active_movie.status());
+					if let Some(frame) = active_movie.desync_frame {
+						framework.gui.movie_status = Some(format!("Movie desynced at frame {}, verification stopped", frame));
+						framework.gui.is_execution_paused = true;
+						movie = None;
+					} else if active_movie.is_finished {
+						framework.gui.movie_status = Some(if active_movie.mode == MovieMode::Verification {"Movie verified, no desync found"} else {"Movie playback finished"}.to_string());
+						movie = None;
+					}
+				}
 			}
 			if Instant::now() >= next_redraw {
 				windows[&WindowTypes::Main].request_redraw();
@@ -233,3 +569,104 @@ fn main() -> Result<(), Error> {
 		}
 	});
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct System {
+		ppu: Ppu,
+		timer: Timer,
+		memory_bus: MemoryBus,
+		framebuffer: Vec<u8>
+	}
+
+	impl System {
+		fn new(is_double_speed: bool) -> Self {
+			let mut memory_bus = MemoryBus::new(None, None, false).unwrap();
+			memory_bus.is_cgb = true;
+			memory_bus.is_double_speed = is_double_speed;
+			memory_bus.timer_memory.system_counter = 0x0000;
+			System { ppu: Ppu::new(), timer: Timer::new(), memory_bus, framebuffer: vec![0x00; VIEWPORT_PX_WIDTH * VIEWPORT_PX_HEIGHT * 4] }
+		}
+		fn tick_m_cycle(&mut self) {
+			tick_m_cycle(&mut self.ppu, &mut self.timer, &mut self.memory_bus, &mut self.framebuffer, CpuState::Running);
+		}
+		fn m_cycles_until(&mut self, is_done: impl Fn(&MemoryBus) -> bool) -> usize {
+			let mut nb_m_cycles = 0;
+			while !is_done(&self.memory_bus) {
+				self.tick_m_cycle();
+				nb_m_cycles += 1;
+				assert!(nb_m_cycles < 0x10000, "never done");
+			}
+			nb_m_cycles
+		}
+	}
+
+	/* M-cycles taken in single then double speed */
+	fn m_cycles_in_both_speeds(setup: impl Fn(&mut MemoryBus), is_done: impl Fn(&MemoryBus) -> bool) -> (usize, usize) {
+		let [nb_single_speed_m_cycles, nb_double_speed_m_cycles] = [false, true].map(|is_double_speed| {
+			let mut system = System::new(is_double_speed);
+			setup(&mut system.memory_bus);
+			system.m_cycles_until(&is_done)
+		});
+		(nb_single_speed_m_cycles, nb_double_speed_m_cycles)
+	}
+
+	#[test]
+	fn test_double_speed_timer() {
+		assert_eq!(m_cycles_in_both_speeds(|_| {}, |memory_bus| memory_bus.read_byte(0xFF04) == 0x01), (64, 64));
+		let tima_setup = |memory_bus: &mut MemoryBus| memory_bus.write_byte(0xFF07, 0x05);
+		assert_eq!(m_cycles_in_both_speeds(tima_setup, |memory_bus| memory_bus.read_byte(0xFF05) == 0x01), (4, 4));
+	}
+
+	#[test]
+	fn test_double_speed_serial() {
+		let is_transfer_done = |memory_bus: &MemoryBus| memory_bus.read_byte(0xFF0F) & (1 << 3) != 0;
+		assert_eq!(m_cycles_in_both_speeds(|memory_bus| memory_bus.write_byte(0xFF02, 0x81), is_transfer_done), (1024, 1024));
+		assert_eq!(m_cycles_in_both_speeds(|memory_bus| memory_bus.write_byte(0xFF02, 0x83), is_transfer_done), (32, 32));
+	}
+
+	#[test]
+	fn test_double_speed_oam_dma() {
+		for is_double_speed in [false, true] {
+			let mut system = System::new(is_double_speed);
+			system.memory_bus.write_byte(0xC09F, 0x42);
+			system.memory_bus.write_byte(0xFF46, 0xC0);
+			let nb_setup_m_cycles = system.m_cycles_until(|memory_bus| memory_bus.ppu_memory.oam_dma_is_active);
+			let nb_transfer_m_cycles = system.m_cycles_until(|memory_bus| !memory_bus.ppu_memory.oam_dma_is_active);
+			assert_eq!((nb_setup_m_cycles, nb_transfer_m_cycles), (2, 160));
+			assert_eq!(system.memory_bus.read_byte(0xFE9F), 0x42);
+		}
+	}
+
+	#[test]
+	fn test_double_speed_ppu() {
+		let lcd_setup = |memory_bus: &mut MemoryBus| memory_bus.write_byte(0xFF40, 0x80);
+		let (nb_single_speed_m_cycles, nb_double_speed_m_cycles) = m_cycles_in_both_speeds(lcd_setup, |memory_bus| memory_bus.read_byte(0xFF44) == 0x02);
+		for (is_double_speed, nb_m_cycles) in [(false, nb_single_speed_m_cycles), (true, nb_double_speed_m_cycles)] {
+			let mut system = System::new(is_double_speed);
+			lcd_setup(&mut system.memory_bus);
+			let nb_m_cycles_to_line_1 = system.m_cycles_until(|memory_bus| memory_bus.read_byte(0xFF44) == 0x01);
+			assert_eq!(nb_m_cycles - nb_m_cycles_to_line_1, if is_double_speed {228} else {114});
+		}
+	}
+
+	#[test]
+	fn test_double_speed_vram_dma() {
+		for is_double_speed in [false, true] {
+			let mut system = System::new(is_double_speed);
+			system.memory_bus.write_byte(0xC000, 0x42);
+			system.memory_bus.write_byte(0xFF51, 0xC0);
+			system.memory_bus.write_byte(0xFF52, 0x00);
+			system.memory_bus.write_byte(0xFF53, 0x00);
+			system.memory_bus.write_byte(0xFF54, 0x00);
+			system.memory_bus.write_byte(0xFF55, 0x01);			// 2 blocks of 0x10 bytes, 8 M-cycles each in single speed, 16 in double speed
+			system.tick_m_cycle();								// Stalled until the end of the transfer
+			assert!(!system.memory_bus.ppu_memory.vram_dma_is_active);
+			let nb_block_m_cycles = if is_double_speed {16} else {8};
+			assert_eq!(system.memory_bus.timer_memory.system_counter as usize, (1 + 2 * nb_block_m_cycles) * M_CYCLE as usize);
+			assert_eq!(system.memory_bus.read_byte(0x8000), 0x42);
+		}
+	}
+}
@@ -1,11 +1,41 @@
 pub mod ppu_memory;
 pub mod timer_memory;
 pub mod input_memory;
+pub mod serial_memory;
+pub mod watchpoints;
+pub mod memory_region;
 mod cartridge;
 
-use self::{ppu_memory::PPUMemory, cartridge::Cartridge, timer_memory::TimerMemory, input_memory::InputMemory};
+use self::{ppu_memory::{PPUMemory, OamCorruption}, cartridge::Cartridge, timer_memory::TimerMemory, input_memory::InputMemory, serial_memory::SerialMemory, watchpoints::Watchpoints, memory_region::MemoryRegion};
 use std::fmt::Debug;
 
+/* Bits of the 0xFF00 - 0xFF7F I/O registers always reading 1: unused bits, and whole write-only or unmapped registers */
+const DMG_IO_READ_MASKS: [u8; 0x80] = [
+	0xC0, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,		// 0xFF00: P1, serial, timer, IF
+	0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,		// 0xFF10: APU channels 1 to 3
+	0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,		// 0xFF20: APU channel 4 and control
+	0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,		// 0xFF30: wave RAM
+	0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,		// 0xFF40: PPU
+	0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+	0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+	0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF
+];
+const CGB_IO_READ_MASKS: [u8; 0x80] = [
+	0xC0, 0x00, 0x7C, 0xFF, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+	0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+	0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+	0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x7E, 0xFF, 0xFE,		// 0xFF40: PPU, KEY0, KEY1, VBK
+	0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x3C, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,		// 0xFF50: boot ROM, VRAM DMA, RP
+	0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x40, 0x00, 0x40, 0x00, 0xFE, 0xFF, 0xFF, 0xFF,		// 0xFF60: CGB palettes, OPRI
+	0xF8, 0xFF, 0x00, 0x00, 0x00, 0x8F, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF		// 0xFF70: SVBK, undocumented registers
+];
+
+#[derive(PartialEq)]
+enum DataBus {
+	Cartridge, Video, Wram
+}
+
 pub struct MemoryBus {
 	bootrom_1: [u8; 0x100],			// 0x0000 - 0x00FF
 	bootrom_2: [u8; 0x700],			// 0x0200 - 0x08FF
@@ -13,28 +43,35 @@ pub struct MemoryBus {
 	pub ppu_memory: PPUMemory,		// 0x8000 - 0x9FFF + 0xFE00 - 0xFE9F + 0xFF40 - 0xFF45 + 0xFF47 - 0xFF4B + 0xFF68 - 0xFF6B
 	intern_ram: [u8; 0x1000],		// 0xC000 - 0xCFFF + echo at 0xE000 - 0xEFFF
 	intern_ram2: [[u8; 0x1000]; 7],		// 0xD000 - 0xDFFF + echo at 0xF000 - 0xFDFF
-	/* unmapped memory */			// 0xFEA0 - 0xFEFF => Read returns 0, write does nothing
+	/* unmapped memory */			// 0xFEA0 - 0xFEFF => Read returns 0 on DMG and the upper address nibble twice on CGB (0xFF while OAM is blocked), write does nothing
 	pub input_memory: InputMemory,	// 0xFF00
+	pub serial_memory: SerialMemory,	// 0xFF01 - 0xFF02
 	pub timer_memory: TimerMemory,	// 0xFF04 - 0xFF07
 	pub is_double_speed: bool,		// 0xFF4D & 0x80
 	pub speed_chg_scheduled: bool,	// 0xFF4D & 0x01
+	key0_reg: u8,					// 0xFF4C
 	vbk_reg: bool,					// 0xFF4F
 	bootrom_reg: u8,				// 0xFF50
+	opri_reg: u8,					// 0xFF6C
 	svbk_reg: u8,					// 0xFF70
+	undocumented_regs: [u8; 4],		// 0xFF72 - 0xFF75
 	io_regis: [u8; 0x007F],			// 0xFF01 - 0xFF7F
 	high_intern_ram: [u8; 0x007F],	// 0xFF80 - 0xFFFE
 	interrupt_enable: u8,			// 0xFFFF
 
-	pub is_cgb: bool
+	pub is_cgb: bool,				// CGB mode, left for DMG compatibility mode by the boot ROM on DMG cartridges
+	pub is_cgb_hardware: bool,
+	pub watchpoints: Watchpoints
 }
 
 impl MemoryBus {
-	pub fn new(rom_path: Option<&str>, force_dmg: bool) -> Self {
+	pub fn new(rom_path: Option<&str>, patch_path: Option<&str>, force_dmg: bool) -> Result<Self, String> {
 		let mut res = MemoryBus {
 			ppu_memory: PPUMemory::new(),
 			timer_memory: TimerMemory::new(),
 			input_memory: InputMemory::new(),
-			cartridge: Cartridge::new(rom_path),
+			serial_memory: SerialMemory::new(),
+			cartridge: Cartridge::new(rom_path, patch_path)?,
 			bootrom_1: [0; 0x100],
 			bootrom_2: [0; 0x700],
 			intern_ram: [0; 0x1000],
@@ -42,22 +79,25 @@ impl MemoryBus {
 			io_regis: [0; 0x007F],
 			is_double_speed: false,
 			speed_chg_scheduled: false,
+			key0_reg: 0x00,
 			vbk_reg: false,
 			bootrom_reg: 0x01,
+			opri_reg: 0x00,
 			svbk_reg: 0x01,
+			undocumented_regs: [0x00; 4],
 			high_intern_ram: [0; 0x007F],
 			interrupt_enable: 0,
-			is_cgb: false
+			is_cgb: !force_dmg,
+			is_cgb_hardware: !force_dmg,
+			watchpoints: Watchpoints::new()
 		};
-		if !force_dmg && res.cartridge.is_cgb {
-			res.is_cgb = true;
-		}
-		if !res.is_cgb {
+		res.ppu_memory.is_obj_priority_by_coordinate = !res.is_cgb_hardware;
+		if !res.is_cgb_hardware {
 			res.load_dmg_bootrom();
 		}  else {
 		 	res.load_cgb_bootrom();
 		}
-		res
+		Ok(res)
 	}
 	fn load_dmg_bootrom(&mut self) {
 		let dmg_bootrom:[u8; 0x100] = [
@@ -262,7 +302,97 @@ impl MemoryBus {
 		self.bootrom_2.clone_from_slice(&cgb_bootrom_2);
 		self.bootrom_reg = 0x00;
 	}
+	pub fn is_bootrom_mapped(&self) -> bool {
+		self.bootrom_reg == 0x00
+	}
+	/* In DMG compatibility mode, the CGB palettes stay writable by the boot ROM only, for it to colorize the game */
+	fn is_cgb_palette_access_enabled(&self) -> bool {
+		self.is_cgb || (self.is_cgb_hardware && self.is_bootrom_mapped())
+	}
+	/* Backing stores present on this console and cartridge */
+	pub fn regions(&self) -> Vec<MemoryRegion> {
+		let mut regions = vec![MemoryRegion::Bus];
+		regions.extend((0..self.cartridge.rom_banks().len()).map(MemoryRegion::RomBank));
+		regions.extend((0..self.cartridge.ram_banks().len()).map(MemoryRegion::RamBank));
+		regions.extend((0..if self.is_cgb_hardware {2} else {1}).map(MemoryRegion::Vram));
+		regions.extend((0..if self.is_cgb_hardware {8} else {2}).map(MemoryRegion::Wram));
+		regions.extend([MemoryRegion::Oam, MemoryRegion::Hram]);
+		regions
+	}
+	/* Debugger accesses: backing stores are read and written directly, without side effects besides the write itself */
+	pub fn peek(&self, region: MemoryRegion, offset: usize) -> u8 {
+		match region {
+			MemoryRegion::Bus			=> self.read_byte(offset as u16),
+			MemoryRegion::RomBank(bank)	=> self.cartridge.rom_banks()[bank][offset],
+			MemoryRegion::RamBank(bank)	=> self.cartridge.ram_banks()[bank][offset],
+			MemoryRegion::Vram(bank)	=> self.ppu_memory.peek(0x8000 + offset, bank == 1),
+			MemoryRegion::Wram(0)		=> self.intern_ram[offset],
+			MemoryRegion::Wram(bank)	=> self.intern_ram2[bank - 1][offset],
+			MemoryRegion::Oam			=> self.ppu_memory.peek(0xFE00 + offset, false),
+			MemoryRegion::Hram			=> self.high_intern_ram[offset]
+		}
+	}
+	pub fn poke(&mut self, region: MemoryRegion, offset: usize, data: u8) {
+		match region {
+			/* Writes to ROM would otherwise be taken as mapper commands; the boot ROM overlay stays untouched */
+			MemoryRegion::Bus if offset < 0x8000 => {
+				let bank = self.cartridge.rom_bank_at(offset as u16);
+				self.cartridge.rom_banks_mut()[bank][offset % 0x4000] = data;
+			}
+			MemoryRegion::Bus			=> self.write_byte(offset as u16, data),
+			MemoryRegion::RomBank(bank)	=> self.cartridge.rom_banks_mut()[bank][offset] = data,
+			MemoryRegion::RamBank(bank)	=> self.cartridge.ram_banks_mut()[bank][offset] = data,
+			MemoryRegion::Vram(bank)	=> self.ppu_memory.poke(0x8000 + offset, data, bank == 1),
+			MemoryRegion::Wram(0)		=> self.intern_ram[offset] = data,
+			MemoryRegion::Wram(bank)	=> self.intern_ram2[bank - 1][offset] = data,
+			MemoryRegion::Oam			=> self.ppu_memory.poke(0xFE00 + offset, data, false),
+			MemoryRegion::Hram			=> self.high_intern_ram[offset] = data
+		}
+	}
+	/* Bus an address is reached through, the CPU internal one (I/O and HRAM) excluded; on CGB WRAM has its own */
+	fn data_bus(&self, address: u16) -> Option<DataBus> {
+		match address {
+			0x0000..=0x7FFF | 0xA000..=0xBFFF	=> Some(DataBus::Cartridge),
+			0x8000..=0x9FFF						=> Some(DataBus::Video),
+			0xC000..=0xFDFF						=> Some(if self.is_cgb_hardware {DataBus::Wram} else {DataBus::Cartridge}),
+			_									=> None
+		}
+	}
+	/* Sources above 0xDFFF are mirrors of WRAM; VRAM and OAM locks don't apply to the DMA */
+	pub fn oam_dma_read(&self, address: u16) -> u8 {
+		let address = if address >= 0xE000 {address - 0x2000} else {address};
+		match address {
+			0x8000..=0x9FFF	=> self.ppu_memory.peek(address as usize, self.is_cgb && self.vbk_reg),
+			_				=> self.read_byte(address)
+		}
+	}
+	/* Only ROM, cartridge RAM and WRAM can be VRAM DMA sources */
+	pub fn vram_dma_read(&self, address: u16) -> u8 {
+		match address {
+			0x0000..=0x7FFF | 0xA000..=0xDFFF	=> self.read_byte(address),
+			_									=> 0xFF
+		}
+	}
+	pub fn vram_dma_write(&mut self, address: u16, data: u8) {
+		self.ppu_memory.poke(address as usize, data, self.vbk_reg);
+	}
+	/* While the OAM DMA runs, the CPU gets the byte being transferred from the bus it uses, and 0xFF from OAM; its writes there get lost */
+	pub fn oam_dma_conflict(&self, address: u16) -> Option<u8> {
+		if !self.ppu_memory.oam_dma_is_active {return None}
+		match address {
+			0xFE00..=0xFEFF	=> Some(0xFF),
+			_ if self.data_bus(address).is_some() && self.data_bus(address) == self.data_bus(self.ppu_memory.oam_dma_src_address) => Some(self.ppu_memory.oam_dma_bus_data),
+			_				=> None
+		}
+	}
+	/* DMG only: an 0xFE00-0xFEFF address on the CPU bus during the OAM search corrupts the row the PPU reads */
+	pub fn trigger_oam_bug(&mut self, address: u16, corruption: OamCorruption) {
+		if !self.is_cgb_hardware && (0xFE00..=0xFEFF).contains(&address) {
+			self.ppu_memory.corrupt_oam(corruption);
+		}
+	}
 	pub fn read_byte(&self, address: u16) -> u8 {
+		self.watchpoints.check(address, false);
 		match address {
 			0x0000..=0x00FF	=> if self.bootrom_reg == 0x00
 									 {self.bootrom_1[(address - 0x0000) as usize]}
@@ -281,10 +411,21 @@ impl MemoryBus {
 			0xE000..=0xEFFF	=>		  self.intern_ram[(address - 0xE000) as usize],
 			0xF000..=0xFDFF	=>		  self.intern_ram2[self.svbk_reg as usize - 1][(address - 0xF000) as usize],
 			0xFE00..=0xFE9F	=>		  self.ppu_memory.read(address as usize, false),
-			0xFEA0..=0xFEFF	=> 0,
+			0xFEA0..=0xFEFF	=>	if self.ppu_memory.is_oam_locked || self.ppu_memory.oam_dma_is_active {0xFF}
+								else if self.is_cgb_hardware {(address as u8 & 0xF0) | (address as u8 >> 4)}		// CGB revision E
+								else {0x00},
+			0xFF00..=0xFF7F	=>		self.read_io(address) | if self.is_cgb_hardware {CGB_IO_READ_MASKS} else {DMG_IO_READ_MASKS}[(address - 0xFF00) as usize],
+			0xFF80..=0xFFFE	=> self.high_intern_ram[(address - 0xFF80) as usize],
+			0xFFFF			=> self.interrupt_enable
+		}
+	}
+	/* Registers read as they were written when readable at all, the always set bits being ORed by the caller */
+	fn read_io(&self, address: u16) -> u8 {
+		match address {
 			0xFF00			=>		self.input_memory.read(),
+			0xFF01 | 0xFF02	=>		self.serial_memory.read(address as usize, self.is_cgb),
 			0xFF04..=0xFF07 =>		self.timer_memory.read(address as usize),
-			0xFF26			=>		0x00,
+			0xFF26			=>		self.io_regis[(0xFF26 - 0xFF01) as usize] & 0x80,		// No channel ever playing
 			0xFF40 | 0xFF47 =>		  self.ppu_memory.read(address as usize, false),
 			0xFF41			=>		self.ppu_memory.read(address as usize, false),
 			0xFF42			=>		  self.ppu_memory.scy_ram,
@@ -295,22 +436,25 @@ impl MemoryBus {
 			0xFF48 | 0xFF49 =>		self.ppu_memory.read(address as usize, false),
 			0xFF4A			=>		  self.ppu_memory.wy_ram,
 			0xFF4B			=>		  self.ppu_memory.wx_ram,
-			0xFF4D			=>		(self.is_double_speed as u8) << 7 | self.speed_chg_scheduled as u8,
-			0xFF4F			=>		  0xFE | self.vbk_reg as u8,
+			0xFF4C if self.is_cgb_hardware && self.is_bootrom_mapped() => self.key0_reg,
+			0xFF4D if self.is_cgb	=>	(self.is_double_speed as u8) << 7 | self.speed_chg_scheduled as u8,
+			0xFF4F if self.is_cgb	=>	self.vbk_reg as u8,
 			0xFF50			=>		  self.bootrom_reg,
-			0xFF51			=>		((self.ppu_memory.vram_dma_src_regs & 0xFF00) >> 8) as u8,
-			0xFF52			=>		(self.ppu_memory.vram_dma_src_regs & 0x00F0) as u8,
-			0xFF53			=>		((self.ppu_memory.vram_dma_dst_regs & 0x1F00) >> 8) as u8,
-			0xFF54			=>		(self.ppu_memory.vram_dma_dst_regs & 0x00F0) as u8,
-			0xFF55			=>		(!self.ppu_memory.vram_dma_is_active as u8) << 7 | self.ppu_memory.vram_dma_stat & 0x7F,
-			0xFF68..=0xFF6B =>		self.ppu_memory.read(address as usize, false),
-			0xFF70			=>		self.svbk_reg,
+			0xFF55 if self.is_cgb	=>	(!self.ppu_memory.vram_dma_is_active as u8) << 7 | self.ppu_memory.vram_dma_stat & 0x7F,
+			0xFF68..=0xFF6B if self.is_cgb_palette_access_enabled() => self.ppu_memory.read(address as usize, false),
+			0xFF6C if self.is_cgb	=>	self.opri_reg,
+			0xFF70 if self.is_cgb	=>	self.svbk_reg,
+			0xFF72 | 0xFF73	=>		self.undocumented_regs[(address - 0xFF72) as usize],
+			0xFF74 if self.is_cgb	=>	self.undocumented_regs[2],
+			0xFF75			=>		self.undocumented_regs[3],
+			0xFF76 | 0xFF77	=>		0x00,		// PCM amplitudes of the APU channels, all silent
+			0xFF4C | 0xFF4D | 0xFF4F | 0xFF55 | 0xFF68..=0xFF6C | 0xFF70 | 0xFF74 => 0xFF,		// CGB registers out of CGB mode
 			0xFF01..=0xFF7F	=>		  self.io_regis[(address - 0xFF01) as usize],
-			0xFF80..=0xFFFE	=> self.high_intern_ram[(address - 0xFF80) as usize],
-			0xFFFF			=> self.interrupt_enable
+			_				=> 0xFF
 		}
 	}
 	pub fn write_byte(&mut self, address: u16, data: u8) {
+		self.watchpoints.check(address, true);
 		match address {
 			0x0000..=0x00FF	=> if self.bootrom_reg == 0x00 {}
 							   else   {self.cartridge.write(address as usize, data)},
@@ -327,18 +471,23 @@ impl MemoryBus {
 			0xFE00..=0xFE9F	=>		   self.ppu_memory.write(address as usize, data, false),
 			0xFEA0..=0xFEFF	=> {},
 			0xFF00			=>		{self.input_memory.write(data)}
+			0xFF01 | 0xFF02	=>		{self.serial_memory.write(address as usize, data, self.is_cgb)}
 			0xFF04..=0xFF07 =>		{self.timer_memory.write(address as usize, data)},
 			0xFF40 | 0xFF47 =>		   self.ppu_memory.write(address as usize, data, false),
-			0xFF41			=>		self.ppu_memory.write(address as usize, data, false),
+			0xFF41			=>		{self.ppu_memory.is_stat_write_glitch_pending = !self.is_cgb_hardware; self.ppu_memory.write(address as usize, data, false)},
 			0xFF42			=>		  {self.ppu_memory.scy_ram = data},
 			0xFF43			=>		  {self.ppu_memory.scx_ram = data},
 			0xFF44			=>		  {},
 			0xFF45			=>		  {self.ppu_memory.lyc_ram = data},
-			0xFF46			=>		  {self.ppu_memory.oam_dma_reg = data; self.ppu_memory.oam_dma_is_active = true;},
+			0xFF46			=>		  {self.ppu_memory.oam_dma_reg = data; self.ppu_memory.oam_dma_is_requested = true;},
 			0xFF48 | 0xFF49 =>		self.ppu_memory.write(address as usize, data, false),
 			0xFF4A			=>		  {self.ppu_memory.wy_ram = data},
 			0xFF4B			=>		  {self.ppu_memory.wx_ram = data},
-			0xFF4D			=>		{self.speed_chg_scheduled = (data & 0x01) != 0}
+			0xFF4C			=>	if self.is_cgb_hardware && self.is_bootrom_mapped() {
+										self.key0_reg = data;
+										self.is_cgb = data & 0x0C == 0;
+									}
+			0xFF4D			=>		{self.speed_chg_scheduled = self.is_cgb && (data & 0x01) != 0}
 			0xFF4F			=>		  {self.vbk_reg = (data & 0x01) != 0}
 			0xFF50			=>		  {self.bootrom_reg = data},
 			0xFF51			=>		{self.ppu_memory.vram_dma_src_regs = (data as u16) << 8 | self.ppu_memory.vram_dma_src_regs & 0x00F0}
@@ -351,8 +500,20 @@ impl MemoryBus {
 										self.ppu_memory.vram_dma_is_hblank_mode = data & 0x80 != 0;
 										self.ppu_memory.vram_dma_stat = data & 0x7F;
 										self.ppu_memory.vram_dma_is_active = true;
+										self.ppu_memory.is_hblank_dma_pending = self.ppu_memory.ppu_mode_id == 0;		// Started in HBlank or with the LCD off, a block goes right away
+									}
+			0xFF68..=0xFF6B =>	if self.is_cgb_palette_access_enabled() {self.ppu_memory.write(address as usize, data, false)},
+			0xFF6C			=>	if self.is_cgb_hardware && self.is_bootrom_mapped() {
+										self.opri_reg = data & 0x01;
+										self.ppu_memory.is_obj_priority_by_coordinate = data & 0x01 != 0;
+									} else if self.is_cgb {
+										self.opri_reg = data & 0x01;
 									}
-			0xFF68..=0xFF6B =>		self.ppu_memory.write(address as usize, data, false),
+			0xFF72..=0xFF77 if !self.is_cgb_hardware => {},
+			0xFF72 | 0xFF73	=>		{self.undocumented_regs[(address - 0xFF72) as usize] = data},
+			0xFF74			=>	if self.is_cgb {self.undocumented_regs[2] = data},
+			0xFF75			=>		{self.undocumented_regs[3] = data & 0x70},
+			0xFF76 | 0xFF77	=> {},
 			0xFF70			=>		{if self.is_cgb {self.svbk_reg = data & 0x07; if self.svbk_reg == 0 {self.svbk_reg += 1}}}
 			0xFF01..=0xFF7F	=>		  {self.io_regis[(address - 0xFF01) as usize] = data},
 			0xFF80..=0xFFFE	=> {self.high_intern_ram[(address - 0xFF80) as usize] = data},
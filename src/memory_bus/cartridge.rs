@@ -1,446 +1,255 @@
-use std::{fs, time::{SystemTime, Duration, UNIX_EPOCH}};
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum MapperType {
-	None, MBC1, MBC2, MBC3, MBC5
+mod no_mbc;
+mod mbc1;
+mod mbc2;
+mod mbc3;
+mod mbc5;
+mod patch;
+
+use std::{fs, ops::RangeInclusive, path::{Path, PathBuf}, time::Duration};
+use crate::crc32::Crc32;
+use no_mbc::NoMbc;
+use mbc1::Mbc1;
+use mbc2::Mbc2;
+use mbc3::Mbc3;
+use mbc5::Mbc5;
+
+pub type RomBank = [u8; 0x4000];
+pub type RamBank = [u8; 0x2000];
+
+/* Banking logic of a cartridge: the mapper owns its registers, while the ROM and RAM banks are owned by the `Cartridge` */
+pub trait Mapper {
+	/* Bank mapped at an address of the ROM area, before wrapping around the ROM size */
+	fn rom_bank_at(&self, address: u16) -> usize;
+	/* Writes to the ROM area, which hit the mapper registers */
+	fn write_register(&mut self, address: u16, data: u8);
+	fn read_ram(&self, ram_banks: &[RamBank], address: u16) -> u8;
+	fn write_ram(&mut self, ram_banks: &mut [RamBank], address: u16, data: u8);
+	/* Snapshot of the registers, and its restoration */
+	fn save_state(&self) -> Vec<u8>;
+	fn load_state(&mut self, state: &[u8]) -> Result<(), String>;
+	/* Number of RAM banks, as declared by the header unless the RAM is built into the mapper */
+	fn nb_ram_banks(&self, header_nb_ram_banks: usize) -> usize {
+		header_nb_ram_banks
+	}
+	/* Contents of the .sav file: the RAM, followed by whatever else the battery keeps */
+	fn save_battery(&self, ram_banks: &[RamBank]) -> Vec<u8> {
+		ram_banks.concat()
+	}
+	fn load_battery(&mut self, ram_banks: &mut [RamBank], contents: &[u8]) {
+		load_ram(ram_banks, contents);
+	}
+	/* Real-time clocks get driven by the emulated time when running movies */
+	fn make_deterministic(&mut self) {}
+	fn advance_emulated_clock(&mut self, _duration: Duration) {}
+	/* New state of the rumble motor, if it changed since the last call */
+	fn take_rumble_event(&mut self) -> Option<bool> {
+		None
+	}
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum ROMType {
-	X2_32KiB, X4_64KiB, X8_128KiB, X16_256KiB, X32_512KiB, X64_1MiB, X128_2MiB, X256_4MiB, X512_8MiB
+type MapperConstructor = fn(&[RomBank]) -> Box<dyn Mapper>;
+
+/* Mappers by cartridge type (header byte 0x147), the other types being run without any */
+const MAPPERS: [(RangeInclusive<u8>, MapperConstructor); 6] = [
+	(0x00..=0x00, NoMbc::create),
+	(0x01..=0x03, Mbc1::create),
+	(0x05..=0x06, Mbc2::create),
+	(0x08..=0x09, NoMbc::create),
+	(0x0F..=0x13, Mbc3::create),
+	(0x19..=0x1E, Mbc5::create)
+];
+
+/* RAM accesses to a bank wrapping around the RAM size, reading 0xFF when there is none */
+fn read_ram_bank(ram_banks: &[RamBank], bank: usize, address: u16) -> u8 {
+	if ram_banks.is_empty() {return 0xFF}
+	ram_banks[bank & (ram_banks.len() - 1)][address as usize - 0xA000]
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
-pub enum RAMType {
-	None, X1_8KiB, X4_32KiB, X8_64KiB, X16_128KiB
+fn write_ram_bank(ram_banks: &mut [RamBank], bank: usize, address: u16, data: u8) {
+	if ram_banks.is_empty() {return}
+	let nb_ram_banks = ram_banks.len();
+	ram_banks[bank & (nb_ram_banks - 1)][address as usize - 0xA000] = data;
+}
+
+fn load_ram(ram_banks: &mut [RamBank], contents: &[u8]) {
+	for (i, byte) in contents.iter().enumerate().take(ram_banks.len() * 0x2000) {
+		ram_banks[i / 0x2000][i % 0x2000] = *byte;
+	}
+}
+
+fn invalid_state_error(mapper_name: &str, state: &[u8]) -> String {
+	format!("invalid {} state of {} bytes", mapper_name, state.len())
+}
+
+/* The boot ROM locks up on a wrong header checksum, which patches seldom update */
+fn fix_header_checksum(rom_contents: &mut [u8]) {
+	rom_contents[0x14D] = rom_contents[0x134..=0x14C].iter().fold(0u8, |checksum, byte| checksum.wrapping_sub(*byte).wrapping_sub(1));
 }
 
 pub struct Cartridge {
 	path: String,
-	mapper_type: MapperType,
-	rom_type: ROMType,
-	rom_banks: Vec<[u8; 0x4000]>,
-	current_2d_rom_bank: usize,
-	ram_type: RAMType,
-	ram_banks: Vec<[u8; 0x2000]>,
-	ram_enable: bool,
-	current_ram_bank: usize,
-	has_battery: bool,
-	mbc1_banking_mode: bool,
-	mbc1_current_rom_banks_upper_bytes: usize,
-	mbc3_has_rtc: bool,
-	mbc3_rtc_last_update_timestamp: SystemTime,
-	mbc3_rtc_registers: [Duration; 2],
-	mbc3_rtc_latch_prev_value: u8,
-	mbc3_rtc_is_latched: bool,
-	mbc3_rtc_is_halted: bool,
-	mbc5_9th_rom_bank_bit: usize,
-
-	pub is_cgb: bool
+	mapper: Box<dyn Mapper>,
+	rom_banks: Vec<RomBank>,
+	ram_banks: Vec<RamBank>,
+	has_battery: bool
 }
 
 impl Drop for Cartridge {
     fn drop(&mut self) {
         if self.has_battery {
-			let mut sav_contents = Vec::new();
-			if self.ram_type != RAMType::None {
-				if self.mapper_type == MapperType::MBC2 {
-					sav_contents.extend_from_slice(&self.ram_banks[0][0x00..=0x01FF])
-				} else {
-					for bank in &self.ram_banks {
-						sav_contents.extend_from_slice(bank)
-					}
-				}
-			}
-			if self.mbc3_has_rtc {
-				sav_contents.push(((self.mbc3_rtc_registers[0]).as_secs() % 60) as u8);
-				sav_contents.push((((self.mbc3_rtc_registers[0]).as_secs() / 60) % 60) as u8);
-				sav_contents.push((((self.mbc3_rtc_registers[0]).as_secs() / 3600) % 24) as u8);
-				sav_contents.push((((self.mbc3_rtc_registers[0]).as_secs() / 86400) % 256) as u8);
-				let nb_256days_periods_elapsed = (self.mbc3_rtc_registers[0]).as_secs() / 22118400;
-				sav_contents.push((nb_256days_periods_elapsed % 2) as u8 | (self.mbc3_rtc_is_halted as u8) << 6 | if nb_256days_periods_elapsed >= 2 {1} else {0} << 7);
-				sav_contents.push(((self.mbc3_rtc_registers[1]).as_secs() % 60) as u8);
-				sav_contents.push((((self.mbc3_rtc_registers[1]).as_secs() / 60) % 60) as u8);
-				sav_contents.push((((self.mbc3_rtc_registers[1]).as_secs() / 3600) % 24) as u8);
-				sav_contents.push((((self.mbc3_rtc_registers[1]).as_secs() / 86400) % 256) as u8);
-				let nb_256days_periods_elapsed = (self.mbc3_rtc_registers[1]).as_secs() / 22118400;
-				sav_contents.push((nb_256days_periods_elapsed % 2) as u8 | (self.mbc3_rtc_is_halted as u8) << 6 | if nb_256days_periods_elapsed >= 2 {1} else {0} << 7);
-				let unix_timestamp = self.mbc3_rtc_last_update_timestamp.duration_since(UNIX_EPOCH).expect("We are before epoch!").as_secs();
-				for i in 0..=7 {
-					sav_contents.push(((unix_timestamp >> i * 8) & 0xFF) as u8)
-				}
-
-			}
 			let path = self.path.clone() + ".sav";
-			fs::write(path, sav_contents).unwrap_or_default();
+			fs::write(path, self.mapper.save_battery(&self.ram_banks)).unwrap_or_default();
 		}
     }
 }
 
 impl Cartridge {
-	pub fn new(rom_path: Option<&str>) -> Self {
+	pub fn new(rom_path: Option<&str>, patch_path: Option<&str>) -> Result<Self, String> {
 		if let Some(path) = rom_path {
-			if let Ok(cart) = Cartridge::load_from_path(path) {
-				cart
-			} else {
-				panic!("Unable to open ROM file at {}", path)
-			}
+			Cartridge::load_from_path(path, patch_path).map_err(|err| format!("Unable to open ROM file at {}: {}", path, err))
 		} else {
-			Cartridge {
+			Ok(Cartridge {
 				path: String::new(),
-				mapper_type: MapperType::None,
-				rom_type: ROMType::X2_32KiB,
+				mapper: Box::new(NoMbc::new()),
 				rom_banks: vec![[0xFF; 0x4000]; 2],
-				current_2d_rom_bank: 0x01,
-				ram_type: RAMType::None,
 				ram_banks: Vec::new(),
-				ram_enable: false,
-				current_ram_bank: 0x00,
-				has_battery: false,
-				mbc1_banking_mode: false,
-				mbc1_current_rom_banks_upper_bytes: 0x00,
-				mbc3_has_rtc: false,
-				mbc3_rtc_registers: [Duration::from_secs(0); 2],
-				mbc3_rtc_last_update_timestamp: SystemTime::UNIX_EPOCH,
-				mbc3_rtc_latch_prev_value: 0xFF,
-				mbc3_rtc_is_latched: false,
-				mbc3_rtc_is_halted: false,
-				mbc5_9th_rom_bank_bit: 0x00,
-				is_cgb: false
-			}
+				has_battery: false
+			})
 		}
 	}
-	fn load_from_path(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-		let rom_contents = fs::read(path)?;
-		let mapper_type = match rom_contents[0x147] {
-			0x01..=0x03 =>	MapperType::MBC1,
-			0x05 | 0x06 =>	MapperType::MBC2,
-			0x0F..=0x13 =>	MapperType::MBC3,
-			0x19..=0x1E => MapperType::MBC5,
-			_ => MapperType::None
-		};
-		let mbc3_has_rtc = match rom_contents[0x147] {
-			0x0F | 0x10 => true,
-			_ => false
-		};
+	/* Applies the given patch, or else a same-named .ips, .bps or .ups one, the save file being named after the patched ROM.
+	   The ROM is not loaded at all when the patch fails, rather than silently running unpatched */
+	fn load_from_path(path: &str, patch_path: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+		let mut rom_contents = fs::read(path)?;
+		let mut save_path = PathBuf::from(path);
+		let patch_path = patch_path.map(PathBuf::from).or_else(|| {
+			patch::PATCH_EXTENSIONS.iter().map(|extension| Path::new(path).with_extension(extension)).find(|patch_path| patch_path.exists())
+		});
+		if let Some(patch_path) = patch_path {
+			rom_contents = fs::read(&patch_path).map_err(|err| err.to_string())
+				.and_then(|patch| patch::apply(&rom_contents, &patch))
+				.map_err(|err| format!("unable to apply patch {}: {}", patch_path.display(), err))?;
+			if rom_contents.len() >= 0x150 {
+				fix_header_checksum(&mut rom_contents);
+			}
+			save_path = patch_path.with_extension(Path::new(path).extension().unwrap_or_default());
+		}
+		if rom_contents.len() < 0x150 {
+			return Err("no cartridge header".into());
+		}
 		let has_battery = match rom_contents[0x147] {
 			0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E => true,
 			_ => false 
 		};
-		let rom_type = match rom_contents[0x148] {
-			0x01 => ROMType::X4_64KiB,
-			0x02 => ROMType::X8_128KiB,
-			0x03 => ROMType::X16_256KiB,
-			0x04 => ROMType::X32_512KiB,
-			0x05 => ROMType::X64_1MiB,
-			0x06 => ROMType::X128_2MiB,
-			0x07 => ROMType::X256_4MiB,
-			0x08 => ROMType::X512_8MiB,
-			_ 	=> ROMType::X2_32KiB
+		let nb_header_rom_banks = match rom_contents[0x148] {
+			0x01..=0x08	=> 0x02 << rom_contents[0x148],
+			_			=> 0x02
 		};
-		let ram_type = match rom_contents[0x149] {
-			0x02 => RAMType::X1_8KiB,
-			0x03 => RAMType::X4_32KiB,
-			0x04 => RAMType::X16_128KiB,
-			0x05 => RAMType::X8_64KiB,
-			_ => if mapper_type == MapperType::MBC2 {RAMType::X1_8KiB} else {RAMType::None}
+		/* Dumps and patches may hold more data than the header declares */
+		let nb_rom_banks = usize::max(nb_header_rom_banks, rom_contents.len().div_ceil(0x4000)).next_power_of_two();
+		let nb_ram_banks = match rom_contents[0x149] {
+			0x02 => 1,
+			0x03 => 4,
+			0x04 => 16,
+			0x05 => 8,
+			_ => 0
 		};
-		let is_cgb = rom_contents[0x143] & 0x80 != 0x00;
-		let mut rom_banks = vec![[0xFF; 0x4000]; match rom_type {
-				ROMType::X2_32KiB =>	0x02,
-				ROMType::X4_64KiB =>	0x04,
-				ROMType::X8_128KiB =>	0x08,
-				ROMType::X16_256KiB =>	0x10,
-				ROMType::X32_512KiB =>	0x20,
-				ROMType::X64_1MiB =>	0x40,
-				ROMType::X128_2MiB =>	0x80,
-				ROMType::X256_4MiB =>	0x100,
-				ROMType::X512_8MiB =>	0x200,
-			}
-		];
+		let mut rom_banks = vec![[0xFF; 0x4000]; nb_rom_banks];
 		for (i, byte) in rom_contents.iter().enumerate() {
 			rom_banks[i / 0x4000][i % 0x4000] = *byte;
 		}
-		let mut ram_banks = vec![[0x00; 0x2000]; match ram_type {
-				RAMType::None => 0,
-				RAMType::X1_8KiB => 1,
-				RAMType::X4_32KiB => 4,
-				RAMType::X8_64KiB => 8,
-				RAMType::X16_128KiB => 16,
-			}
-		];
-		let mut mbc3_rtc_registers = [Duration::from_secs(0); 2];
-		let mut mbc3_rtc_is_latched = false;
-		let mut mbc3_rtc_is_halted = false;
-		let mut mbc3_rtc_last_update_timestamp = SystemTime::UNIX_EPOCH;
+		let mut mapper = MAPPERS.iter()
+			.find(|(cartridge_types, _)| cartridge_types.contains(&rom_contents[0x147]))
+			.map_or_else(|| Box::new(NoMbc::new()) as Box<dyn Mapper>, |(_, create)| create(&rom_banks));
+		let mut ram_banks = vec![[0x00; 0x2000]; mapper.nb_ram_banks(nb_ram_banks)];
 		if has_battery {
-			let ram_path = path.to_owned() + ".sav";
+			let ram_path = save_path.to_string_lossy().into_owned() + ".sav";
 			let ram_contents = fs::read(ram_path).unwrap_or_default();
-			for (i, byte) in ram_contents.iter().enumerate() {
-				if !mbc3_has_rtc || i < ram_contents.len() - 0x12 {
-					ram_banks[i / 0x2000][i % 0x2000] = *byte;
-				}
-			}
-			if mbc3_has_rtc && ram_contents.len() >= 0x12 {
-				mbc3_rtc_registers[0] +=  Duration::from_secs(ram_contents[ram_contents.len() - 0x12] as u64);
-				mbc3_rtc_registers[0] +=  Duration::from_secs((ram_contents[ram_contents.len() - 0x11] as u64) * 60);
-				mbc3_rtc_registers[0] +=  Duration::from_secs((ram_contents[ram_contents.len() - 0x10] as u64) * 3600);
-				mbc3_rtc_registers[0] +=  Duration::from_secs((ram_contents[ram_contents.len() - 0x0F] as u64) * 86400);
-				mbc3_rtc_registers[0] +=  Duration::from_secs(((ram_contents[ram_contents.len() - 0x0E] & 1 + ram_contents[ram_contents.len() - 0x0E] >> 7)  as u64) * 22118400);
-				mbc3_rtc_is_halted = ram_contents[ram_contents.len() - 0x0E] & (1 << 6) != 0;
-				mbc3_rtc_registers[1] +=  Duration::from_secs(ram_contents[ram_contents.len() - 0x0D] as u64);
-				mbc3_rtc_registers[1] +=  Duration::from_secs((ram_contents[ram_contents.len() - 0x0C] as u64) * 60);
-				mbc3_rtc_registers[1] +=  Duration::from_secs((ram_contents[ram_contents.len() - 0x0B] as u64) * 3600);
-				mbc3_rtc_registers[1] +=  Duration::from_secs((ram_contents[ram_contents.len() - 0x0A] as u64) * 86400);
-				mbc3_rtc_registers[1] +=  Duration::from_secs(((ram_contents[ram_contents.len() - 0x09] & 1 + ram_contents[ram_contents.len() - 0x09] >> 7)  as u64) * 22118400);
-				mbc3_rtc_is_latched = mbc3_rtc_registers[0] != mbc3_rtc_registers[1];
-				let mut last_timestamp = 0 as u64;
-				for i in 0..=7 {
-					last_timestamp += (ram_contents[ram_contents.len() - 0x08 + i] as u64) << (8 * i);
-				}
-				mbc3_rtc_last_update_timestamp += Duration::from_secs(last_timestamp);
-			}
+			mapper.load_battery(&mut ram_banks, &ram_contents);
 		}
 		Ok(Cartridge {
-			path: path.to_string(),
-			mapper_type,
-			rom_type,
+			path: save_path.to_string_lossy().into_owned(),
+			mapper,
 			rom_banks,
-			current_2d_rom_bank: 0x01,
-			ram_type,
 			ram_banks,
-			ram_enable: false,
-			current_ram_bank: 0x00,
-			has_battery,
-			mbc1_banking_mode: false,
-			mbc1_current_rom_banks_upper_bytes: 0x00,
-			mbc3_has_rtc,
-			mbc3_rtc_last_update_timestamp,
-			mbc3_rtc_registers,
-			mbc3_rtc_latch_prev_value: 0xFF,
-			mbc3_rtc_is_latched,
-			mbc3_rtc_is_halted,
-			mbc5_9th_rom_bank_bit: 0x00,
-			is_cgb
+			has_battery
 		})
 	}
+	/* Drives the RTC from emulated time and stops writing the .sav, so that runs are reproducible across machines.
+	Returns the battery-backed state the run starts from, for movies to record it */
+	pub fn make_deterministic(&mut self) -> Option<Vec<u8>> {
+		let has_battery = std::mem::replace(&mut self.has_battery, false);
+		self.mapper.make_deterministic();
+		has_battery.then(|| self.save_state())
+	}
+	pub fn clear_ram(&mut self) {
+		for bank in self.ram_banks.iter_mut() {
+			bank.fill(0x00);
+		}
+	}
+	pub fn advance_emulated_clock(&mut self, duration: Duration) {
+		self.mapper.advance_emulated_clock(duration);
+	}
+	pub fn take_rumble_event(&mut self) -> Option<bool> {
+		self.mapper.take_rumble_event()
+	}
+	/* The RAM contents followed by the mapper registers */
+	pub fn save_state(&self) -> Vec<u8> {
+		let mut state = self.ram_banks.concat();
+		state.extend(self.mapper.save_state());
+		state
+	}
+	pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+		let ram_size = self.ram_banks.len() * 0x2000;
+		if state.len() < ram_size {
+			return Err(format!("cartridge state of {} bytes, without the {} bytes of RAM", state.len(), ram_size));
+		}
+		self.mapper.load_state(&state[ram_size..])?;
+		load_ram(&mut self.ram_banks, &state[..ram_size]);
+		Ok(())
+	}
+	pub fn rom_crc32(&self) -> u32 {
+		let mut crc = Crc32::new();
+		for bank in &self.rom_banks {
+			crc.update(bank);
+		}
+		crc.finish()
+	}
+	/* Bank numbers wrap around the ROM size, the unused upper bits of the registers being ignored */
+	pub fn rom_bank_at(&self, address: u16) -> usize {
+		self.mapper.rom_bank_at(address) & (self.rom_banks.len() - 1)
+	}
+	pub fn rom_banks(&self) -> &[RomBank] {
+		&self.rom_banks
+	}
+	pub fn rom_banks_mut(&mut self) -> &mut [RomBank] {
+		&mut self.rom_banks
+	}
+	pub fn ram_banks(&self) -> &[RamBank] {
+		&self.ram_banks
+	}
+	pub fn ram_banks_mut(&mut self) -> &mut [RamBank] {
+		&mut self.ram_banks
+	}
 	pub fn read(&self, address: usize) -> u8 {
 		match address {
-			0x0000..=0x3FFF	=> self.rom_banks[if self.mbc1_banking_mode {self.mbc1_current_rom_banks_upper_bytes << 5} else {0}][address],
-			0x4000..=0x7FFF	=> self.rom_banks[self.mbc5_9th_rom_bank_bit << 9 | self.mbc1_current_rom_banks_upper_bytes << 5 | self.current_2d_rom_bank][address - 0x4000],
-			0xA000..=0xBFFF	=> if (self.ram_type == RAMType::None && !(self.mbc3_has_rtc && self.current_ram_bank >= 0x08)) || !self.ram_enable	{0xFF}
-								else if self.mbc3_has_rtc && self.current_ram_bank >= 0x08 {
-									let elapsed_time = if self.mbc3_rtc_is_halted || self.mbc3_rtc_is_latched {Duration::new(0, 0)} else {self.mbc3_rtc_last_update_timestamp.elapsed().expect("Time ran backwards")};
-									match self.current_ram_bank {
-										0x08 => {((self.mbc3_rtc_registers[1] + elapsed_time).as_secs() % 60) as u8}
-										0x09 => {(((self.mbc3_rtc_registers[1] + elapsed_time).as_secs() / 60) % 60) as u8}
-										0x0A => {(((self.mbc3_rtc_registers[1] + elapsed_time).as_secs() / 3600) % 24) as u8}
-										0x0B => {(((self.mbc3_rtc_registers[1] + elapsed_time).as_secs() / 86400) % 256) as u8}
-										_	 => {
-											let nb_256days_periods_elapsed = (self.mbc3_rtc_registers[1] + elapsed_time).as_secs() / 22118400;
-											(if nb_256days_periods_elapsed >= 2 {1} else {0} as u8) << 7 | (self.mbc3_rtc_is_halted as u8) << 6 | (nb_256days_periods_elapsed % 2) as u8
-										}
-									}
-								}
-								else { self.ram_banks
-										[
-											if self.mapper_type == MapperType::MBC1 && !self.mbc1_banking_mode {0}
-											else {self.current_ram_bank}
-										]
-										[(address - 0xA000) % if self.mapper_type == MapperType::MBC2 {0x0200} else {0x2000}]
-								},
+			0x0000..=0x7FFF	=> self.rom_banks[self.rom_bank_at(address as u16)][address % 0x4000],
+			0xA000..=0xBFFF	=> self.mapper.read_ram(&self.ram_banks, address as u16),
 			_ => 0
 		}
 	}
 	pub fn write(&mut self, address: usize, data: u8) {
-		match self.mapper_type {
-			MapperType::None =>
-				match address {
-					0x0000..=0x7FFF	=> {},
-					0xA000..=0xBFFF	=> if let RAMType::None = self.ram_type {}
-										else if self.ram_enable {self.ram_banks[self.current_ram_bank][(address - 0xA000)] = data},
-					_ => {}
-				}
-			MapperType::MBC1 =>
-				match address {
-					0x0000..=0x1FFF => if data & 0x0F == 0x0A {self.ram_enable = true} else {self.ram_enable = false}
-					0x2000..=0x3FFF => {
-						let mut data = data & 0x1F;
-						if data == 0x00 {data = 0x01}
-						self.current_2d_rom_bank = (data as usize) & match self.rom_type {
-																		ROMType::X2_32KiB	=> 0x01,
-																		ROMType::X4_64KiB	=> 0x03,
-																		ROMType::X8_128KiB	=> 0x07,
-																		ROMType::X16_256KiB	=> 0x0F,
-																		_					=> 0x1F,
-        															}
-						}
-					0x4000..=0x5FFF => {
-						self.current_ram_bank = data as usize & match self.ram_type {
-							RAMType::X4_32KiB => 0x03,
-							_ => 0x00
-						};
-						self.mbc1_current_rom_banks_upper_bytes = data as usize & match self.rom_type {
-							ROMType::X128_2MiB => 0x03,
-							ROMType::X64_1MiB => 0x01,
-							_ => 0x00					
-						}
-					}
-					0x6000..=0x7FFF => {
-						if data & 0x01 != 0x00 && (self.ram_type == RAMType::X4_32KiB || self.rom_type == ROMType::X64_1MiB || self.rom_type == ROMType::X128_2MiB) {
-							self.mbc1_banking_mode = true
-						}
-						else {self.mbc1_banking_mode = false}
-					}
-					0xA000..=0xBFFF	=> if let RAMType::None = self.ram_type {}
-										else if self.ram_enable {self.ram_banks[if !self.mbc1_banking_mode {0} else {self.current_ram_bank}][(address - 0xA000)] = data},
-					_ => {}
-				}
-			MapperType::MBC2 => {
-				match address {
-					0x0000..=0x3FFF => {
-						let mut data = data & 0x0F;
-						if address & 0x0100 == 0 {if data == 0x0A {self.ram_enable = true} else {self.ram_enable = false}}
-						else {
-							if data == 0x00 {data = 0x01}
-							self.current_2d_rom_bank = (data as usize) & match self.rom_type {
-																			ROMType::X2_32KiB	=> 0x01,
-																			ROMType::X4_64KiB	=> 0x03,
-																			ROMType::X8_128KiB	=> 0x07,
-																			_					=> 0x0F,
-																		};
-						}
-					}
-					0xA000..=0xBFFF => if self.ram_enable {self.ram_banks[0][(address - 0xA000) % 0x0200] = data}
-					_ => {}
-				}
-			},
-			MapperType::MBC3 => {
-				match address {
-					0x0000..=0x1FFF => if data & 0x0F == 0x0A {self.ram_enable = true} else {self.ram_enable = false}
-					0x2000..=0x2FFF => {
-						let mut data = data & 0x7F;
-						if data == 0x00 {data = 0x01}
-						self.current_2d_rom_bank = (data as usize) & match self.rom_type {
-																		ROMType::X2_32KiB	=> 0x01,
-																		ROMType::X4_64KiB	=> 0x03,
-																		ROMType::X8_128KiB	=> 0x07,
-																		ROMType::X16_256KiB	=> 0x0F,
-																		ROMType::X32_512KiB	=> 0x1F,
-																		ROMType::X64_1MiB	=> 0x3F,
-																		_					=> 0x7F
-        															}
-						}
-					0x4000..=0x5FFF => {
-							if data <= 0x03 {
-								self.current_ram_bank = (data as usize) & match self.ram_type {
-																			RAMType::None		=> 0x00,
-																			RAMType::X1_8KiB	=> 0x01,
-																			_					=> 0x03,
-																		}
-							}
-							else if data >= 0x08 && data <= 0x0C && self.mbc3_has_rtc {self.current_ram_bank = data as usize}
-						}
-					0x6000..=0x7FFF => {
-						if !self.mbc3_has_rtc {return}
-						let is_latch_started = self.mbc3_rtc_latch_prev_value == 0x00;
-						self.mbc3_rtc_latch_prev_value = data;
-						if is_latch_started && data == 0x01 {
-							if !self.mbc3_rtc_is_halted {
-								self.mbc3_rtc_registers[0] += self.mbc3_rtc_last_update_timestamp.elapsed().expect("Time ran backwards")
-							};
-							self.mbc3_rtc_last_update_timestamp = SystemTime::now();
-							self.mbc3_rtc_registers[1] = self.mbc3_rtc_registers[0];
-							self.mbc3_rtc_is_latched = !self.mbc3_rtc_is_latched
-						}
-					}
-					0xA000..=0xBFFF => {
-						if self.ram_enable {
-							if self.ram_type != RAMType::None && self.current_ram_bank < 0x08  {
-								self.ram_banks[self.current_ram_bank][address - 0xA000] = data
-							}
-							else if self.mbc3_has_rtc && self.current_ram_bank >= 0x08 {
-								if !self.mbc3_rtc_is_halted {
-									self.mbc3_rtc_registers[0] += self.mbc3_rtc_last_update_timestamp.elapsed().expect("Time ran backwards!");
-								}
-								self.mbc3_rtc_last_update_timestamp = SystemTime::now();
-								match self.current_ram_bank {
-									0x08 => {
-										self.mbc3_rtc_registers[0] -= Duration::from_secs(self.mbc3_rtc_registers[0].as_secs() % 60);
-										self.mbc3_rtc_registers[0] += Duration::from_secs(data as u64);
-									}
-									0x09 => {
-										self.mbc3_rtc_registers[0] -= Duration::from_secs((self.mbc3_rtc_registers[0].as_secs() / 60) % 60);
-										self.mbc3_rtc_registers[0] += Duration::from_secs(data as u64 * 60);
-									}
-									0x0A => {
-										self.mbc3_rtc_registers[0] -= Duration::from_secs((self.mbc3_rtc_registers[0].as_secs() / 3600) % 24);
-										self.mbc3_rtc_registers[0] += Duration::from_secs(data as u64 * 3600);
-									}
-									0x0B => {
-										self.mbc3_rtc_registers[0] -= Duration::from_secs((self.mbc3_rtc_registers[0].as_secs() / 86400) % 256);
-										self.mbc3_rtc_registers[0] += Duration::from_secs(data as u64 * 86400);
-									}
-									_ => {
-										let is_carry = data & (1 << 7) != 0;
-										let is_halt = data & (1 << 6) != 0;
-										let data = data & 1;
-										self.mbc3_rtc_registers[0] = Duration::from_secs(self.mbc3_rtc_registers[0].as_secs() % 44236800);
-										self.mbc3_rtc_registers[0] += Duration::from_secs(if is_carry {1} else {0} * 44236800);
-										self.mbc3_rtc_registers[0] -= Duration::from_secs((self.mbc3_rtc_registers[0].as_secs() / 22118400) % 2);
-										self.mbc3_rtc_registers[0] += Duration::from_secs(data as u64 * 22118400);
-										if is_halt {self.mbc3_rtc_is_halted = true} else {self.mbc3_rtc_is_halted = false}
-									}
-								}
-								if !self.mbc3_rtc_is_latched {
-									self.mbc3_rtc_registers[1] = self.mbc3_rtc_registers[0];
-								}
-
-							}
-						}
-					}
-					_ => {}
-				}
-			}
-			MapperType::MBC5 => {
-				match address {
-					0x0000..=0x1FFF => if data & 0x0F == 0x0A {self.ram_enable = true} else {self.ram_enable = false}
-					0x2000..=0x2FFF => {
-						self.current_2d_rom_bank = (data as usize) & match self.rom_type {
-																		ROMType::X2_32KiB	=> 0x01,
-																		ROMType::X4_64KiB	=> 0x03,
-																		ROMType::X8_128KiB	=> 0x07,
-																		ROMType::X16_256KiB	=> 0x0F,
-																		ROMType::X32_512KiB	=> 0x1F,
-																		ROMType::X64_1MiB	=> 0x3F,
-																		ROMType::X128_2MiB	=> 0x7F,
-																		_					=> 0xFF
-        															}
-						}
-					0x3000..=0x3FFF => {
-						self.mbc5_9th_rom_bank_bit = if data & 0x01 != 0 && self.rom_type == ROMType::X512_8MiB {1} else {0} 
-					}
-					0x4000..=0x5FFF => {
-						if data <= 0x0F {
-							self.current_ram_bank = (data as usize) & match self.ram_type {
-																		RAMType::None		=> 0x00,
-																		RAMType::X1_8KiB	=> 0x01,
-																		RAMType::X4_32KiB	=> 0x03,
-																		RAMType::X8_64KiB	=> 0x07,
-																		RAMType::X16_128KiB	=> 0x0F
-																	}
-						}
-					}
-					0xA000..=0xBFFF => if self.ram_type != RAMType::None && self.ram_enable {self.ram_banks[self.current_ram_bank][address - 0xA000] = data}
-					_ => {}
-				}
-			},
+		match address {
+			0x0000..=0x7FFF	=> self.mapper.write_register(address as u16, data),
+			0xA000..=0xBFFF	=> self.mapper.write_ram(&mut self.ram_banks, address as u16, data),
+			_ => {}
 		}
 	}
 	pub fn _debug_insert_cart_logo(&mut self) {
 		let logo_data : [u8; 48] = [
 			0xCE, 0xED, 0x66, 0x66, 0xCC, 0x0D, 0x00, 0x0B, 
 			0x03, 0x73, 0x00, 0x83, 0x00, 0x0C, 0x00, 0x0D, 0x00, 0x08, 0x11, 0x1F, 0x88, 0x89, 0x00, 0x0E, 
-			0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC, 
+			0xDC, 0xCC, 0x6E, 0xE6, 0xDD, 0xDD, 0xD9, 0x99, 0xBB, 0xBB, 0x67, 0x63, 0x6E, 0x0E, 0xEC, 0xCC,
 			0xDD, 0xDC, 0x99, 0x9F, 0xBB, 0xB9, 0x33, 0x3E
 		];
 		for (i, byte) in logo_data.iter().enumerate() {
@@ -451,4 +260,79 @@ impl Cartridge {
 			self.rom_banks[0][i] = 0x00;
 		}
 	}
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/* Cartridge of blank banks, the boot logo being repeated at the start of the given ones */
+	fn cartridge(cartridge_type: u8, ram_size: u8, nb_rom_banks: usize, nb_ram_banks: usize, logo_banks: &[usize]) -> Cartridge {
+		let mut rom_banks = vec![[0x00; 0x4000]; nb_rom_banks];
+		rom_banks[0][0x147] = cartridge_type;
+		rom_banks[0][0x149] = ram_size;
+		for bank in logo_banks {
+			for (i, byte) in rom_banks[*bank][0x104..0x134].iter_mut().enumerate() {
+				*byte = 0xA5 ^ i as u8;
+			}
+		}
+		let (_, create) = MAPPERS.iter().find(|(cartridge_types, _)| cartridge_types.contains(&cartridge_type)).unwrap();
+		Cartridge { path: String::new(), mapper: create(&rom_banks), rom_banks, ram_banks: vec![[0x00; 0x2000]; nb_ram_banks], has_battery: false }
+	}
+
+	fn rom_banks_at(cartridge: &mut Cartridge, writes: &[(usize, u8)]) -> (usize, usize) {
+		for (address, data) in writes {
+			cartridge.write(*address, *data);
+		}
+		(cartridge.rom_bank_at(0x0000), cartridge.rom_bank_at(0x4000))
+	}
+
+	#[test]
+	fn test_mbc1_banking() {
+		let mut mbc1 = cartridge(0x01, 0x00, 0x40, 0, &[0x00]);
+		assert_eq!(rom_banks_at(&mut mbc1, &[(0x2000, 0x12), (0x4000, 0x01)]), (0x00, 0x32));
+		assert_eq!(rom_banks_at(&mut mbc1, &[(0x6000, 0x01)]), (0x20, 0x32));
+		assert_eq!(rom_banks_at(&mut mbc1, &[(0x2000, 0x00)]), (0x20, 0x21));
+		/* Bank numbers wrap around the ROM size, the bank 0 check being done on the 5 bits */
+		let mut small_mbc1 = cartridge(0x01, 0x00, 0x10, 0, &[0x00]);
+		assert_eq!(rom_banks_at(&mut small_mbc1, &[(0x2000, 0x1F), (0x4000, 0x01), (0x6000, 0x01)]), (0x00, 0x0F));
+		assert_eq!(rom_banks_at(&mut small_mbc1, &[(0x2000, 0x10)]), (0x00, 0x00));
+		assert_eq!(rom_banks_at(&mut small_mbc1, &[(0x2000, 0x20)]), (0x00, 0x01));
+	}
+
+	#[test]
+	fn test_mbc1_multicart() {
+		/* MBC1M: the logo repeated every 0x40000 bytes, the upper bits being wired as bits 4-5 of the bank number */
+		let mut mbc1m = cartridge(0x01, 0x00, 0x40, 0, &[0x00, 0x10, 0x20, 0x30]);
+		assert_eq!(rom_banks_at(&mut mbc1m, &[(0x2000, 0x12), (0x4000, 0x01)]), (0x00, 0x12));
+		assert_eq!(rom_banks_at(&mut mbc1m, &[(0x6000, 0x01)]), (0x10, 0x12));
+		assert_eq!(rom_banks_at(&mut mbc1m, &[(0x4000, 0x03)]), (0x30, 0x32));
+		assert_eq!(rom_banks_at(&mut mbc1m, &[(0x2000, 0x10)]), (0x30, 0x30));
+		assert_eq!(rom_banks_at(&mut mbc1m, &[(0x6000, 0x00), (0x4000, 0x02)]), (0x00, 0x20));
+		/* A single repeated logo is not enough */
+		let mut mbc1 = cartridge(0x01, 0x00, 0x40, 0, &[0x00, 0x10]);
+		assert_eq!(rom_banks_at(&mut mbc1, &[(0x2000, 0x12), (0x4000, 0x01)]), (0x00, 0x32));
+	}
+
+	#[test]
+	fn test_mbc30_ram_banks() {
+		for (ram_size, nb_ram_banks) in [(0x05, 8), (0x03, 4)] {
+			let mut mbc3 = cartridge(0x13, ram_size, 0x80, nb_ram_banks, &[0x00]);
+			for (ram_bank, data) in [(0x02, 0x22), (0x05, 0x55), (0x07, 0x77)] {
+				mbc3.write(0x0000, 0x0A);
+				mbc3.write(0x4000, ram_bank);
+				mbc3.write(0xA000, data);
+			}
+			if nb_ram_banks == 8 {
+				assert_eq!([0x02, 0x05, 0x07].map(|ram_bank| mbc3.ram_banks()[ram_bank][0x0000]), [0x22, 0x55, 0x77]);
+			} else {
+				assert_eq!(mbc3.ram_banks()[0x02][0x0000], 0x77);		// Banks 4-7 are not selected on MBC3
+			}
+		}
+		/* MBC30 cartridges are also told apart by their 4 MiB of ROM */
+		let mut mbc30 = cartridge(0x13, 0x03, 0x100, 4, &[0x00]);
+		assert_eq!(rom_banks_at(&mut mbc30, &[(0x2000, 0xFF)]), (0x00, 0xFF));
+		let mut mbc3 = cartridge(0x13, 0x03, 0x80, 4, &[0x00]);
+		assert_eq!(rom_banks_at(&mut mbc3, &[(0x2000, 0xFF)]), (0x00, 0x7F));
+	}
+}
@@ -0,0 +1,70 @@
+use super::{Mapper, RamBank, RomBank, read_ram_bank, write_ram_bank, invalid_state_error};
+
+pub struct Mbc1 {
+	ram_enable: bool,
+	current_2d_rom_bank: usize,
+	current_upper_bits: usize,				// ROM bank upper bits, or RAM bank
+	banking_mode: bool,
+	is_multicart: bool						// MBC1M: the upper bits select one of the 4 games of 256 KiB
+}
+
+impl Mbc1 {
+	pub fn create(rom_banks: &[RomBank]) -> Box<dyn Mapper> {
+		/* MBC1M multicarts are 1 MiB, with the boot logo repeated at the start of every game */
+		let is_multicart = rom_banks.len() == 0x40
+			&& (0x10..0x40).step_by(0x10).filter(|&bank| rom_banks[bank][0x104..0x134] == rom_banks[0][0x104..0x134]).count() >= 2;
+		Box::new(Mbc1 {
+			ram_enable: false,
+			current_2d_rom_bank: 0x01,
+			current_upper_bits: 0x00,
+			banking_mode: false,
+			is_multicart
+		})
+	}
+	fn ram_bank(&self) -> usize {
+		if self.banking_mode {self.current_upper_bits} else {0}
+	}
+}
+
+impl Mapper for Mbc1 {
+	fn rom_bank_at(&self, address: u16) -> usize {
+		let upper_bits_shift = if self.is_multicart {4} else {5};
+		match address {
+			0x0000..=0x3FFF	=> if self.banking_mode {self.current_upper_bits << upper_bits_shift} else {0},
+			_ if self.is_multicart => self.current_upper_bits << 4 | self.current_2d_rom_bank & 0x0F,
+			_				=> self.current_upper_bits << 5 | self.current_2d_rom_bank
+		}
+	}
+	fn write_register(&mut self, address: u16, data: u8) {
+		match address {
+			0x0000..=0x1FFF => self.ram_enable = data & 0x0F == 0x0A,
+			0x2000..=0x3FFF => {
+				let mut data = data & 0x1F;
+				if data == 0x00 {data = 0x01}				// Checked on all 5 bits, even when the ROM is smaller
+				self.current_2d_rom_bank = data as usize
+			}
+			0x4000..=0x5FFF => self.current_upper_bits = data as usize & 0x03,
+			_				=> self.banking_mode = data & 0x01 != 0x00
+		}
+	}
+	fn read_ram(&self, ram_banks: &[RamBank], address: u16) -> u8 {
+		if !self.ram_enable {return 0xFF}
+		read_ram_bank(ram_banks, self.ram_bank(), address)
+	}
+	fn write_ram(&mut self, ram_banks: &mut [RamBank], address: u16, data: u8) {
+		if self.ram_enable {write_ram_bank(ram_banks, self.ram_bank(), address, data)}
+	}
+	fn save_state(&self) -> Vec<u8> {
+		vec![self.ram_enable as u8, self.current_2d_rom_bank as u8, self.current_upper_bits as u8, self.banking_mode as u8]
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+		let &[ram_enable, current_2d_rom_bank, current_upper_bits, banking_mode] = state else {
+			return Err(invalid_state_error("MBC1", state));
+		};
+		self.ram_enable = ram_enable != 0;
+		self.current_2d_rom_bank = current_2d_rom_bank as usize;
+		self.current_upper_bits = current_upper_bits as usize;
+		self.banking_mode = banking_mode != 0;
+		Ok(())
+	}
+}
@@ -0,0 +1,55 @@
+use super::{Mapper, RamBank, RomBank, invalid_state_error};
+
+/* Up to 256 KiB of ROM, and 512 half-bytes of built-in RAM */
+pub struct Mbc2 {
+	ram_enable: bool,
+	current_2d_rom_bank: usize
+}
+
+impl Mbc2 {
+	pub fn create(_rom_banks: &[RomBank]) -> Box<dyn Mapper> {
+		Box::new(Mbc2 {
+			ram_enable: false,
+			current_2d_rom_bank: 0x01
+		})
+	}
+}
+
+impl Mapper for Mbc2 {
+	fn rom_bank_at(&self, address: u16) -> usize {
+		if address < 0x4000 {0} else {self.current_2d_rom_bank}
+	}
+	fn write_register(&mut self, address: u16, data: u8) {
+		if address >= 0x4000 {return}
+		let mut data = data & 0x0F;
+		if address & 0x0100 == 0 {self.ram_enable = data == 0x0A}
+		else {
+			if data == 0x00 {data = 0x01}
+			self.current_2d_rom_bank = data as usize;
+		}
+	}
+	fn read_ram(&self, ram_banks: &[RamBank], address: u16) -> u8 {
+		if !self.ram_enable {return 0xFF}
+		ram_banks[0][(address as usize - 0xA000) % 0x0200] | 0xF0
+	}
+	fn write_ram(&mut self, ram_banks: &mut [RamBank], address: u16, data: u8) {
+		if self.ram_enable {ram_banks[0][(address as usize - 0xA000) % 0x0200] = data}
+	}
+	fn save_state(&self) -> Vec<u8> {
+		vec![self.ram_enable as u8, self.current_2d_rom_bank as u8]
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+		let &[ram_enable, current_2d_rom_bank] = state else {
+			return Err(invalid_state_error("MBC2", state));
+		};
+		self.ram_enable = ram_enable != 0;
+		self.current_2d_rom_bank = current_2d_rom_bank as usize;
+		Ok(())
+	}
+	fn nb_ram_banks(&self, _header_nb_ram_banks: usize) -> usize {
+		1
+	}
+	fn save_battery(&self, ram_banks: &[RamBank]) -> Vec<u8> {
+		ram_banks[0][0x00..=0x01FF].to_vec()
+	}
+}
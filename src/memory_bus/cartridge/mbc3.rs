@@ -0,0 +1,217 @@
+use std::time::{SystemTime, Duration, UNIX_EPOCH};
+use super::{Mapper, RamBank, RomBank, read_ram_bank, write_ram_bank, invalid_state_error};
+
+const RTC_NB_BYTES: usize = 0x12;
+
+pub struct Mbc3 {
+	ram_enable: bool,
+	current_2d_rom_bank: usize,
+	current_ram_bank: usize,				// RAM bank, or RTC register from 0x08
+	has_rtc: bool,
+	is_mbc30: bool,							// Up to 4 MiB of ROM and 64 KiB of RAM
+	rtc_last_update_timestamp: SystemTime,
+	rtc_registers: [Duration; 2],			// Running and latched
+	rtc_latch_prev_value: u8,
+	rtc_is_latched: bool,
+	rtc_is_halted: bool,
+	emulated_clock: Option<Duration>
+}
+
+impl Mbc3 {
+	pub fn create(rom_banks: &[RomBank]) -> Box<dyn Mapper> {
+		Box::new(Mbc3 {
+			ram_enable: false,
+			current_2d_rom_bank: 0x01,
+			current_ram_bank: 0x00,
+			has_rtc: matches!(rom_banks[0][0x147], 0x0F | 0x10),
+			is_mbc30: rom_banks.len() == 0x100 || rom_banks[0][0x149] == 0x05,
+			rtc_last_update_timestamp: UNIX_EPOCH,
+			rtc_registers: [Duration::from_secs(0); 2],
+			rtc_latch_prev_value: 0xFF,
+			rtc_is_latched: false,
+			rtc_is_halted: false,
+			emulated_clock: None
+		})
+	}
+	fn now(&self) -> SystemTime {
+		match self.emulated_clock {
+			Some(emulated_time) => UNIX_EPOCH + emulated_time,
+			None => SystemTime::now()
+		}
+	}
+	/* Time since the last RTC update, none if the clock went backwards (e.g. a state saved in wall-clock time loaded while running a movie) */
+	fn elapsed_time(&self) -> Duration {
+		self.now().duration_since(self.rtc_last_update_timestamp).unwrap_or_default()
+	}
+	fn read_rtc(&self) -> u8 {
+		let elapsed_time = if self.rtc_is_halted || self.rtc_is_latched {Duration::new(0, 0)} else {self.elapsed_time()};
+		match self.current_ram_bank {
+			0x08 => {((self.rtc_registers[1] + elapsed_time).as_secs() % 60) as u8}
+			0x09 => {(((self.rtc_registers[1] + elapsed_time).as_secs() / 60) % 60) as u8}
+			0x0A => {(((self.rtc_registers[1] + elapsed_time).as_secs() / 3600) % 24) as u8}
+			0x0B => {(((self.rtc_registers[1] + elapsed_time).as_secs() / 86400) % 256) as u8}
+			_	 => {
+				let nb_256days_periods_elapsed = (self.rtc_registers[1] + elapsed_time).as_secs() / 22118400;
+				(if nb_256days_periods_elapsed >= 2 {1} else {0} as u8) << 7 | (self.rtc_is_halted as u8) << 6 | (nb_256days_periods_elapsed % 2) as u8
+			}
+		}
+	}
+	fn write_rtc(&mut self, data: u8) {
+		if !self.rtc_is_halted {
+			self.rtc_registers[0] += self.elapsed_time();
+		}
+		self.rtc_last_update_timestamp = self.now();
+		match self.current_ram_bank {
+			0x08 => {
+				self.rtc_registers[0] -= Duration::from_secs(self.rtc_registers[0].as_secs() % 60);
+				self.rtc_registers[0] += Duration::from_secs(data as u64);
+			}
+			0x09 => {
+				self.rtc_registers[0] -= Duration::from_secs((self.rtc_registers[0].as_secs() / 60) % 60);
+				self.rtc_registers[0] += Duration::from_secs(data as u64 * 60);
+			}
+			0x0A => {
+				self.rtc_registers[0] -= Duration::from_secs((self.rtc_registers[0].as_secs() / 3600) % 24);
+				self.rtc_registers[0] += Duration::from_secs(data as u64 * 3600);
+			}
+			0x0B => {
+				self.rtc_registers[0] -= Duration::from_secs((self.rtc_registers[0].as_secs() / 86400) % 256);
+				self.rtc_registers[0] += Duration::from_secs(data as u64 * 86400);
+			}
+			_ => {
+				let is_carry = data & (1 << 7) != 0;
+				let is_halt = data & (1 << 6) != 0;
+				let data = data & 1;
+				self.rtc_registers[0] = Duration::from_secs(self.rtc_registers[0].as_secs() % 44236800);
+				self.rtc_registers[0] += Duration::from_secs(if is_carry {1} else {0} * 44236800);
+				self.rtc_registers[0] -= Duration::from_secs((self.rtc_registers[0].as_secs() / 22118400) % 2);
+				self.rtc_registers[0] += Duration::from_secs(data as u64 * 22118400);
+				self.rtc_is_halted = is_halt
+			}
+		}
+		if !self.rtc_is_latched {
+			self.rtc_registers[1] = self.rtc_registers[0];
+		}
+	}
+	/* Running and latched registers, then the timestamp of the last update */
+	fn save_rtc(&self) -> Vec<u8> {
+		let mut rtc_contents = Vec::new();
+		for rtc_register in self.rtc_registers {
+			rtc_contents.push((rtc_register.as_secs() % 60) as u8);
+			rtc_contents.push(((rtc_register.as_secs() / 60) % 60) as u8);
+			rtc_contents.push(((rtc_register.as_secs() / 3600) % 24) as u8);
+			rtc_contents.push(((rtc_register.as_secs() / 86400) % 256) as u8);
+			let nb_256days_periods_elapsed = rtc_register.as_secs() / 22118400;
+			rtc_contents.push((nb_256days_periods_elapsed % 2) as u8 | (self.rtc_is_halted as u8) << 6 | if nb_256days_periods_elapsed >= 2 {1} else {0} << 7);
+		}
+		let unix_timestamp = self.rtc_last_update_timestamp.duration_since(UNIX_EPOCH).expect("We are before epoch!").as_secs();
+		rtc_contents.extend(unix_timestamp.to_le_bytes());
+		rtc_contents
+	}
+	fn load_rtc(&mut self, rtc_contents: &[u8]) {
+		for (i, rtc_register) in self.rtc_registers.iter_mut().enumerate() {
+			let rtc_contents = &rtc_contents[i * 5..];
+			*rtc_register = Duration::from_secs(rtc_contents[0] as u64);
+			*rtc_register += Duration::from_secs((rtc_contents[1] as u64) * 60);
+			*rtc_register += Duration::from_secs((rtc_contents[2] as u64) * 3600);
+			*rtc_register += Duration::from_secs((rtc_contents[3] as u64) * 86400);
+			*rtc_register += Duration::from_secs((((rtc_contents[4] & 1) + (rtc_contents[4] >> 7)) as u64) * 22118400);
+		}
+		self.rtc_is_halted = rtc_contents[4] & (1 << 6) != 0;
+		self.rtc_is_latched = self.rtc_registers[0] != self.rtc_registers[1];
+		self.rtc_last_update_timestamp = Mbc3::rtc_timestamp(rtc_contents).unwrap_or_else(|| self.now());
+	}
+	fn rtc_timestamp(rtc_contents: &[u8]) -> Option<SystemTime> {
+		UNIX_EPOCH.checked_add(Duration::from_secs(u64::from_le_bytes(rtc_contents[0x0A..RTC_NB_BYTES].try_into().unwrap())))
+	}
+}
+
+impl Mapper for Mbc3 {
+	fn rom_bank_at(&self, address: u16) -> usize {
+		if address < 0x4000 {0} else {self.current_2d_rom_bank}
+	}
+	fn write_register(&mut self, address: u16, data: u8) {
+		match address {
+			0x0000..=0x1FFF => self.ram_enable = data & 0x0F == 0x0A,
+			0x2000..=0x2FFF => {
+				let mut data = data & if self.is_mbc30 {0xFF} else {0x7F};
+				if data == 0x00 {data = 0x01}
+				self.current_2d_rom_bank = data as usize
+			}
+			0x4000..=0x5FFF if data <= if self.is_mbc30 {0x07} else {0x03} || ((0x08..=0x0C).contains(&data) && self.has_rtc) => {
+				self.current_ram_bank = data as usize
+			}
+			0x6000..=0x7FFF if self.has_rtc => {
+				let is_latch_started = self.rtc_latch_prev_value == 0x00;
+				self.rtc_latch_prev_value = data;
+				if is_latch_started && data == 0x01 {
+					if !self.rtc_is_halted {
+						self.rtc_registers[0] += self.elapsed_time()
+					};
+					self.rtc_last_update_timestamp = self.now();
+					self.rtc_registers[1] = self.rtc_registers[0];
+					self.rtc_is_latched = !self.rtc_is_latched
+				}
+			}
+			_ => {}
+		}
+	}
+	fn read_ram(&self, ram_banks: &[RamBank], address: u16) -> u8 {
+		if !self.ram_enable {0xFF}
+		else if self.current_ram_bank >= 0x08 {self.read_rtc()}
+		else {read_ram_bank(ram_banks, self.current_ram_bank, address)}
+	}
+	fn write_ram(&mut self, ram_banks: &mut [RamBank], address: u16, data: u8) {
+		if !self.ram_enable {return}
+		if self.current_ram_bank >= 0x08 {self.write_rtc(data)}
+		else {write_ram_bank(ram_banks, self.current_ram_bank, address, data)}
+	}
+	fn save_state(&self) -> Vec<u8> {
+		let mut state = vec![self.ram_enable as u8, self.current_2d_rom_bank as u8, self.current_ram_bank as u8, self.rtc_latch_prev_value];
+		state.extend(self.save_rtc());
+		state
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+		let &[ram_enable, current_2d_rom_bank, current_ram_bank, rtc_latch_prev_value, ref rtc_contents @ ..] = state else {
+			return Err(invalid_state_error("MBC3", state));
+		};
+		if rtc_contents.len() != RTC_NB_BYTES {return Err(invalid_state_error("MBC3", state))}
+		if Mbc3::rtc_timestamp(rtc_contents).is_none_or(|timestamp| timestamp > self.now()) {
+			return Err("MBC3 state with an RTC updated after the current time".to_string());
+		}
+		self.ram_enable = ram_enable != 0;
+		self.current_2d_rom_bank = current_2d_rom_bank as usize;
+		self.current_ram_bank = current_ram_bank as usize;
+		self.rtc_latch_prev_value = rtc_latch_prev_value;
+		self.load_rtc(rtc_contents);
+		Ok(())
+	}
+	fn save_battery(&self, ram_banks: &[RamBank]) -> Vec<u8> {
+		let mut sav_contents = ram_banks.concat();
+		if self.has_rtc {
+			sav_contents.extend(self.save_rtc());
+		}
+		sav_contents
+	}
+	fn load_battery(&mut self, ram_banks: &mut [RamBank], contents: &[u8]) {
+		if self.has_rtc && contents.len() >= RTC_NB_BYTES {
+			let (ram_contents, rtc_contents) = contents.split_at(contents.len() - RTC_NB_BYTES);
+			super::load_ram(ram_banks, ram_contents);
+			self.load_rtc(rtc_contents);
+		} else {
+			super::load_ram(ram_banks, contents);
+		}
+	}
+	fn make_deterministic(&mut self) {
+		self.rtc_registers = [Duration::from_secs(0); 2];
+		self.rtc_is_latched = false;
+		self.rtc_is_halted = false;
+		self.rtc_last_update_timestamp = UNIX_EPOCH;
+		self.emulated_clock = Some(Duration::from_secs(0));
+	}
+	fn advance_emulated_clock(&mut self, duration: Duration) {
+		if let Some(emulated_time) = &mut self.emulated_clock {
+			*emulated_time += duration;
+		}
+	}
+}
@@ -0,0 +1,75 @@
+use super::{Mapper, RamBank, RomBank, read_ram_bank, write_ram_bank, invalid_state_error};
+
+/* Up to 8 MiB of ROM and 128 KiB of RAM, bank 0 being selectable in the switchable area */
+pub struct Mbc5 {
+	ram_enable: bool,
+	current_2d_rom_bank: usize,
+	rom_bank_9th_bit: usize,
+	current_ram_bank: usize,
+	has_rumble: bool,
+	is_motor_on: bool,
+	motor_event: Option<bool>				// Motor state change not yet forwarded to the input subsystem
+}
+
+impl Mbc5 {
+	pub fn create(rom_banks: &[RomBank]) -> Box<dyn Mapper> {
+		Box::new(Mbc5 {
+			ram_enable: false,
+			current_2d_rom_bank: 0x01,
+			rom_bank_9th_bit: 0x00,
+			current_ram_bank: 0x00,
+			has_rumble: matches!(rom_banks[0][0x147], 0x1C..=0x1E),
+			is_motor_on: false,
+			motor_event: None
+		})
+	}
+}
+
+impl Mapper for Mbc5 {
+	fn rom_bank_at(&self, address: u16) -> usize {
+		if address < 0x4000 {0} else {self.rom_bank_9th_bit << 8 | self.current_2d_rom_bank}
+	}
+	fn write_register(&mut self, address: u16, data: u8) {
+		match address {
+			0x0000..=0x1FFF => self.ram_enable = data & 0x0F == 0x0A,
+			0x2000..=0x2FFF => self.current_2d_rom_bank = data as usize,
+			0x3000..=0x3FFF => self.rom_bank_9th_bit = data as usize & 0x01,
+			0x4000..=0x5FFF if self.has_rumble => {			// Bit 3 drives the motor instead of selecting RAM
+				if self.is_motor_on != (data & 0x08 != 0) {
+					self.is_motor_on = !self.is_motor_on;
+					self.motor_event = Some(self.is_motor_on);
+				}
+				self.current_ram_bank = data as usize & 0x07
+			}
+			0x4000..=0x5FFF if data <= 0x0F => self.current_ram_bank = data as usize,
+			_ => {}
+		}
+	}
+	fn read_ram(&self, ram_banks: &[RamBank], address: u16) -> u8 {
+		if !self.ram_enable {return 0xFF}
+		read_ram_bank(ram_banks, self.current_ram_bank, address)
+	}
+	fn write_ram(&mut self, ram_banks: &mut [RamBank], address: u16, data: u8) {
+		if self.ram_enable {write_ram_bank(ram_banks, self.current_ram_bank, address, data)}
+	}
+	fn save_state(&self) -> Vec<u8> {
+		vec![self.ram_enable as u8, self.current_2d_rom_bank as u8, self.rom_bank_9th_bit as u8, self.current_ram_bank as u8, self.is_motor_on as u8]
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+		let &[ram_enable, current_2d_rom_bank, rom_bank_9th_bit, current_ram_bank, is_motor_on] = state else {
+			return Err(invalid_state_error("MBC5", state));
+		};
+		self.ram_enable = ram_enable != 0;
+		self.current_2d_rom_bank = current_2d_rom_bank as usize;
+		self.rom_bank_9th_bit = rom_bank_9th_bit as usize;
+		self.current_ram_bank = current_ram_bank as usize;
+		if self.is_motor_on != (is_motor_on != 0) {
+			self.is_motor_on = is_motor_on != 0;
+			self.motor_event = Some(self.is_motor_on);
+		}
+		Ok(())
+	}
+	fn take_rumble_event(&mut self) -> Option<bool> {
+		self.motor_event.take()
+	}
+}
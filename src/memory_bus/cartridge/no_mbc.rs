@@ -0,0 +1,33 @@
+use super::{Mapper, RamBank, RomBank, read_ram_bank, write_ram_bank, invalid_state_error};
+
+/* 32 KiB of ROM, and up to 8 KiB of RAM wired directly */
+pub struct NoMbc;
+
+impl NoMbc {
+	pub fn new() -> Self {
+		NoMbc
+	}
+	pub fn create(_rom_banks: &[RomBank]) -> Box<dyn Mapper> {
+		Box::new(NoMbc::new())
+	}
+}
+
+impl Mapper for NoMbc {
+	fn rom_bank_at(&self, address: u16) -> usize {
+		(address / 0x4000) as usize
+	}
+	fn write_register(&mut self, _address: u16, _data: u8) {}
+	fn read_ram(&self, ram_banks: &[RamBank], address: u16) -> u8 {
+		read_ram_bank(ram_banks, 0, address)
+	}
+	fn write_ram(&mut self, ram_banks: &mut [RamBank], address: u16, data: u8) {
+		write_ram_bank(ram_banks, 0, address, data)
+	}
+	fn save_state(&self) -> Vec<u8> {
+		Vec::new()
+	}
+	fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+		if !state.is_empty() {return Err(invalid_state_error("ROM only", state))}
+		Ok(())
+	}
+}
@@ -0,0 +1,231 @@
+use crate::crc32::crc32;
+
+pub const PATCH_EXTENSIONS: [&str; 3] = ["ips", "bps", "ups"];
+
+const MAX_ROM_SIZE: usize = 0x800000;
+
+/* Applies an IPS, BPS or UPS patch, recognized from its magic, to the contents of a ROM */
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+	match patch {
+		[b'P', b'A', b'T', b'C', b'H', ..]	=> apply_ips(rom, &patch[5..]),
+		[b'B', b'P', b'S', b'1', ..]		=> apply_bps(rom, patch),
+		[b'U', b'P', b'S', b'1', ..]		=> apply_ups(rom, patch),
+		_ => Err("unknown patch format".to_string())
+	}
+}
+
+struct PatchReader<'a> {
+	data: &'a [u8],
+	pos: usize
+}
+
+impl<'a> PatchReader<'a> {
+	fn new(data: &'a [u8], pos: usize) -> Self {
+		PatchReader { data, pos }
+	}
+	fn is_at(&self, pos: usize) -> bool {
+		self.pos >= pos
+	}
+	fn bytes(&mut self, nb: usize) -> Result<&'a [u8], String> {
+		let bytes = self.data.get(self.pos..self.pos.saturating_add(nb)).ok_or("truncated patch")?;
+		self.pos += nb;
+		Ok(bytes)
+	}
+	fn byte(&mut self) -> Result<u8, String> {
+		Ok(self.bytes(1)?[0])
+	}
+	fn big_endian(&mut self, nb: usize) -> Result<usize, String> {
+		Ok(self.bytes(nb)?.iter().fold(0, |value, byte| value << 8 | *byte as usize))
+	}
+	/* BPS and UPS variable-length numbers, 7 bits at a time with an implicit +1 on every continuation */
+	fn number(&mut self) -> Result<usize, String> {
+		let mut value = 0usize;
+		let mut shift = 1usize;
+		loop {
+			let byte = self.byte()?;
+			value = ((byte & 0x7F) as usize).checked_mul(shift).and_then(|bits| value.checked_add(bits)).ok_or("number overflow in patch")?;
+			if byte & 0x80 != 0 {return Ok(value)}
+			shift = shift.checked_mul(0x80).ok_or("number overflow in patch")?;
+			value = value.checked_add(shift).ok_or("number overflow in patch")?;
+		}
+	}
+}
+
+/* Source, target and patch CRC32s, stored little-endian at the end of BPS and UPS patches */
+fn check_footer(rom: &[u8], patch: &[u8]) -> Result<u32, String> {
+	if patch.len() < 12 {return Err("truncated patch".to_string())}
+	let footer: Vec<u32> = patch[patch.len() - 12..].chunks(4).map(|crc| u32::from_le_bytes(crc.try_into().unwrap())).collect();
+	if crc32(&patch[..patch.len() - 4]) != footer[2] {
+		return Err("corrupted patch, its CRC32 does not match".to_string());
+	}
+	if crc32(rom) != footer[0] {
+		return Err(format!("the ROM CRC32 {:08X} does not match the source CRC32 {:08X} of the patch", crc32(rom), footer[0]));
+	}
+	Ok(footer[1])
+}
+
+fn check_sizes(rom: &[u8], source_size: usize, target_size: usize) -> Result<(), String> {
+	if source_size != rom.len() {
+		return Err(format!("the ROM is {} bytes, while the patch expects {}", rom.len(), source_size));
+	}
+	check_target_size(target_size)
+}
+
+fn check_target_size(target_size: usize) -> Result<(), String> {
+	if target_size > MAX_ROM_SIZE {
+		return Err(format!("the patched ROM would be {} bytes, more than the 8 MiB a cartridge can map", target_size));
+	}
+	Ok(())
+}
+
+fn check_target(target: &[u8], target_crc: u32) -> Result<(), String> {
+	if crc32(target) != target_crc {
+		return Err(format!("the patched ROM CRC32 {:08X} does not match the target CRC32 {:08X} of the patch", crc32(target), target_crc));
+	}
+	Ok(())
+}
+
+/* Records of a 3 bytes offset and a 2 bytes size, RLE when the size is 0, until "EOF" and an optional truncation size */
+fn apply_ips(rom: &[u8], records: &[u8]) -> Result<Vec<u8>, String> {
+	let mut target = rom.to_vec();
+	let mut reader = PatchReader::new(records, 0);
+	loop {
+		if reader.data[reader.pos..].starts_with(b"EOF") {
+			reader.pos += 3;
+			if !reader.is_at(records.len()) {
+				let truncated_size = reader.big_endian(3)?;
+				check_target_size(truncated_size)?;
+				target.resize(truncated_size, 0x00);
+			}
+			return Ok(target);
+		}
+		let offset = reader.big_endian(3)?;
+		let (data, size) = match reader.big_endian(2)? {
+			0x0000 => {
+				let size = reader.big_endian(2)?;
+				(vec![reader.byte()?; size], size)
+			}
+			size => (reader.bytes(size)?.to_vec(), size)
+		};
+		if target.len() < offset + size {
+			check_target_size(offset + size)?;
+			target.resize(offset + size, 0x00);
+		}
+		target[offset..offset + size].copy_from_slice(&data);
+	}
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+	let target_crc = check_footer(rom, patch)?;
+	let mut reader = PatchReader::new(patch, 4);
+	let source_size = reader.number()?;
+	let target_size = reader.number()?;
+	let metadata_size = reader.number()?;
+	reader.bytes(metadata_size)?;
+	check_sizes(rom, source_size, target_size)?;
+	let mut target = Vec::with_capacity(target_size);
+	let mut source_relative_offset = 0usize;
+	let mut target_relative_offset = 0usize;
+	while !reader.is_at(patch.len() - 12) {
+		let action = reader.number()?;
+		let length = (action >> 2) + 1;
+		if target.len().saturating_add(length) > target_size {
+			return Err(format!("the patch writes past the {} bytes it declares", target_size));
+		}
+		match action & 0x03 {
+			0 => {				// SourceRead
+				let output_offset = target.len();
+				target.extend_from_slice(rom.get(output_offset..output_offset + length).ok_or("patch reading past the ROM")?);
+			}
+			1 => target.extend_from_slice(reader.bytes(length)?),	// TargetRead
+			action => {			// SourceCopy, TargetCopy
+				let relative_offset = if action == 2 {&mut source_relative_offset} else {&mut target_relative_offset};
+				let delta = reader.number()?;
+				*relative_offset = if delta & 1 != 0 {relative_offset.checked_sub(delta >> 1)} else {relative_offset.checked_add(delta >> 1)}.ok_or("patch copying from before the start")?;
+				for _ in 0..length {
+					let byte = if action == 2 {rom.get(*relative_offset)} else {target.get(*relative_offset)};
+					target.push(*byte.ok_or("patch copying past the end")?);
+					*relative_offset += 1;
+				}
+			}
+		}
+	}
+	if target.len() != target_size {
+		return Err(format!("the patched ROM is {} bytes, while the patch declares {}", target.len(), target_size));
+	}
+	check_target(&target, target_crc)?;
+	Ok(target)
+}
+
+/* Hunks of bytes XORed with the ROM, each after a number of skipped bytes and ended by a 0 */
+fn apply_ups(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+	let target_crc = check_footer(rom, patch)?;
+	let mut reader = PatchReader::new(patch, 4);
+	let source_size = reader.number()?;
+	let target_size = reader.number()?;
+	check_sizes(rom, source_size, target_size)?;
+	let mut target = rom.to_vec();
+	target.resize(target_size, 0x00);
+	let mut output_offset = 0usize;
+	while !reader.is_at(patch.len() - 12) {
+		output_offset = output_offset.saturating_add(reader.number()?);
+		loop {
+			let byte = reader.byte()?;
+			if let Some(target_byte) = target.get_mut(output_offset) {
+				*target_byte ^= byte;
+			}
+			output_offset = output_offset.saturating_add(1);
+			if byte == 0x00 {break}
+		}
+	}
+	check_target(&target, target_crc)?;
+	Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::apply;
+	use crate::crc32::crc32;
+
+	fn with_footer(rom: &[u8], target: &[u8], mut patch: Vec<u8>) -> Vec<u8> {
+		patch.extend(crc32(rom).to_le_bytes());
+		patch.extend(crc32(target).to_le_bytes());
+		patch.extend(crc32(&patch).to_le_bytes());
+		patch
+	}
+
+	#[test]
+	fn test_ips() {
+		let rom = [0x00, 0x01, 0x02, 0x03];
+		let patch = b"PATCH\x00\x00\x01\x00\x02\xAA\xBB\x00\x00\x05\x00\x00\x00\x02\xCCEOF";
+		assert_eq!(apply(&rom, patch), Ok(vec![0x00, 0xAA, 0xBB, 0x03, 0x00, 0xCC, 0xCC]));
+		let truncating_patch = b"PATCHEOF\x00\x00\x02";
+		assert_eq!(apply(&rom, truncating_patch), Ok(vec![0x00, 0x01]));
+		assert!(apply(&rom, b"PATCH\x00\x00\x01\x00\x02\xAA").is_err());
+		assert!(apply(&rom, b"PATCH\x7F\xFF\xFF\x00\x00\x00\x02\xCCEOF").is_err());
+	}
+
+	#[test]
+	fn test_bps() {
+		let rom = [0x10, 0x11, 0x12, 0x13];
+		let target = [0x10, 0x11, 0xAA, 0x12, 0x13, 0xAA, 0x12];
+		/* SourceRead 2, TargetRead 1, SourceCopy 2 from +2, TargetCopy 2 from +2 */
+		let patch = with_footer(&rom, &target, vec![b'B', b'P', b'S', b'1', 0x84, 0x87, 0x80, 0x84, 0x81, 0xAA, 0x86, 0x84, 0x87, 0x84]);
+		assert_eq!(apply(&rom, &patch), Ok(target.to_vec()));
+		assert!(apply(&[0x10, 0x11, 0x12, 0x14], &patch).is_err());
+		let overflowing_patch = with_footer(&rom, &target, [b"BPS1".to_vec(), vec![0x7F; 10], vec![0xFF]].concat());
+		assert!(apply(&rom, &overflowing_patch).is_err());
+	}
+
+	#[test]
+	fn test_ups() {
+		let rom = [0x10, 0x11, 0x12, 0x13];
+		let target = [0x10, 0x11, 0x02, 0x13, 0x05];
+		/* Skip 2, XOR 0x10 then end, skip 0, XOR 0x05 then end */
+		let patch = with_footer(&rom, &target, vec![b'U', b'P', b'S', b'1', 0x84, 0x85, 0x82, 0x10, 0x00, 0x80, 0x05, 0x00]);
+		assert_eq!(apply(&rom, &patch), Ok(target.to_vec()));
+		let mut corrupted_patch = patch.clone();
+		corrupted_patch[7] ^= 0xFF;
+		assert!(apply(&rom, &corrupted_patch).is_err());
+	}
+}
@@ -1,4 +1,4 @@
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
 pub struct InputState {
 	pub is_up_pressed: bool,
 	pub is_down_pressed: bool,
@@ -10,9 +10,38 @@ pub struct InputState {
 	pub is_select_pressed: bool,
 }
 
+impl std::convert::From<InputState> for u8 {
+	fn from(state: InputState) -> u8 {
+		(state.is_down_pressed as u8)	<< 7 |
+		(state.is_up_pressed as u8)		<< 6 |
+		(state.is_left_pressed as u8)	<< 5 |
+		(state.is_right_pressed as u8)	<< 4 |
+		(state.is_start_pressed as u8)	<< 3 |
+		(state.is_select_pressed as u8)	<< 2 |
+		(state.is_b_pressed as u8)		<< 1 |
+		(state.is_a_pressed as u8)
+	}
+}
+
+impl std::convert::From<u8> for InputState {
+	fn from(byte: u8) -> Self {
+		InputState {
+			is_down_pressed:	byte & (1 << 7) != 0,
+			is_up_pressed:		byte & (1 << 6) != 0,
+			is_left_pressed:	byte & (1 << 5) != 0,
+			is_right_pressed:	byte & (1 << 4) != 0,
+			is_start_pressed:	byte & (1 << 3) != 0,
+			is_select_pressed:	byte & (1 << 2) != 0,
+			is_b_pressed:		byte & (1 << 1) != 0,
+			is_a_pressed:		byte & 1 != 0
+		}
+	}
+}
+
 #[derive(Debug)]
 pub struct InputMemory {
 	joypad_state: InputState,
+	injected_state: InputState,
 
 	is_reading_action_buttons: bool,
 	is_reading_direction_buttons: bool,
@@ -21,16 +50,8 @@ pub struct InputMemory {
 impl InputMemory {
 	pub fn new() -> Self {
 		InputMemory {
-			joypad_state: InputState {
-				is_up_pressed: false,
-				is_down_pressed: false,
-				is_left_pressed: false,
-				is_right_pressed: false,
-				is_a_pressed: false,
-				is_b_pressed: false,
-				is_start_pressed: false,
-				is_select_pressed: false
-			},
+			joypad_state: InputState::default(),
+			injected_state: InputState::default(),
 			is_reading_action_buttons: false,
 			is_reading_direction_buttons: false,
 		}
@@ -48,18 +69,19 @@ impl InputMemory {
 		}
 	}
 	pub fn read(&self) -> u8 {
-		let mut res = (self.is_reading_action_buttons as u8) << 5 | (self.is_reading_direction_buttons as u8) << 4;
+		let joypad_state = InputState::from(u8::from(self.joypad_state) | u8::from(self.injected_state));
+		let mut res = (!self.is_reading_action_buttons as u8) << 5 | (!self.is_reading_direction_buttons as u8) << 4;		// Selection bits are active low
 		if self.is_reading_action_buttons && !self.is_reading_direction_buttons {
-			res |= (!self.joypad_state.is_start_pressed as u8)	<< 3
-				| (!self.joypad_state.is_select_pressed as u8)	<< 2
-				| (!self.joypad_state.is_b_pressed as u8)		<< 1
-				| (!self.joypad_state.is_a_pressed as u8);
+			res |= (!joypad_state.is_start_pressed as u8)	<< 3
+				| (!joypad_state.is_select_pressed as u8)	<< 2
+				| (!joypad_state.is_b_pressed as u8)		<< 1
+				| (!joypad_state.is_a_pressed as u8);
 
 		} else if self.is_reading_direction_buttons && !self.is_reading_action_buttons {
-			res |= (!self.joypad_state.is_down_pressed as u8)	<< 3
-				| (!self.joypad_state.is_up_pressed as u8)		<< 2
-				| (!self.joypad_state.is_left_pressed as u8)	<< 1
-				| (!self.joypad_state.is_right_pressed as u8);
+			res |= (!joypad_state.is_down_pressed as u8)	<< 3
+				| (!joypad_state.is_up_pressed as u8)		<< 2
+				| (!joypad_state.is_left_pressed as u8)	<< 1
+				| (!joypad_state.is_right_pressed as u8);
 		} else {
 			res |= 0x0F
 		}
@@ -68,4 +90,10 @@ impl InputMemory {
 	pub fn update(&mut self, input_state: &InputState) {
 		self.joypad_state = *input_state;
 	}
+	pub fn inject(&mut self, input_state: &InputState) {
+		self.injected_state = *input_state;
+	}
+	pub fn injected_state(&self) -> InputState {
+		self.injected_state
+	}
 }
\ No newline at end of file
@@ -0,0 +1,60 @@
+use std::fmt::Display;
+
+/* Views over the address space, or directly over one of its backing stores regardless of the current banking */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryRegion {
+	Bus, RomBank(usize), RamBank(usize), Vram(usize), Wram(usize), Oam, Hram
+}
+
+impl MemoryRegion {
+	pub fn base_address(&self) -> u16 {
+		match self {
+			MemoryRegion::Bus			=> 0x0000,
+			MemoryRegion::RomBank(0)	=> 0x0000,
+			MemoryRegion::RomBank(_)	=> 0x4000,
+			MemoryRegion::RamBank(_)	=> 0xA000,
+			MemoryRegion::Vram(_)		=> 0x8000,
+			MemoryRegion::Wram(0)		=> 0xC000,
+			MemoryRegion::Wram(_)		=> 0xD000,
+			MemoryRegion::Oam			=> 0xFE00,
+			MemoryRegion::Hram			=> 0xFF80
+		}
+	}
+	pub fn size(&self) -> usize {
+		match self {
+			MemoryRegion::Bus			=> 0x10000,
+			MemoryRegion::RomBank(_)	=> 0x4000,
+			MemoryRegion::RamBank(_)	=> 0x2000,
+			MemoryRegion::Vram(_)		=> 0x2000,
+			MemoryRegion::Wram(_)		=> 0x1000,
+			MemoryRegion::Oam			=> 0x00A0,
+			MemoryRegion::Hram			=> 0x007F
+		}
+	}
+	/* Backing store holding a bank-qualified address, e.g. "03:4567" */
+	pub fn from_bank_address(bank: usize, address: u16) -> Option<Self> {
+		match address {
+			0x0000..=0x3FFF	=> Some(MemoryRegion::RomBank(bank)),
+			0x4000..=0x7FFF	=> Some(MemoryRegion::RomBank(bank.max(1))),
+			0x8000..=0x9FFF	=> Some(MemoryRegion::Vram(bank)),
+			0xA000..=0xBFFF	=> Some(MemoryRegion::RamBank(bank)),
+			0xC000..=0xCFFF	=> Some(MemoryRegion::Wram(0)),
+			0xD000..=0xDFFF	=> Some(MemoryRegion::Wram(bank.max(1))),
+			_				=> None
+		}
+	}
+}
+
+impl Display for MemoryRegion {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			MemoryRegion::Bus			=> write!(f, "Address space"),
+			MemoryRegion::RomBank(bank)	=> write!(f, "ROM bank {:02X}", bank),
+			MemoryRegion::RamBank(bank)	=> write!(f, "RAM bank {:02X}", bank),
+			MemoryRegion::Vram(bank)	=> write!(f, "VRAM bank {}", bank),
+			MemoryRegion::Wram(bank)	=> write!(f, "WRAM bank {}", bank),
+			MemoryRegion::Oam			=> write!(f, "OAM"),
+			MemoryRegion::Hram			=> write!(f, "HRAM")
+		}
+	}
+}
@@ -16,6 +16,14 @@ pub enum PixelColour {
 	RGBColour(u8, u8, u8)
 }
 
+/* Kinds of DMG OAM corruption, depending on how the CPU put an 0xFE00-0xFEFF address on the bus */
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OamCorruption {
+	Write,					// Write access, or the IDU alone incrementing/decrementing a register pair
+	Read,
+	ReadIncrease			// Read while the IDU increments/decrements the address
+}
+
 #[derive(Copy, Clone)]
 pub struct CGBTileAttr {
 	pub bg_oam_priority: bool,
@@ -57,6 +65,7 @@ impl OAMObject {
 pub struct PPUMemory {
 	pub is_vram_locked: bool,
 	pub is_oam_locked: bool,
+	pub oam_scan_row: Option<usize>,		// Row of 8 bytes the PPU is about to read during the OAM search
 
 	video_ram: [u8; 0x2000],
 	video_ram2: [u8; 0x2000],
@@ -68,6 +77,9 @@ pub struct PPUMemory {
 	pub lyc_ram: u8,						// 0xFF45
 	pub oam_dma_reg: u8,					// 0xFF46
 	pub oam_dma_is_active: bool,
+	pub oam_dma_is_requested: bool,			// Written, the transfer (re)starting on the next M-cycle
+	pub oam_dma_src_address: u16,
+	pub oam_dma_bus_data: u8,				// Last byte transferred, which the CPU reads on the bus used by the DMA
 	bgp_ram: u8,							// 0xFF47
 	obp_ram: [u8; 2],						// 0xFF48 - 0xFF49
 	pub wy_ram: u8,							// 0xFF4A
@@ -77,6 +89,7 @@ pub struct PPUMemory {
 	pub vram_dma_stat: u8,					// 0xFF55 & 0x7F
 	pub vram_dma_is_hblank_mode: bool,		// 0xFF55 & 0x80 in write mode
 	pub vram_dma_is_active: bool,			// 0xFF55 & 0x80 in read mode
+	pub is_hblank_dma_pending: bool,		// Set for the length of HBlank, until a block gets transferred
 
 	pub tiles: [[Tile; 0x80]; 3],			// 0x8000 - 0x97FF
 	pub tiles2: [[Tile; 0x80]; 3],			// idem
@@ -99,14 +112,16 @@ pub struct PPUMemory {
 	pub ppu_mode_0_interrupt_enable: bool,	// 0xFF41 & (1 << 3)
 	pub lyc_match_flag: bool,				// 0xFF41 & (1 << 2)
 	pub ppu_mode_id: u8,					// 0xFF41 & 0x03
+	pub is_stat_write_glitch_pending: bool,
 	pub bg_palette: [PixelColour; 4],		// 0xFF47
 	pub cgb_bg_palettes: [[PixelColour; 4]; 8],
 	pub cgb_bg_palette_autoincr: bool,
 	pub cgb_bg_palette_addr: u8,
 	pub obj_palettes: [[PixelColour; 3]; 2],	// 0xFF48 - 0xFF49
-	pub cgb_obj_palettes: [[PixelColour; 3]; 8],
+	pub cgb_obj_palettes: [[PixelColour; 4]; 8],		// Colour 0 is transparent, but still gets stored
 	pub cgb_obj_palette_autoincr: bool,
-	pub cgb_obj_palette_addr: u8
+	pub cgb_obj_palette_addr: u8,
+	pub is_obj_priority_by_coordinate: bool		// 0xFF6C & 0x01, the DMG object priority
 }
 
 impl PPUMemory {
@@ -114,6 +129,7 @@ impl PPUMemory {
 		PPUMemory {
 			is_vram_locked: false,
 			is_oam_locked: false,
+			oam_scan_row: None,
 			video_ram: [0; 0x2000],
 			video_ram2: [0; 0x2000],
 			oam: [0xFF; 0xA0],
@@ -124,6 +140,9 @@ impl PPUMemory {
 			lyc_ram: 0,
 			oam_dma_reg: 0x00,
 			oam_dma_is_active: false,
+			oam_dma_is_requested: false,
+			oam_dma_src_address: 0x0000,
+			oam_dma_bus_data: 0xFF,
 			bgp_ram: 0,
 			obp_ram: [0; 2],
 			wy_ram: 0,
@@ -133,6 +152,7 @@ impl PPUMemory {
 			vram_dma_stat: 0xFF,
 			vram_dma_is_hblank_mode: false,
 			vram_dma_is_active: false,
+			is_hblank_dma_pending: false,
 			tiles: [ [[[TilePixel::Zero;8];8];0x80]; 3],
 			tiles2: [ [[[TilePixel::Zero;8];8];0x80]; 3],
 			bg_tilemap0: [[0; 0x20]; 0x20],
@@ -154,14 +174,16 @@ impl PPUMemory {
 			ppu_mode_0_interrupt_enable: false,
 			lyc_match_flag: false,
 			ppu_mode_id: 0,
+			is_stat_write_glitch_pending: false,
 			bg_palette: [PixelColour::White, PixelColour::LightGray, PixelColour::DarkGray, PixelColour::Black],
 			cgb_bg_palettes: [[PixelColour::RGBColour(0x00, 0x00, 0x00), PixelColour::RGBColour(0x00, 0x00, 0x00), PixelColour::RGBColour(0x00, 0x00, 0x00), PixelColour::RGBColour(0x00, 0x00, 0x00)]; 8],
 			cgb_bg_palette_addr: 0x00,
 			cgb_bg_palette_autoincr: false,
 			obj_palettes: [[PixelColour::LightGray, PixelColour::DarkGray, PixelColour::Black]; 2],
-			cgb_obj_palettes: [[PixelColour::RGBColour(0x00, 0x00, 0x00), PixelColour::RGBColour(0x00, 0x00, 0x00), PixelColour::RGBColour(0x00, 0x00, 0x00)]; 8],
+			cgb_obj_palettes: [[PixelColour::RGBColour(0x00, 0x00, 0x00); 4]; 8],
 			cgb_obj_palette_addr: 0x00,
-			cgb_obj_palette_autoincr: false
+			cgb_obj_palette_autoincr: false,
+			is_obj_priority_by_coordinate: false
 		}
 	}
 	fn write_tile(&mut self, floored_even_addr: usize, is_to_bank1: bool) {
@@ -322,8 +344,8 @@ impl PPUMemory {
 			self.cgb_obj_palette_autoincr = data & 0x80 != 0;
 			self.cgb_obj_palette_addr = data & 0x3F;
 		} else if address == 0xFF6B {
-			if !self.is_vram_locked && self.cgb_obj_palette_addr % 8 / 2 != 0 {
-				let selected_pixel_colour = &mut self.cgb_obj_palettes[self.cgb_obj_palette_addr as usize / 8][self.cgb_obj_palette_addr as usize % 8 / 2 - 1];
+			if !self.is_vram_locked {
+				let selected_pixel_colour = &mut self.cgb_obj_palettes[self.cgb_obj_palette_addr as usize / 8][self.cgb_obj_palette_addr as usize % 8 / 2];
 				if let PixelColour::RGBColour(r, g, b) = selected_pixel_colour {
 					if self.cgb_obj_palette_addr % 2 == 0 {
 						*r = (data & 0x1F) * 8;
@@ -339,6 +361,20 @@ impl PPUMemory {
 			if self.cgb_obj_palette_autoincr {self.cgb_obj_palette_addr = (self.cgb_obj_palette_addr + 1) & 0x3F}
 		}
 	}
+	/* Debugger accesses, ignoring the locks held by the PPU */
+	pub fn peek(&self, address: usize, is_from_bank1: bool) -> u8 {
+		match address {
+			0x8000..=0x9FFF	=> if is_from_bank1 {self.video_ram2[address - 0x8000]} else {self.video_ram[address - 0x8000]},
+			_				=> self.oam[address - 0xFE00]
+		}
+	}
+	pub fn poke(&mut self, address: usize, data: u8, is_to_bank1: bool) {
+		let locks = (self.is_vram_locked, self.is_oam_locked);
+		self.is_vram_locked = false;
+		self.is_oam_locked = false;
+		self.write(address, data, is_to_bank1);
+		(self.is_vram_locked, self.is_oam_locked) = locks;
+	}
 	pub fn read(&self, address: usize, is_from_bank1: bool) -> u8 {
 		if address < 0xA000 {
 			if self.is_vram_locked {
@@ -388,8 +424,8 @@ impl PPUMemory {
 		}
 		else if address == 0xFF6A { ((self.cgb_obj_palette_autoincr as u8) << 7) | self.cgb_obj_palette_addr }
 		else {	// address == 0xFF6B
-			if self.is_vram_locked || self.cgb_obj_palette_addr % 8 / 2 == 0 {0xFF}
-			else if let PixelColour::RGBColour(r, g, b) = self.cgb_obj_palettes[self.cgb_obj_palette_addr as usize / 8][self.cgb_obj_palette_addr as usize % 8 / 2 - 1] {
+			if self.is_vram_locked {0xFF}
+			else if let PixelColour::RGBColour(r, g, b) = self.cgb_obj_palettes[self.cgb_obj_palette_addr as usize / 8][self.cgb_obj_palette_addr as usize % 8 / 2] {
 				if self.cgb_obj_palette_addr % 2 == 0 {
 					(r / 8) | ((g / 8) << 5)
 				} else {
@@ -401,6 +437,46 @@ impl PPUMemory {
 			}
 		}
 	}
+	/* DMG OAM bug: the row being read by the OAM search gets glitched with the preceding one. The first row is never affected */
+	pub fn corrupt_oam(&mut self, corruption: OamCorruption) {
+		let row = match self.oam_scan_row {
+			Some(row) if self.is_oam_locked && (1..0x14).contains(&row) => row,
+			_ => return
+		};
+		if corruption == OamCorruption::ReadIncrease && (4..0x13).contains(&row) {
+			let (a, b, c, d) = (self.oam_word(row - 2, 0), self.oam_word(row - 1, 0), self.oam_word(row, 0), self.oam_word(row - 1, 2));
+			self.set_oam_word(row - 1, 0, (b & (a | c | d)) | (a & c & d));
+			for word in 0..4 {
+				let data = self.oam_word(row - 1, word);
+				self.set_oam_word(row, word, data);
+				self.set_oam_word(row - 2, word, data);
+			}
+		}
+		let (a, b, c) = (self.oam_word(row, 0), self.oam_word(row - 1, 0), self.oam_word(row - 1, 2));
+		self.set_oam_word(row, 0, match corruption {
+			OamCorruption::Write => ((a ^ c) & (b ^ c)) ^ c,
+			_ => b | (a & c)
+		});
+		for word in 1..4 {
+			let data = self.oam_word(row - 1, word);
+			self.set_oam_word(row, word, data);
+		}
+	}
+	fn oam_word(&self, row: usize, word: usize) -> u16 {
+		u16::from_le_bytes([self.oam[row * 8 + word * 2], self.oam[row * 8 + word * 2 + 1]])
+	}
+	fn set_oam_word(&mut self, row: usize, word: usize, data: u16) {
+		let [lsb, msb] = data.to_le_bytes();
+		self.poke(0xFE00 + row * 8 + word * 2, lsb, false);
+		self.poke(0xFE00 + row * 8 + word * 2 + 1, msb, false);
+	}
+	/* DMG compatibility mode: the DMG palettes pick a colour of CGB BG palette 0 and OBJ palettes 0 and 1 */
+	pub fn get_compat_bg_colour(&self, colour_index: usize) -> PixelColour {
+		self.cgb_bg_palettes[0][(self.bgp_ram >> (2 * colour_index)) as usize & 0x03]
+	}
+	pub fn get_compat_obj_colour(&self, palette: usize, colour_index: usize) -> PixelColour {
+		self.cgb_obj_palettes[palette][(self.obp_ram[palette] >> (2 * colour_index)) as usize & 0x03]
+	}
 	pub fn get_bg_tile_index(&self, x: u8, y: u8) -> u8{
 		if self.bg_using_secondary_tilemap {
 			self.bg_tilemap1[y as usize][x as usize]
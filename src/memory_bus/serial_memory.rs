@@ -0,0 +1,55 @@
+/* Serial port without any link partner, so that the bits shifted in are all 1s. Transfers only progress on the internal clock,
+8192 Hz (262144 Hz in CGB fast mode) taken from the system counter, which doubles along with it in double speed */
+pub struct SerialMemory {
+	pub sb_reg: u8,				// 0xFF01
+	is_transferring: bool,		// 0xFF02 & 0x80
+	is_fast_clock: bool,		// 0xFF02 & 0x02, CGB only
+	is_internal_clock: bool,	// 0xFF02 & 0x01
+	nb_bits_shifted: u8
+}
+
+impl SerialMemory {
+	pub fn new() -> Self {
+		SerialMemory {
+			sb_reg: 0x00,
+			is_transferring: false,
+			is_fast_clock: false,
+			is_internal_clock: false,
+			nb_bits_shifted: 0
+		}
+	}
+	fn clock_bit(&self) -> u16 {
+		if self.is_fast_clock {1 << 3} else {1 << 8}
+	}
+	/* Returns whether the transfer just completed, requesting the serial interrupt */
+	pub fn tick(&mut self, previous_counter: u16, counter: u16) -> bool {
+		if !self.is_transferring || !self.is_internal_clock || previous_counter & self.clock_bit() == 0 || counter & self.clock_bit() != 0 {
+			return false;
+		}
+		self.sb_reg = self.sb_reg << 1 | 0x01;
+		self.nb_bits_shifted += 1;
+		if self.nb_bits_shifted == 8 {
+			self.nb_bits_shifted = 0;
+			self.is_transferring = false;
+			return true;
+		}
+		false
+	}
+	pub fn read(&self, address: usize, is_cgb: bool) -> u8 {
+		match address {
+			0xFF01	=> self.sb_reg,
+			_		=> (self.is_transferring as u8) << 7 | 0x7C | ((self.is_fast_clock || !is_cgb) as u8) << 1 | self.is_internal_clock as u8
+		}
+	}
+	pub fn write(&mut self, address: usize, data: u8, is_cgb: bool) {
+		match address {
+			0xFF01	=> self.sb_reg = data,
+			_		=> {
+				self.is_transferring = data & 0x80 != 0;
+				self.is_fast_clock = is_cgb && data & 0x02 != 0;
+				self.is_internal_clock = data & 0x01 != 0;
+				self.nb_bits_shifted = 0;
+			}
+		}
+	}
+}
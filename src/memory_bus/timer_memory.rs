@@ -2,40 +2,82 @@
 pub enum CPUFreqDivider {
 	Ratio0x10, Ratio0x40, Ratio0x100, Ratio0x400
 }
+
+impl CPUFreqDivider {
+	/* Bit of the system counter whose falling edge increments TIMA */
+	pub fn counter_bit(&self) -> u16 {
+		match self {
+			CPUFreqDivider::Ratio0x10	=> 1 << 3,
+			CPUFreqDivider::Ratio0x40	=> 1 << 5,
+			CPUFreqDivider::Ratio0x100	=> 1 << 7,
+			CPUFreqDivider::Ratio0x400	=> 1 << 9
+		}
+	}
+}
+
+pub const TIMA_RELOAD_DELAY: u8 = 4;
+
 pub struct TimerMemory {
-	pub div_reg: u8,	// 0xFF04
+	pub system_counter: u16,	// 0xFF04 is its upper byte
 	pub tima_reg: u8,	// 0xFF05
 	pub tim_reg: u8,	// 0xFF06
 	tac_reg: u8,	// 0xFF07
 
 	pub timer_enabled: bool,
-	pub timer_freq_div: CPUFreqDivider
+	pub timer_freq_div: CPUFreqDivider,
+	pub overflow_delay: u8,		// Cycles TIMA reads 0x00 after overflowing, before TMA gets reloaded
+	pub reload_delay: u8		// Cycles following the reload, during which TMA writes go through to TIMA
 }
 
 impl TimerMemory {
 	pub fn new() -> Self {
 		TimerMemory{
-			div_reg: 0x00,
+			system_counter: 0x0000,
 			tima_reg: 0x00,
 			tim_reg: 0x00,
 			tac_reg: 0x00,
 			timer_enabled: false,
-			timer_freq_div: CPUFreqDivider::Ratio0x400
+			timer_freq_div: CPUFreqDivider::Ratio0x400,
+			overflow_delay: 0,
+			reload_delay: 0
+		}
+	}
+	/* TIMA is clocked by the selected system counter bit ANDed with the enable bit, so anything making
+	that signal fall increments it: counter ticks, but also DIV resets and TAC writes */
+	pub fn timer_signal(&self) -> bool {
+		self.timer_enabled && self.system_counter & self.timer_freq_div.counter_bit() != 0
+	}
+	pub fn increase_tima(&mut self) {
+		let (tima, has_overflowed) = self.tima_reg.overflowing_add(1);
+		self.tima_reg = tima;
+		if has_overflowed {
+			self.overflow_delay = TIMA_RELOAD_DELAY;
 		}
 	}
 	pub fn read(&self, address: usize) -> u8 {
 		match address {
-			0xFF04	=> self.div_reg,
+			0xFF04	=> (self.system_counter >> 8) as u8,
 			0xFF05	=> self.tima_reg,
 			0xFF06	=> self.tim_reg,
 			_		=> self.tac_reg
 		}
 	}
 	pub fn write(&mut self, address: usize, data: u8) {
+		let previous_signal = self.timer_signal();
 		match address {
-			0xFF04	=> self.div_reg = 0x00,
-			0xFF05	=> self.tima_reg = data,
-			0xFF06	=> self.tim_reg = data,
+			0xFF04	=> self.system_counter = 0x0000,
+			0xFF05	=> {
+				if self.reload_delay == 0 {					// Written while reloading, TMA wins
+					self.tima_reg = data;
+					self.overflow_delay = 0;				// Written before the reload, cancels it along with the interrupt
+				}
+			}
+			0xFF06	=> {
+				self.tim_reg = data;
+				if self.reload_delay != 0 {
+					self.tima_reg = data;
+				}
+			}
 			_		=> {
 				self.tac_reg = data;
 				self.timer_enabled = (data & (1 << 2)) != 0;
@@ -47,5 +89,8 @@ impl TimerMemory {
 				}
 			}
 		}
+		if previous_signal && !self.timer_signal() {
+			self.increase_tima();
+		}
 	}
-}
\ No newline at end of file
+}
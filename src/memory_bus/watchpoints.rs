@@ -0,0 +1,70 @@
+use std::cell::RefCell;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+	Read, Write, Access
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchSource {
+	Gdb, Debugger
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+	pub address: u16,
+	pub len: u16,
+	pub kind: WatchKind,
+	pub source: WatchSource
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchHit {
+	pub address: u16,
+	pub watchpoint: Watchpoint,
+	pub is_write: bool
+}
+
+impl Watchpoint {
+	fn matches(&self, address: u16, is_write: bool) -> bool {
+		address.wrapping_sub(self.address) < self.len.max(1) && match self.kind {
+			WatchKind::Read => !is_write,
+			WatchKind::Write => is_write,
+			WatchKind::Access => true
+		}
+	}
+}
+
+/* Only accesses done while armed are checked, so that debugger and DMA reads don't trigger them.
+Every matching hit of the current instruction is kept, for the debugger and the GDB stub to each take their own */
+pub struct Watchpoints {
+	pub list: Vec<Watchpoint>,
+	pub is_armed: bool,
+	hits: RefCell<Vec<WatchHit>>
+}
+
+impl Watchpoints {
+	pub fn new() -> Self {
+		Watchpoints {
+			list: Vec::new(),
+			is_armed: false,
+			hits: RefCell::new(Vec::new())
+		}
+	}
+	pub fn check(&self, address: u16, is_write: bool) {
+		if !self.is_armed || self.list.is_empty() {
+			return;
+		}
+		self.hits.borrow_mut().extend(self.list.iter()
+			.filter(|watchpoint| watchpoint.matches(address, is_write))
+			.map(|watchpoint| WatchHit { address, watchpoint: *watchpoint, is_write }));
+	}
+	pub fn clear_hits(&self) {
+		self.hits.borrow_mut().clear();
+	}
+	pub fn take_hits(&self, source: WatchSource) -> Vec<WatchHit> {
+		let (taken_hits, hits) = self.hits.take().into_iter().partition(|hit| hit.watchpoint.source == source);
+		self.hits.replace(hits);
+		taken_hits
+	}
+}
@@ -0,0 +1,169 @@
+use std::{fs, path::PathBuf, time::Duration};
+use crate::crc32::crc32;
+use crate::memory_bus::{MemoryBus, input_memory::InputState};
+
+const MOVIE_MAGIC: &[u8; 4] = b"GBMV";
+const MOVIE_VERSION: u8 = 0x01;
+const MOVIE_HEADER_SIZE: usize = 0x10;
+const MOVIE_FRAME_SIZE: usize = 0x05;
+const FRAME_DURATION: Duration = Duration::from_nanos(70224 * 1_000_000_000 / 4194304);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MovieMode {
+	Recording, Playback, Verification
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MovieHeader {
+	pub rom_crc32: u32,
+	pub is_cgb: bool,
+	pub force_dmg: bool,
+	pub start_state: Option<Vec<u8>>
+}
+
+pub struct Movie {
+	path: PathBuf,
+	pub mode: MovieMode,
+	pub header: MovieHeader,
+	inputs: Vec<InputState>,
+	frame_hashes: Vec<u32>,
+	current_frame: usize,
+	pub desync_frame: Option<usize>,
+	pub is_finished: bool
+}
+
+impl Drop for Movie {
+	fn drop(&mut self) {
+		if self.mode == MovieMode::Recording {
+			let mut movie_contents = Vec::with_capacity(MOVIE_HEADER_SIZE + self.inputs.len() * MOVIE_FRAME_SIZE);
+			movie_contents.extend_from_slice(MOVIE_MAGIC);
+			movie_contents.push(MOVIE_VERSION);
+			movie_contents.push(self.header.is_cgb as u8);
+			movie_contents.push(self.header.force_dmg as u8);
+			movie_contents.push(0x00);
+			movie_contents.extend_from_slice(&self.header.rom_crc32.to_le_bytes());
+			let start_state = self.header.start_state.clone().unwrap_or_default();
+			movie_contents.extend_from_slice(&(start_state.len() as u32).to_le_bytes());
+			movie_contents.extend_from_slice(&start_state);
+			for (input, hash) in self.inputs.iter().zip(self.frame_hashes.iter()) {
+				movie_contents.push(u8::from(*input));
+				movie_contents.extend_from_slice(&hash.to_le_bytes());
+			}
+			if let Err(err) = fs::write(&self.path, movie_contents) {
+				println!("Unable to write movie file at {}: {}", self.path.display(), err);
+			}
+		}
+	}
+}
+
+impl Movie {
+	pub fn new_recording(path: PathBuf, force_dmg: bool) -> Self {
+		Movie {
+			path,
+			mode: MovieMode::Recording,
+			header: MovieHeader { rom_crc32: 0, is_cgb: false, force_dmg, start_state: None },
+			inputs: Vec::new(),
+			frame_hashes: Vec::new(),
+			current_frame: 0,
+			desync_frame: None,
+			is_finished: false
+		}
+	}
+	pub fn load_from_path(path: PathBuf, mode: MovieMode) -> Result<Self, Box<dyn std::error::Error>> {
+		let movie_contents = fs::read(&path)?;
+		if movie_contents.len() < MOVIE_HEADER_SIZE || &movie_contents[0x00..0x04] != MOVIE_MAGIC {
+			return Err("not a GBMU movie file".into());
+		}
+		if movie_contents[0x04] != MOVIE_VERSION {
+			return Err(format!("unsupported movie version {}", movie_contents[0x04]).into());
+		}
+		let is_cgb = movie_contents[0x05] != 0;
+		let force_dmg = movie_contents[0x06] != 0;
+		let rom_crc32 = u32::from_le_bytes(movie_contents[0x08..0x0C].try_into()?);
+		let start_state_len = u32::from_le_bytes(movie_contents[0x0C..0x10].try_into()?) as usize;
+		let start_state = movie_contents.get(MOVIE_HEADER_SIZE..MOVIE_HEADER_SIZE.saturating_add(start_state_len)).ok_or("movie start state is truncated")?;
+		let frames = &movie_contents[MOVIE_HEADER_SIZE + start_state_len..];
+		if frames.len() % MOVIE_FRAME_SIZE != 0 {
+			return Err("movie file is truncated".into());
+		}
+		let mut inputs = Vec::with_capacity(frames.len() / MOVIE_FRAME_SIZE);
+		let mut frame_hashes = Vec::with_capacity(frames.len() / MOVIE_FRAME_SIZE);
+		for frame in frames.chunks_exact(MOVIE_FRAME_SIZE) {
+			inputs.push(InputState::from(frame[0]));
+			frame_hashes.push(u32::from_le_bytes(frame[1..5].try_into()?));
+		}
+		Ok(Movie {
+			path,
+			mode,
+			header: MovieHeader { rom_crc32, is_cgb, force_dmg, start_state: (start_state_len != 0).then(|| start_state.to_vec()) },
+			inputs,
+			frame_hashes,
+			current_frame: 0,
+			desync_frame: None,
+			is_finished: false
+		})
+	}
+	pub fn bind(&mut self, memory_bus: &mut MemoryBus) -> Result<(), String> {
+		let rom_crc32 = memory_bus.cartridge.rom_crc32();
+		if self.mode == MovieMode::Recording {
+			self.header.rom_crc32 = rom_crc32;
+			self.header.is_cgb = memory_bus.is_cgb_hardware;
+		} else {
+			if self.header.rom_crc32 != rom_crc32 {
+				return Err(format!("Movie was recorded on a ROM with CRC32 {:08X}, loaded ROM is {:08X}", self.header.rom_crc32, rom_crc32));
+			}
+			if self.header.is_cgb != memory_bus.is_cgb_hardware {
+				return Err("Movie was recorded on a different GameBoy model".to_string());
+			}
+		}
+		/* Movies start from the battery-backed save of the cartridge, or else from a wiped RAM */
+		let start_state = memory_bus.cartridge.make_deterministic();
+		if self.mode == MovieMode::Recording {
+			self.header.start_state = start_state;
+		} else if let Some(start_state) = &self.header.start_state {
+			memory_bus.cartridge.load_state(start_state).map_err(|err| format!("Unable to restore the movie start state: {}", err))?;
+		} else {
+			memory_bus.cartridge.clear_ram();
+		}
+		Ok(())
+	}
+	pub fn is_playing(&self) -> bool {
+		self.mode != MovieMode::Recording
+	}
+	pub fn next_input(&mut self) -> InputState {
+		if let Some(input) = self.inputs.get(self.current_frame) {
+			*input
+		} else {
+			self.is_finished = true;
+			InputState::default()
+		}
+	}
+	pub fn record_input(&mut self, input: InputState) {
+		self.inputs.push(input);
+	}
+	pub fn end_frame(&mut self, memory_bus: &mut MemoryBus, framebuffer: &[u8]) {
+		let frame_hash = crc32(framebuffer);
+		match self.mode {
+			MovieMode::Recording => self.frame_hashes.push(frame_hash),
+			MovieMode::Verification => {
+				if self.desync_frame.is_none() && self.frame_hashes.get(self.current_frame).is_some_and(|hash| *hash != frame_hash) {
+					self.desync_frame = Some(self.current_frame);
+				}
+			}
+			MovieMode::Playback => {}
+		}
+		memory_bus.cartridge.advance_emulated_clock(FRAME_DURATION);
+		self.current_frame += 1;
+		if self.is_playing() && self.current_frame >= self.inputs.len() {
+			self.is_finished = true;
+		}
+	}
+	pub fn status(&self) -> String {
+		match (self.mode, self.desync_frame) {
+			(MovieMode::Recording, _) => format!("Recording movie: frame {}", self.current_frame),
+			(MovieMode::Playback, _) => format!("Playing movie: frame {}/{}", self.current_frame, self.inputs.len()),
+			(MovieMode::Verification, None) => format!("Verifying movie: frame {}/{}", self.current_frame, self.inputs.len()),
+			(MovieMode::Verification, Some(frame)) => format!("Movie desynced at frame {}", frame)
+		}
+	}
+}
@@ -1,5 +1,8 @@
+mod pixel_fifo;
+
 use crate::memory_bus::MemoryBus;
-use crate::memory_bus::ppu_memory::{TilePixel, PixelColour, TileRow};
+use crate::memory_bus::ppu_memory::{TilePixel, PixelColour};
+use pixel_fifo::PixelFifo;
 
 const TILE_WIDTH: usize = 0x08;
 const TILE_HEIGHT: usize = 0x08;
@@ -21,6 +24,10 @@ pub const TILEMAP_PX_HEIGHT: usize	= TILE_HEIGHT * TILEMAP_NB_TILES_HEIGHT;
 pub const VIEWPORT_PX_WIDTH: usize	= 160;
 pub const VIEWPORT_PX_HEIGHT: usize	= 144;
 
+const FRAME_NB_DOTS: usize = 70224;
+const OAM_DMA_NB_BYTES: usize = 0xA0;
+const VRAM_DMA_NB_BYTES_PER_M_CYCLE: u16 = 2;
+
 #[derive(Debug, Clone, Copy)]
 enum PPUModes {
 	OAMSearch(u8, usize),
@@ -31,18 +38,28 @@ enum PPUModes {
 
 pub struct Ppu {
 	ppu_mode: PPUModes,
-	current_line_obj_rows: Vec<(usize, TileRow, bool, bool, u8)>,
+	pixel_fifo: PixelFifo,
 	oam_dma_count: usize,
-	vram_dma_count: u16
+	oam_dma_setup_count: Option<u8>,
+	oam_dma_src_page: u8,					// Latched when the transfer starts, a restart only taking over after its setup
+	vram_dma_count: u16,
+	stat_irq_line: bool,
+	lcd_off_count: Option<usize>,			// Dots into the blank frame while the LCD is off
+	is_first_frame_after_enable: bool
 }
 
 impl Ppu {
 	pub fn new() -> Ppu {
 		let ppu = Ppu {
 			ppu_mode: PPUModes::VBlank(153, 4559),
-			current_line_obj_rows: Vec::new(),
+			pixel_fifo: PixelFifo::new(),
 			oam_dma_count: 0,
-			vram_dma_count: 0x00
+			oam_dma_setup_count: None,
+			oam_dma_src_page: 0x00,
+			vram_dma_count: 0x00,
+			stat_irq_line: false,
+			lcd_off_count: Some(0),
+			is_first_frame_after_enable: false
 		};
 		ppu
 	}
@@ -158,32 +175,25 @@ impl Ppu {
 					PPUModes::OAMSearch(line_index, count + 1)
 				},
 			PPUModes::LineDraw(line_index, count) =>
-				if count == 167 {
+				if self.pixel_fifo.x == VIEWPORT_PX_WIDTH {
 					memory_bus.ppu_memory.is_vram_locked = false;
 					memory_bus.ppu_memory.is_oam_locked = false;
 					memory_bus.ppu_memory.ppu_mode_id = 0;
-					if memory_bus.ppu_memory.ppu_mode_0_interrupt_enable {
-						memory_bus.write_byte(0xFF0F, memory_bus.read_byte(0xFF0F) | (1 << 1));
-					}
+					memory_bus.ppu_memory.is_hblank_dma_pending = true;
 					PPUModes::HBlank(line_index, count + 1)
 				} else {
 					PPUModes::LineDraw(line_index, count + 1)
 				},
 			PPUModes::HBlank(line_index, count) =>
 				if count == 375 {
+					memory_bus.ppu_memory.is_hblank_dma_pending = false;
 					if line_index == 143 {
 						memory_bus.write_byte(0xFF0F, memory_bus.read_byte(0xFF0F) | (1 << 0));
 						memory_bus.ppu_memory.ppu_mode_id = 1;
-						if memory_bus.ppu_memory.ppu_mode_1_interrupt_enable {
-							memory_bus.write_byte(0xFF0F, memory_bus.read_byte(0xFF0F) | (1 << 1));
-						}
 						PPUModes::VBlank(144, 0)
 					} else {
 						memory_bus.ppu_memory.is_oam_locked = true;
 						memory_bus.ppu_memory.ppu_mode_id = 2;
-						if memory_bus.ppu_memory.ppu_mode_2_interrupt_enable {
-							memory_bus.write_byte(0xFF0F, memory_bus.read_byte(0xFF0F) | (1 << 1));
-						}
 						PPUModes::OAMSearch(line_index + 1, 0)
 					}
 				} else {
@@ -193,13 +203,15 @@ impl Ppu {
 				if count == 4559 {
 					memory_bus.ppu_memory.is_oam_locked = true;
 					memory_bus.ppu_memory.ppu_mode_id = 2;
-						if memory_bus.ppu_memory.ppu_mode_2_interrupt_enable {
-							memory_bus.write_byte(0xFF0F, memory_bus.read_byte(0xFF0F) | (1 << 1));
-						}
 					PPUModes::OAMSearch(0, 0)
 				} else {
 					PPUModes::VBlank(144 + ((count + 1) / 456) as u8, count + 1)
 				},
+		};
+		/* 2 objects of 4 bytes read every 4 dots, the row affected by the OAM bug being the next one */
+		memory_bus.ppu_memory.oam_scan_row = match self.ppu_mode {
+			PPUModes::OAMSearch(_, count) if memory_bus.ppu_memory.is_oam_locked => Some(count / 4 + 1),
+			_ => None
 		}
 	}
 	fn tick_viewport(&mut self, memory_bus: &mut MemoryBus, framebuffer: &mut [u8]) {
@@ -207,165 +219,319 @@ impl Ppu {
 			PPUModes::OAMSearch(line, count) => {
 				if count == 0 {
 					memory_bus.ppu_memory.ly_ram = line;
-					memory_bus.ppu_memory.lyc_match_flag = line == memory_bus.ppu_memory.lyc_ram;
-					if memory_bus.ppu_memory.lyc_match_flag && memory_bus.ppu_memory.lyc_interrupt_enable {
-						memory_bus.write_byte(0xFF0F, memory_bus.read_byte(0xFF0F) | (1 << 1));
+					if line == 0 {
+						self.pixel_fifo.start_frame();
 					}
-					self.current_line_obj_rows.clear();
+					self.pixel_fifo.line_objects.clear();
 				}
-				if count % 2 == 0 && self.current_line_obj_rows.len() != 10 {
+				if count % 2 == 0 && self.pixel_fifo.line_objects.len() != 10 {
 					let examined_obj = memory_bus.ppu_memory.objects[count / 2];
 					let obj_bottom_line_plus_1 = examined_obj.pos_y;
 					if memory_bus.ppu_memory.double_heigth_obj && line < obj_bottom_line_plus_1 && line + 16 >= obj_bottom_line_plus_1 {
-						let mut row = memory_bus.ppu_memory.get_obj_row(examined_obj.tile_id,
-							if examined_obj.is_y_flipped {obj_bottom_line_plus_1 - line - 1} else {16 - (obj_bottom_line_plus_1 - line)},
-							memory_bus.is_cgb && examined_obj.cgb_is_from_bank1
-						);
-						self.current_line_obj_rows.push(
-							(	examined_obj.pos_x as usize, 
-								if examined_obj.is_x_flipped {row.reverse(); row} else {row},
-								examined_obj.is_using_obp1,
-								examined_obj.is_under_bg_win,
-								examined_obj.cgb_palette_number	)
-						);
+						self.pixel_fifo.line_objects.push((count / 2,
+							if examined_obj.is_y_flipped {obj_bottom_line_plus_1 - line - 1} else {16 - (obj_bottom_line_plus_1 - line)}
+						));
 					}
 					if !memory_bus.ppu_memory.double_heigth_obj && line + 8 < obj_bottom_line_plus_1 && line + 16 >= obj_bottom_line_plus_1 {
-						let mut row = memory_bus.ppu_memory.get_obj_row(examined_obj.tile_id,
-							if examined_obj.is_y_flipped {obj_bottom_line_plus_1 - line - 9} else {8 - (obj_bottom_line_plus_1 - line - 8)},
-							memory_bus.is_cgb && examined_obj.cgb_is_from_bank1
-						);
-						self.current_line_obj_rows.push(
-							(	examined_obj.pos_x as usize, 
-								if examined_obj.is_x_flipped {row.reverse(); row} else {row},
-								examined_obj.is_using_obp1,
-								examined_obj.is_under_bg_win,
-								examined_obj.cgb_palette_number	)
-						);
+						self.pixel_fifo.line_objects.push((count / 2,
+							if examined_obj.is_y_flipped {obj_bottom_line_plus_1 - line - 9} else {8 - (obj_bottom_line_plus_1 - line - 8)}
+						));
 					}
 				}
 			},
 			PPUModes::LineDraw(line, count) => {
-				if count == 0 && !memory_bus.is_cgb{
-					self.current_line_obj_rows.sort_by(|row_a, row_b| row_a.0.cmp(&row_b.0));
-				}
-				if count < VIEWPORT_PX_WIDTH {
-					let viewport_pixel = &mut framebuffer[((line as usize) * VIEWPORT_PX_WIDTH + count) * 4..((line as usize) * VIEWPORT_PX_WIDTH + count + 1) * 4];
-					let mut bgwin_is_a_zero_pixel = true;
-					let mut tile_attrs = None;
-					if memory_bus.ppu_memory.bg_win_enable || memory_bus.is_cgb {
-						let pixel;
-						if memory_bus.ppu_memory.win_enable && line >= memory_bus.ppu_memory.wy_ram && count as u8 + 7 >= memory_bus.ppu_memory.wx_ram {
-							let tile_index = memory_bus.ppu_memory.get_win_tile_index((count as u8 + 7 - memory_bus.ppu_memory.wx_ram) / 8, (line - memory_bus.ppu_memory.wy_ram) / 8);
-							tile_attrs = if memory_bus.is_cgb {Some(memory_bus.ppu_memory.get_win_tile_cgb_attr((count as u8 + 7 - memory_bus.ppu_memory.wx_ram) / 8, (line - memory_bus.ppu_memory.wy_ram) / 8))} else {None};
-							let mut tile = memory_bus.ppu_memory.get_bg_win_tile(tile_index, memory_bus.is_cgb && tile_attrs.unwrap().is_from_bank1);
-							if memory_bus.is_cgb && tile_attrs.unwrap().vertical_flip {tile.reverse()}
-							let mut row = tile[(line - memory_bus.ppu_memory.wy_ram) as usize % 8];
-							if memory_bus.is_cgb && tile_attrs.unwrap().horizontal_flip {row.reverse()}
-							pixel = row[(count + 7 - memory_bus.ppu_memory.wx_ram as usize) % 8];
-						} else {
-							let tile_index = memory_bus.ppu_memory.get_bg_tile_index(memory_bus.ppu_memory.scx_ram.overflowing_add(count as u8).0 / 8, memory_bus.ppu_memory.scy_ram.overflowing_add(line).0 / 8);
-							tile_attrs = if memory_bus.is_cgb {Some(memory_bus.ppu_memory.get_bg_tile_cgb_attr(memory_bus.ppu_memory.scx_ram.overflowing_add(count as u8).0 / 8, memory_bus.ppu_memory.scy_ram.overflowing_add(line).0 / 8))} else {None};
-							let mut tile = memory_bus.ppu_memory.get_bg_win_tile(tile_index, memory_bus.is_cgb && tile_attrs.unwrap().is_from_bank1);
-							if memory_bus.is_cgb && tile_attrs.unwrap().vertical_flip {tile.reverse()}
-							let mut row = tile[(memory_bus.ppu_memory.scy_ram as usize + line as usize) % 8];
-							if memory_bus.is_cgb && tile_attrs.unwrap().horizontal_flip {row.reverse()}
-							pixel = row[(memory_bus.ppu_memory.scx_ram as usize + count) % 8];
-						};
-						viewport_pixel.clone_from_slice(&Ppu::palette_translation(&if memory_bus.is_cgb {memory_bus.ppu_memory.cgb_bg_palettes[tile_attrs.unwrap().bg_palette_index as usize]} else {memory_bus.ppu_memory.bg_palette} [
-							match pixel {
-								TilePixel::Zero =>	0,
-								TilePixel::One =>	{bgwin_is_a_zero_pixel = false; 1},
-								TilePixel::Two =>	{bgwin_is_a_zero_pixel = false; 2},
-								TilePixel::Three =>	{bgwin_is_a_zero_pixel = false; 3},
-							}
-						]))
-					} else {
-						viewport_pixel.clone_from_slice(&Ppu::palette_translation(&PixelColour::White));
-					}
-					if memory_bus.ppu_memory.obj_enable {
-						let mut pixel = (TilePixel::Zero, 0, false, 0x00);
-						for relevant_row in self.current_line_obj_rows.iter().rev() {
-							if count < relevant_row.0 && count + 8 >= relevant_row.0 {
-								let pixel_value = relevant_row.1[8 - (relevant_row.0 - count)];
-								if pixel_value != TilePixel::Zero {
-									pixel = (pixel_value, relevant_row.2 as usize, relevant_row.3, relevant_row.4 as usize);
-								}
-							}
-						}
-						if  !memory_bus.ppu_memory.bg_win_enable || (!pixel.2 && (!memory_bus.is_cgb || !tile_attrs.unwrap().bg_oam_priority)) || bgwin_is_a_zero_pixel {
-							match pixel.0 {
-								TilePixel::Zero =>	{}
-								TilePixel::One =>	{viewport_pixel.clone_from_slice(&Ppu::palette_translation(&if memory_bus.is_cgb {memory_bus.ppu_memory.cgb_obj_palettes[pixel.3]} else {memory_bus.ppu_memory.obj_palettes[pixel.1]}[0]))}
-								TilePixel::Two =>	{viewport_pixel.clone_from_slice(&Ppu::palette_translation(&if memory_bus.is_cgb {memory_bus.ppu_memory.cgb_obj_palettes[pixel.3]} else {memory_bus.ppu_memory.obj_palettes[pixel.1]}[1]))}
-								TilePixel::Three =>	{viewport_pixel.clone_from_slice(&Ppu::palette_translation(&if memory_bus.is_cgb {memory_bus.ppu_memory.cgb_obj_palettes[pixel.3]} else {memory_bus.ppu_memory.obj_palettes[pixel.1]}[2]))}
-							}
-						}
-					}
+				if count == 0 {
+					self.pixel_fifo.start_line(line, memory_bus.ppu_memory.scx_ram, memory_bus.ppu_memory.wy_ram);
 				}
+				self.pixel_fifo.tick(memory_bus, line, count, framebuffer);
 			},
 			PPUModes::HBlank(_, _) => {},
-			PPUModes::VBlank(line, _) => {
-				memory_bus.ppu_memory.ly_ram = line;
+			PPUModes::VBlank(line, count) => {
+				memory_bus.ppu_memory.ly_ram = if line == 153 && count % 456 >= 4 {0} else {line};		// LY=0 is compared to LYC through most of line 153
 			},
 		}
 	}
-	fn tick_oam_dma(&mut self, memory_bus: &mut MemoryBus) {
-		if self.oam_dma_count % 4 == 3 && self.oam_dma_count != 3{
-			//TODO do not use general read/write
-			memory_bus.ppu_memory.write(0xFE00 + self.oam_dma_count / 4 - 1,
-				memory_bus.read_byte(memory_bus.ppu_memory.oam_dma_reg as u16 * 0x100 + self.oam_dma_count as u16 / 4 - 1),
-				false
-			)
+	/* Clocked once per CPU M-cycle: starts one M-cycle after the 0xFF46 write, a running transfer going on meanwhile when restarted,
+	then copies one byte per M-cycle */
+	pub fn tick_oam_dma(&mut self, memory_bus: &mut MemoryBus) {
+		if std::mem::take(&mut memory_bus.ppu_memory.oam_dma_is_requested) {
+			self.oam_dma_setup_count = Some(1);
+		}
+		match self.oam_dma_setup_count {
+			Some(0) => {
+				self.oam_dma_setup_count = None;
+				self.oam_dma_count = 0;
+				self.oam_dma_src_page = memory_bus.ppu_memory.oam_dma_reg;
+				memory_bus.ppu_memory.oam_dma_is_active = true;
+			},
+			Some(count) => self.oam_dma_setup_count = Some(count - 1),
+			None => {}
 		}
-		if self.oam_dma_count == 643 {
+		if !memory_bus.ppu_memory.oam_dma_is_active {return}
+		if self.oam_dma_count == OAM_DMA_NB_BYTES {
 			memory_bus.ppu_memory.oam_dma_is_active = false;
-			self.oam_dma_count = 0;
-		} else {
-			self.oam_dma_count += 1;
+			return;
 		}
+		let src_address = (self.oam_dma_src_page as u16) << 8 | self.oam_dma_count as u16;
+		let data = memory_bus.oam_dma_read(src_address);
+		memory_bus.ppu_memory.poke(0xFE00 + self.oam_dma_count, data, false);
+		memory_bus.ppu_memory.oam_dma_src_address = src_address;
+		memory_bus.ppu_memory.oam_dma_bus_data = data;
+		self.oam_dma_count += 1;
 	}
 
-	fn tick_vram_dma(&mut self, memory_bus: &mut MemoryBus) {
-		memory_bus.write_byte(0x8000 | (memory_bus.ppu_memory.vram_dma_dst_regs & 0x1FF0) | self.vram_dma_count, memory_bus.read_byte((memory_bus.ppu_memory.vram_dma_src_regs  & 0xFFF0) | self.vram_dma_count));
-		if self.vram_dma_count == 0x0F {
+	/* Clocked once per CPU M-cycle, returns whether the CPU is stalled by the transfer during it. Each 0x10 bytes block takes 32 dots,
+	so 8 M-cycles in single speed and 16 in double speed: back to back for general purpose DMA, one per HBlank for HBlank DMA, which pauses while the CPU is halted */
+	pub fn tick_vram_dma(&mut self, memory_bus: &mut MemoryBus, is_cpu_halted: bool) -> bool {
+		if !memory_bus.is_cgb || !memory_bus.ppu_memory.vram_dma_is_active {
 			self.vram_dma_count = 0x00;
-			memory_bus.ppu_memory.vram_dma_stat = memory_bus.ppu_memory.vram_dma_stat.overflowing_sub(0x01).0;
-			memory_bus.ppu_memory.vram_dma_dst_regs = (memory_bus.ppu_memory.vram_dma_dst_regs + 0x0010) & 0x1FF0;
-			memory_bus.ppu_memory.vram_dma_src_regs =  memory_bus.ppu_memory.vram_dma_src_regs.overflowing_add(0x0010).0;
+			return false;
+		}
+		if self.vram_dma_count == 0x00 && memory_bus.ppu_memory.vram_dma_is_hblank_mode {
+			if !memory_bus.ppu_memory.is_hblank_dma_pending || is_cpu_halted {return false}
+			memory_bus.ppu_memory.is_hblank_dma_pending = false;
+		}
+		for _ in 0..if memory_bus.is_double_speed {VRAM_DMA_NB_BYTES_PER_M_CYCLE / 2} else {VRAM_DMA_NB_BYTES_PER_M_CYCLE} {
+			let data = memory_bus.vram_dma_read((memory_bus.ppu_memory.vram_dma_src_regs & 0xFFF0) | self.vram_dma_count);
+			memory_bus.vram_dma_write(0x8000 | (memory_bus.ppu_memory.vram_dma_dst_regs & 0x1FF0) | self.vram_dma_count, data);
+			self.vram_dma_count += 1;
+		}
+		if self.vram_dma_count == 0x10 {
+			self.vram_dma_count = 0x00;
+			memory_bus.ppu_memory.vram_dma_stat = memory_bus.ppu_memory.vram_dma_stat.wrapping_sub(0x01);
+			memory_bus.ppu_memory.vram_dma_dst_regs = (memory_bus.ppu_memory.vram_dma_dst_regs + 0x0010) & 0x1FF0;		// Wraps around within VRAM
+			memory_bus.ppu_memory.vram_dma_src_regs = memory_bus.ppu_memory.vram_dma_src_regs.wrapping_add(0x0010);
 			if memory_bus.ppu_memory.vram_dma_stat == 0xFF {memory_bus.ppu_memory.vram_dma_is_active = false}
-		} else {self.vram_dma_count += 1}
+		}
+		true
 	}
 
-	pub fn tick(&mut self, memory_bus: &mut MemoryBus, framebuffer: &mut [u8]) -> (bool, bool) {
-		let mut ppu_doing_vram_dma_transfer = false;
-		if memory_bus.ppu_memory.oam_dma_is_active {
-			self.tick_oam_dma(memory_bus);
-			if memory_bus.is_double_speed {
-				self.tick_oam_dma(memory_bus);
-			}
-		}
-		if memory_bus.is_cgb && memory_bus.ppu_memory.vram_dma_is_active {
-			if !memory_bus.ppu_memory.vram_dma_is_hblank_mode {
-				self.tick_vram_dma(memory_bus);
-				ppu_doing_vram_dma_transfer = true;
-			} else if let PPUModes::HBlank(_, count) = self.ppu_mode {
-				if count >= 360 {
-					self.tick_vram_dma(memory_bus);
-					ppu_doing_vram_dma_transfer = true;
-				}
-			}
-		} else {self.vram_dma_count = 0x00}
+	pub fn tick(&mut self, memory_bus: &mut MemoryBus, framebuffer: &mut [u8]) -> bool {
 		let mut frame_completed = false;
 		if memory_bus.ppu_memory.lcd_enable {
+			if self.lcd_off_count.take().is_some() {
+				/* The first line after enabling skips the OAM search: mode stays 0 and OAM accessible */
+				self.ppu_mode = PPUModes::OAMSearch(0, 0);
+				self.is_first_frame_after_enable = true;
+			}
 			self.tick_viewport(memory_bus, framebuffer);
 			if let PPUModes::VBlank(153, 4559) = self.ppu_mode  {
 				frame_completed = true;
+				if self.is_first_frame_after_enable {			// Not output by the LCD
+					self.is_first_frame_after_enable = false;
+					Self::blank_framebuffer(framebuffer);
+				}
 			}
 			self.tick_ppu_mode(memory_bus);
+			self.update_stat_irq_line(memory_bus);
+		} else {
+			/* LY and the mode are reset by the LCDC write; frames keep being timed so that the blank screen gets presented */
+			let lcd_off_count = self.lcd_off_count.get_or_insert_with(|| {
+				Self::blank_framebuffer(framebuffer);
+				0
+			});
+			*lcd_off_count += 1;
+			if *lcd_off_count == FRAME_NB_DOTS {
+				*lcd_off_count = 0;
+				frame_completed = true;
+			}
+			self.stat_irq_line = false;
+			memory_bus.ppu_memory.is_stat_write_glitch_pending = false;
+		}
+		frame_completed
+	}
+	fn blank_framebuffer(framebuffer: &mut [u8]) {
+		for pixel in framebuffer.chunks_exact_mut(4) {
+			pixel.copy_from_slice(&Ppu::palette_translation(&PixelColour::White));
+		}
+	}
+	/* All STAT sources share one interrupt line, the interrupt being requested on its rising edge only */
+	fn update_stat_irq_line(&mut self, memory_bus: &mut MemoryBus) {
+		let ppu_memory = &mut memory_bus.ppu_memory;
+		ppu_memory.lyc_match_flag = ppu_memory.ly_ram == ppu_memory.lyc_ram;
+		let stat_irq_line = (ppu_memory.lyc_interrupt_enable && ppu_memory.lyc_match_flag)
+			|| (ppu_memory.ppu_mode_0_interrupt_enable && ppu_memory.ppu_mode_id == 0)
+			|| (ppu_memory.ppu_mode_1_interrupt_enable && ppu_memory.ppu_mode_id == 1)
+			|| (ppu_memory.ppu_mode_2_interrupt_enable && ppu_memory.ppu_mode_id == 2);
+		/* DMG STAT writes briefly enable every source, firing spuriously in HBlank, VBlank or on a LY=LYC match */
+		let is_stat_write_glitching = std::mem::take(&mut ppu_memory.is_stat_write_glitch_pending) && (ppu_memory.ppu_mode_id <= 1 || ppu_memory.lyc_match_flag);
+		if (stat_irq_line || is_stat_write_glitching) && !self.stat_irq_line {
+			memory_bus.write_byte(0xFF0F, memory_bus.read_byte(0xFF0F) | (1 << 1));
+		}
+		self.stat_irq_line = stat_irq_line;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct Lcd {
+		ppu: Ppu,
+		memory_bus: MemoryBus,
+		framebuffer: Vec<u8>
+	}
+
+	impl Lcd {
+		/* DMG with the LCD turned on by the LCDC value, once the given registers are written */
+		fn new(lcdc: u8, registers: &[(u16, u8)]) -> Self {
+			let mut memory_bus = MemoryBus::new(None, None, true).unwrap();
+			for (address, data) in registers {
+				memory_bus.write_byte(*address, *data);
+			}
+			memory_bus.write_byte(0xFF40, lcdc);
+			Lcd { ppu: Ppu::new(), memory_bus, framebuffer: vec![0x00; VIEWPORT_PX_WIDTH * VIEWPORT_PX_HEIGHT * 4] }
+		}
+		fn tick(&mut self) {
+			self.ppu.tick(&mut self.memory_bus, &mut self.framebuffer);
+		}
+		fn tick_until(&mut self, is_reached: impl Fn(&MemoryBus) -> bool) {
+			for _ in 0..FRAME_NB_DOTS * 2 {
+				if is_reached(&self.memory_bus) {return}
+				self.tick();
+			}
+			panic!("never reached");
+		}
+		/* Emulates a frame up to VBlank, writing registers at the start of the given lines */
+		fn run_frame(&mut self, writes: &[(u8, u16, u8)]) {
+			for (line, address, data) in writes {
+				self.tick_until(|memory_bus| memory_bus.ppu_memory.ly_ram == *line && memory_bus.ppu_memory.ppu_mode_id == 2);
+				self.memory_bus.write_byte(*address, *data);
+			}
+			self.tick_until(|memory_bus| memory_bus.ppu_memory.ly_ram == 144);
+		}
+		fn black_lines(&self, x: usize) -> Vec<usize> {
+			(0..VIEWPORT_PX_HEIGHT).filter(|y| {
+				let pixel_pos = y * VIEWPORT_PX_WIDTH + x;
+				self.framebuffer[pixel_pos * 4..(pixel_pos + 1) * 4] == Ppu::palette_translation(&PixelColour::Black)
+			}).collect()
+		}
+		/* LY and mode at each STAT interrupt request over a number of dots */
+		fn stat_irqs(&mut self, nb_dots: usize) -> Vec<(u8, u8)> {
+			let mut stat_irqs = Vec::new();
+			self.memory_bus.write_byte(0xFF0F, 0x00);
+			for _ in 0..nb_dots {
+				self.tick();
+				if self.memory_bus.read_byte(0xFF0F) & (1 << 1) != 0 {
+					stat_irqs.push((self.memory_bus.ppu_memory.ly_ram, self.memory_bus.ppu_memory.ppu_mode_id));
+					self.memory_bus.write_byte(0xFF0F, 0x00);
+				}
+			}
+			stat_irqs
+		}
+		/* Dots spent in mode 3 on a line */
+		fn mode_3_length(&mut self, line: u8) -> usize {
+			self.tick_until(|memory_bus| memory_bus.ppu_memory.ly_ram == line && memory_bus.ppu_memory.ppu_mode_id == 3);
+			let mut nb_dots = 0;
+			while self.memory_bus.ppu_memory.ppu_mode_id == 3 {
+				self.tick();
+				nb_dots += 1;
+			}
+			nb_dots
+		}
+	}
+
+	#[test]
+	fn test_mode_3_fine_scroll() {
+		for scx in 0..=0x10 {
+			assert_eq!(Lcd::new(0x91, &[(0xFF43, scx)]).mode_3_length(1), 172 + (scx % 8) as usize);
+		}
+	}
+
+	#[test]
+	fn test_mode_3_objects() {
+		let obj_mode_3_length = |lcdc: u8, scx: u8, pos_xs: &[u8]| {
+			let mut registers = vec![(0xFF43, scx)];
+			for (i, pos_x) in pos_xs.iter().enumerate() {
+				registers.extend([(0xFE00 + i as u16 * 4, 17), (0xFE01 + i as u16 * 4, *pos_x)]);		// Lines 1 to 8
+			}
+			Lcd::new(lcdc, &registers).mode_3_length(1)
+		};
+		/* 6 dots of fetch, after waiting for the pixels of the background tile right of the object's leftmost one, minus 2 */
+		for (pos_x, penalty) in [(0, 11), (8, 11), (9, 10), (12, 7), (13, 6), (16, 11), (160, 11), (167, 6), (168, 0)] {
+			assert_eq!(obj_mode_3_length(0x93, 0, &[pos_x]), 172 + penalty);
+		}
+		assert_eq!(obj_mode_3_length(0x93, 3, &[8]), 172 + 3 + 8);
+		assert_eq!(obj_mode_3_length(0x93, 0, &[8, 8]), 172 + 11 + 6);				// The wait is only for the first object on a tile
+		assert_eq!(obj_mode_3_length(0x93, 0, &[8, 16]), 172 + 11 + 11);
+		assert_eq!(obj_mode_3_length(0x93, 0, &[8; 11]), 172 + 11 + 9 * 6);		// 10 objects per line
+		assert_eq!(obj_mode_3_length(0x91, 0, &[8]), 172);
+	}
+
+	#[test]
+	fn test_window_line_counter() {
+		/* Window line 10 is the only black one: the third row of the tiles on the second row of the window tilemap */
+		let mut registers = vec![(0xFF47, 0xE4), (0xFF4B, 7), (0x8014, 0xFF), (0x8015, 0xFF)];
+		registers.extend((0x9C20..0x9C40).map(|address| (address, 0x01)));
+		let mut lcd = Lcd::new(0xD1, &registers);
+		/* WY is latched once LY reached it, and the counter stops while the window is disabled */
+		let toggled_window_writes = [(0, 0xFF4A, 10), (0, 0xFF40, 0xF1), (15, 0xFF4A, 100), (20, 0xFF40, 0xD1), (30, 0xFF40, 0xF1)];
+		for _ in 0..2 {
+			lcd.run_frame(&toggled_window_writes);		// The first frame after enabling the LCD is blank
+		}
+		assert_eq!(lcd.black_lines(80), vec![30]);
+		/* Every frame restarts the counter */
+		lcd.run_frame(&[(0, 0xFF4A, 40)]);
+		assert_eq!(lcd.black_lines(80), vec![50]);
+		/* WY only matches on the line it is written for */
+		lcd.run_frame(&[(0, 0xFF4A, 50), (40, 0xFF4A, 20)]);
+		assert_eq!(lcd.black_lines(80), vec![]);
+	}
+
+	#[test]
+	fn test_stat_irq_line() {
+		let frame_stat_irqs = |stat: u8, lyc: u8| {
+			let mut lcd = Lcd::new(0x91, &[(0xFF41, stat), (0xFF45, lyc)]);
+			lcd.run_frame(&[]);
+			lcd.stat_irqs(FRAME_NB_DOTS)
+		};
+		assert_eq!(frame_stat_irqs(0x08, 0xFF).len(), 144);
+		assert_eq!(frame_stat_irqs(0x10, 0xFF).iter().map(|(_, mode)| *mode).collect::<Vec<u8>>(), vec![1]);
+		/* The line stays high from the HBlank of line 143 through VBlank */
+		let stat_irqs = frame_stat_irqs(0x18, 0xFF);
+		assert_eq!(stat_irqs.len(), 144);
+		assert!(stat_irqs.iter().all(|(_, mode)| *mode == 0));
+		/* LY reads 0 through most of line 153, so LYC=0 matches from there on to line 0 */
+		assert_eq!(frame_stat_irqs(0x40, 0x00), vec![(0, 1)]);
+		assert_eq!(frame_stat_irqs(0x40, 0x05), vec![(5, 2)]);
+	}
+
+	#[test]
+	fn test_stat_write_glitch() {
+		for is_cgb_hardware in [false, true] {
+			let mut lcd = Lcd::new(0x91, &[(0xFF45, 0x0A)]);
+			lcd.memory_bus.is_cgb_hardware = is_cgb_hardware;
+			let mut stat_write_irqs = |is_reached: &dyn Fn(&MemoryBus) -> bool| {
+				lcd.tick_until(is_reached);
+				lcd.memory_bus.write_byte(0xFF0F, 0x00);
+				lcd.memory_bus.write_byte(0xFF41, 0x00);
+				lcd.stat_irqs(1).len()
+			};
+			/* On DMG, in HBlank, VBlank and on a LY=LYC match only */
+			assert_eq!(stat_write_irqs(&|memory_bus| memory_bus.ppu_memory.ly_ram == 5 && memory_bus.ppu_memory.ppu_mode_id == 0), !is_cgb_hardware as usize);
+			assert_eq!(stat_write_irqs(&|memory_bus| memory_bus.ppu_memory.ly_ram == 6 && memory_bus.ppu_memory.ppu_mode_id == 3), 0);
+			assert_eq!(stat_write_irqs(&|memory_bus| memory_bus.ppu_memory.ly_ram == 10 && memory_bus.ppu_memory.ppu_mode_id == 3), !is_cgb_hardware as usize);
+			assert_eq!(stat_write_irqs(&|memory_bus| memory_bus.ppu_memory.ppu_mode_id == 1), !is_cgb_hardware as usize);
+		}
+	}
+
+	#[test]
+	fn test_oam_dma_restart() {
+		let mut registers: Vec<(u16, u8)> = (0..0xA0).flat_map(|i| [(0xC000 + i, i as u8), (0xD000 + i, 0x80 | i as u8)]).collect();
+		registers.push((0xFF46, 0xC0));
+		let mut lcd = Lcd::new(0x00, &registers);
+		for _ in 0..2 + 0x10 {
+			lcd.ppu.tick_oam_dma(&mut lcd.memory_bus);
 		}
-		else {
-			self.ppu_mode = PPUModes::VBlank(153, 4559)
+		/* The running transfer keeps copying from its own source during the setup of the new one */
+		lcd.memory_bus.write_byte(0xFF46, 0xD0);
+		lcd.ppu.tick_oam_dma(&mut lcd.memory_bus);
+		assert_eq!((0x00..=0x10).map(|i| lcd.memory_bus.ppu_memory.peek(0xFE00 + i, false)).collect::<Vec<u8>>(), (0x00..=0x10).collect::<Vec<u8>>());
+		lcd.ppu.tick_oam_dma(&mut lcd.memory_bus);
+		assert_eq!([0x00, 0x01, 0x11].map(|i| lcd.memory_bus.ppu_memory.peek(0xFE00 + i, false)), [0x80, 0x01, 0x11]);
+		while lcd.memory_bus.ppu_memory.oam_dma_is_active {
+			lcd.ppu.tick_oam_dma(&mut lcd.memory_bus);
 		}
-		(frame_completed, ppu_doing_vram_dma_transfer)
+		assert!((0x00..0xA0).all(|i| lcd.memory_bus.ppu_memory.peek(0xFE00 + i, false) == 0x80 | i as u8));
 	}
 }
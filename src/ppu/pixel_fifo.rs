@@ -0,0 +1,229 @@
+use std::collections::VecDeque;
+use crate::memory_bus::MemoryBus;
+use crate::memory_bus::ppu_memory::{TilePixel, PixelColour};
+use super::{Ppu, VIEWPORT_PX_WIDTH};
+
+const FETCH_NB_DOTS: u8 = 6;				// Tile index, low and high data bytes, 2 dots each
+
+#[derive(Debug, Clone, Copy)]
+struct BgPixel {
+	colour: TilePixel,
+	palette: u8,
+	has_priority: bool						// CGB BG-to-OAM priority attribute
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ObjPixel {
+	colour: TilePixel,
+	palette: u8,							// OBP0/OBP1 on DMG, palette number on CGB
+	is_under_bg_win: bool,
+	oam_index: usize
+}
+
+const TRANSPARENT_OBJ_PIXEL: ObjPixel = ObjPixel { colour: TilePixel::Zero, palette: 0, is_under_bg_win: false, oam_index: usize::MAX };
+
+/* Mode 3 renderer: a fetcher fills the background FIFO one tile row at a time, which gets shifted out one pixel per dot,
+mixed with the object FIFO. Fine scrolling, the window start and object fetches stall it, making mode 3 length vary */
+pub struct PixelFifo {
+	pub x: usize,							// Next viewport pixel to be pushed to the LCD
+	pub line_objects: Vec<(usize, u8)>,		// OAM index and row of the objects found by the OAM search, not fetched yet
+	bg_fifo: VecDeque<BgPixel>,
+	obj_fifo: VecDeque<ObjPixel>,
+	fetcher_x: u8,
+	fetch_step: u8,
+	fetched_row: [BgPixel; 8],
+	is_fetching_window: bool,
+	nb_pixels_to_discard: u8,
+	obj_fetch: Option<(usize, u8, u8)>,		// OAM index, row and dots left
+	obj_waited_tile: Option<u8>,			// Background tile an object fetch already waited for
+	window_line: u8,						// Only advances on lines the window got rendered on
+	is_wy_reached: bool,					// Latched for the rest of the frame once LY matched WY
+	is_window_wrapping: bool,				// Started at WX=166, the window then spans the whole next line
+	is_window_spanning_line: bool
+}
+
+impl PixelFifo {
+	pub fn new() -> Self {
+		PixelFifo {
+			x: 0,
+			line_objects: Vec::with_capacity(10),
+			bg_fifo: VecDeque::with_capacity(8),
+			obj_fifo: VecDeque::with_capacity(8),
+			fetcher_x: 0,
+			fetch_step: 0,
+			fetched_row: [BgPixel { colour: TilePixel::Zero, palette: 0, has_priority: false }; 8],
+			is_fetching_window: false,
+			nb_pixels_to_discard: 0,
+			obj_fetch: None,
+			obj_waited_tile: None,
+			window_line: 0,
+			is_wy_reached: false,
+			is_window_wrapping: false,
+			is_window_spanning_line: false
+		}
+	}
+	pub fn start_frame(&mut self) {
+		self.window_line = 0;
+		self.is_wy_reached = false;
+		self.is_fetching_window = false;
+		self.is_window_wrapping = false;
+	}
+	pub fn start_line(&mut self, line: u8, scx: u8, wy: u8) {
+		if self.is_fetching_window {
+			self.window_line = self.window_line.wrapping_add(1);
+		}
+		self.is_wy_reached |= line == wy;
+		self.is_window_spanning_line = std::mem::take(&mut self.is_window_wrapping);
+		self.x = 0;
+		self.bg_fifo.clear();
+		self.obj_fifo.clear();
+		self.fetcher_x = 0;
+		self.fetch_step = 0;
+		self.is_fetching_window = false;
+		self.nb_pixels_to_discard = scx % 8;
+		self.obj_fetch = None;
+		self.obj_waited_tile = None;
+	}
+	pub fn tick(&mut self, memory_bus: &MemoryBus, line: u8, count: usize, framebuffer: &mut [u8]) {
+		if count < FETCH_NB_DOTS as usize {		// The first tile gets fetched twice, the first fetch being thrown away
+			return;
+		}
+		if self.obj_fetch.is_none() && memory_bus.ppu_memory.obj_enable && self.nb_pixels_to_discard == 0 {
+			let x = self.x;
+			if let Some(position) = self.line_objects.iter().position(|(oam_index, _)| memory_bus.ppu_memory.objects[*oam_index].pos_x as usize <= x + 8) {
+				let (oam_index, row_index) = self.line_objects.remove(position);
+				self.obj_fetch = Some((oam_index, row_index, self.obj_fetch_wait() + FETCH_NB_DOTS));
+			}
+		}
+		if let Some((oam_index, row_index, nb_dots_left)) = self.obj_fetch {
+			if nb_dots_left == 1 {
+				self.obj_fetch = None;
+				self.merge_object(memory_bus, oam_index, row_index);
+			} else {
+				self.obj_fetch = Some((oam_index, row_index, nb_dots_left - 1));
+			}
+			return;
+		}
+		if self.is_window_starting(memory_bus) {
+			let wx = memory_bus.ppu_memory.wx_ram;
+			self.is_fetching_window = true;
+			self.is_window_wrapping = wx == 166;
+			self.nb_pixels_to_discard = if self.is_window_spanning_line {0} else {7_u8.saturating_sub(wx)};		// WX<7 shifts the window left
+			self.bg_fifo.clear();
+			self.fetcher_x = 0;
+			self.fetch_step = 0;
+			self.obj_waited_tile = None;
+			return;
+		}
+		self.tick_fetcher(memory_bus, line);
+		if let Some(bg_pixel) = self.bg_fifo.pop_front() {
+			if self.nb_pixels_to_discard != 0 {
+				self.nb_pixels_to_discard -= 1;
+				return;
+			}
+			let obj_pixel = self.obj_fifo.pop_front().unwrap_or(TRANSPARENT_OBJ_PIXEL);
+			let pixel_pos = line as usize * VIEWPORT_PX_WIDTH + self.x;
+			framebuffer[pixel_pos * 4..(pixel_pos + 1) * 4].copy_from_slice(&Ppu::palette_translation(&Self::mix_pixels(memory_bus, bg_pixel, obj_pixel)));
+			self.x += 1;
+		}
+	}
+	/* Dots an object fetch waits for the background tile under its leftmost pixel to be fetched: the pixels of that tile right of it, minus 2,
+	and none if a previous object already waited for the same tile */
+	fn obj_fetch_wait(&mut self) -> u8 {
+		let tile = self.fetcher_x.wrapping_sub(!self.bg_fifo.is_empty() as u8);		// The one being fetched once the FIFO is empty
+		if self.obj_waited_tile.replace(tile) == Some(tile) {return 0}
+		let pixel_index = if self.bg_fifo.is_empty() {0} else {8 - self.bg_fifo.len() as u8};
+		5_u8.saturating_sub(pixel_index)
+	}
+	/* WX=0 is only compared once the SCX fine scroll pixels are discarded, making its start vary with SCX */
+	fn is_window_starting(&self, memory_bus: &MemoryBus) -> bool {
+		let ppu_memory = &memory_bus.ppu_memory;
+		!self.is_fetching_window && ppu_memory.win_enable && (ppu_memory.bg_win_enable || memory_bus.is_cgb) && self.is_wy_reached
+			&& (self.is_window_spanning_line || (self.x + 7 >= ppu_memory.wx_ram as usize && (ppu_memory.wx_ram != 0 || self.nb_pixels_to_discard == 0)))
+	}
+	fn tick_fetcher(&mut self, memory_bus: &MemoryBus, line: u8) {
+		if self.fetch_step < FETCH_NB_DOTS {
+			self.fetch_step += 1;
+			if self.fetch_step == FETCH_NB_DOTS {
+				self.fetched_row = self.fetch_bg_win_row(memory_bus, line);
+			}
+		} else if self.bg_fifo.is_empty() {
+			self.bg_fifo.extend(self.fetched_row);
+			self.fetcher_x = self.fetcher_x.wrapping_add(1);
+			self.fetch_step = 0;
+		}
+	}
+	fn fetch_bg_win_row(&self, memory_bus: &MemoryBus, line: u8) -> [BgPixel; 8] {
+		let ppu_memory = &memory_bus.ppu_memory;
+		let (tile_index, tile_attrs, row_index) = if self.is_fetching_window {
+			let (x, y) = (self.fetcher_x & 0x1F, self.window_line);
+			(ppu_memory.get_win_tile_index(x, y / 8), ppu_memory.get_win_tile_cgb_attr(x, y / 8), y % 8)
+		} else {
+			let (x, y) = ((ppu_memory.scx_ram / 8).wrapping_add(self.fetcher_x) & 0x1F, ppu_memory.scy_ram.wrapping_add(line));
+			(ppu_memory.get_bg_tile_index(x, y / 8), ppu_memory.get_bg_tile_cgb_attr(x, y / 8), y % 8)
+		};
+		let is_cgb = memory_bus.is_cgb;
+		let tile = ppu_memory.get_bg_win_tile(tile_index, is_cgb && tile_attrs.is_from_bank1);
+		let mut row = tile[if is_cgb && tile_attrs.vertical_flip {7 - row_index} else {row_index} as usize];
+		if is_cgb && tile_attrs.horizontal_flip {row.reverse()}
+		row.map(|colour| BgPixel {
+			colour,
+			palette: if is_cgb {tile_attrs.bg_palette_index} else {0},
+			has_priority: is_cgb && tile_attrs.bg_oam_priority
+		})
+	}
+	/* Objects only fill the transparent slots of the object FIFO, so that on DMG the leftmost one wins; on CGB the lowest OAM index does,
+	unless the boot ROM selected the DMG priority through OPRI */
+	fn merge_object(&mut self, memory_bus: &MemoryBus, oam_index: usize, row_index: u8) {
+		let object = memory_bus.ppu_memory.objects[oam_index];
+		let mut row = memory_bus.ppu_memory.get_obj_row(object.tile_id, row_index, memory_bus.is_cgb && object.cgb_is_from_bank1);
+		if object.is_x_flipped {row.reverse()}
+		let nb_hidden_pixels = (self.x + 8).saturating_sub(object.pos_x as usize);		// Left of the viewport
+		while self.obj_fifo.len() < 8 {
+			self.obj_fifo.push_back(TRANSPARENT_OBJ_PIXEL);
+		}
+		for (i, colour) in row.iter().enumerate().skip(nb_hidden_pixels) {
+			let slot = &mut self.obj_fifo[i - nb_hidden_pixels];
+			if *colour != TilePixel::Zero && (slot.colour == TilePixel::Zero || (!memory_bus.ppu_memory.is_obj_priority_by_coordinate && oam_index < slot.oam_index)) {
+				*slot = ObjPixel {
+					colour: *colour,
+					palette: if memory_bus.is_cgb {object.cgb_palette_number} else {object.is_using_obp1 as u8},
+					is_under_bg_win: object.is_under_bg_win,
+					oam_index
+				};
+			}
+		}
+	}
+	/* Palettes are looked up as pixels get pushed to the LCD, so that mid-line palette changes show */
+	fn mix_pixels(memory_bus: &MemoryBus, bg_pixel: BgPixel, obj_pixel: ObjPixel) -> PixelColour {
+		let ppu_memory = &memory_bus.ppu_memory;
+		let is_compat_mode = memory_bus.is_cgb_hardware && !memory_bus.is_cgb;
+		let is_bg_blank = !ppu_memory.bg_win_enable && !memory_bus.is_cgb;
+		let is_bg_zero = is_bg_blank || bg_pixel.colour == TilePixel::Zero;
+		if obj_pixel.colour != TilePixel::Zero && ppu_memory.obj_enable && (!ppu_memory.bg_win_enable || (!obj_pixel.is_under_bg_win && !bg_pixel.has_priority) || is_bg_zero) {
+			if memory_bus.is_cgb {
+				ppu_memory.cgb_obj_palettes[obj_pixel.palette as usize][colour_index(obj_pixel.colour)]
+			} else if is_compat_mode {
+				ppu_memory.get_compat_obj_colour(obj_pixel.palette as usize, colour_index(obj_pixel.colour))
+			} else {
+				ppu_memory.obj_palettes[obj_pixel.palette as usize][colour_index(obj_pixel.colour) - 1]
+			}
+		} else if is_bg_blank {
+			if is_compat_mode {ppu_memory.cgb_bg_palettes[0][0]} else {PixelColour::White}
+		} else if is_compat_mode {
+			ppu_memory.get_compat_bg_colour(colour_index(bg_pixel.colour))
+		} else {
+			let palette = if memory_bus.is_cgb {ppu_memory.cgb_bg_palettes[bg_pixel.palette as usize]} else {ppu_memory.bg_palette};
+			palette[colour_index(bg_pixel.colour)]
+		}
+	}
+}
+
+fn colour_index(pixel: TilePixel) -> usize {
+	match pixel {
+		TilePixel::Zero		=> 0,
+		TilePixel::One		=> 1,
+		TilePixel::Two		=> 2,
+		TilePixel::Three	=> 3
+	}
+}
@@ -0,0 +1,204 @@
+use std::{cell::RefCell, collections::HashSet, path::Path, rc::Rc};
+use rhai::{Blob, CallFnOptions, Engine, EvalAltResult, Scope, AST};
+use crate::cpu::Cpu;
+use crate::memory_bus::{MemoryBus, input_memory::InputState};
+use crate::ppu::{VIEWPORT_PX_WIDTH, VIEWPORT_PX_HEIGHT};
+
+struct ScriptState {
+	memory_bus: Option<MemoryBus>,
+	cpu: Option<Cpu>,
+	framebuffer: Vec<u8>,
+	breakpoints: HashSet<u16>,
+	frame_count: u64,
+	pause_requested: bool,
+	exit_requested: bool
+}
+
+pub struct ScriptHost {
+	engine: Engine,
+	ast: AST,
+	scope: Scope<'static>,
+	state: Rc<RefCell<ScriptState>>,
+	has_on_frame: bool,
+	has_on_breakpoint: bool
+}
+
+fn button_mask(button: &str) -> Result<u8, Box<EvalAltResult>> {
+	match button.to_ascii_lowercase().as_str() {
+		"down" =>	Ok(1 << 7),
+		"up" =>		Ok(1 << 6),
+		"left" =>	Ok(1 << 5),
+		"right" =>	Ok(1 << 4),
+		"start" =>	Ok(1 << 3),
+		"select" =>	Ok(1 << 2),
+		"b" =>		Ok(1 << 1),
+		"a" =>		Ok(1 << 0),
+		_ => Err(format!("unknown joypad button '{}'", button).into())
+	}
+}
+
+fn with_memory_bus<T>(state: &Rc<RefCell<ScriptState>>, f: impl FnOnce(&mut MemoryBus) -> T) -> Result<T, Box<EvalAltResult>> {
+	state.borrow_mut().memory_bus.as_mut().map(f).ok_or_else(|| "no ROM is loaded".into())
+}
+
+fn with_cpu<T>(state: &Rc<RefCell<ScriptState>>, f: impl FnOnce(&mut Cpu) -> T) -> Result<T, Box<EvalAltResult>> {
+	state.borrow_mut().cpu.as_mut().map(f).ok_or_else(|| "CPU is only accessible from callbacks".into())
+}
+
+impl ScriptHost {
+	pub fn load_from_path(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+		let state = Rc::new(RefCell::new(ScriptState {
+			memory_bus: None,
+			cpu: None,
+			framebuffer: Vec::new(),
+			breakpoints: HashSet::new(),
+			frame_count: 0,
+			pause_requested: false,
+			exit_requested: false
+		}));
+		let mut engine = Engine::new();
+		Self::register_api(&mut engine, &state);
+		let ast = engine.compile_file(path.to_path_buf())?;
+		let mut scope = Scope::new();
+		engine.run_ast_with_scope(&mut scope, &ast)?;
+		let has_on_frame = ast.iter_functions().any(|function| function.name == "on_frame" && function.params.is_empty());
+		let has_on_breakpoint = ast.iter_functions().any(|function| function.name == "on_breakpoint" && function.params.len() == 1);
+		Ok(ScriptHost { engine, ast, scope, state, has_on_frame, has_on_breakpoint })
+	}
+	fn register_api(engine: &mut Engine, state: &Rc<RefCell<ScriptState>>) {
+		let s = state.clone();
+		engine.register_fn("read_byte", move |address: i64| with_memory_bus(&s, |memory_bus| memory_bus.read_byte(address as u16) as i64));
+		let s = state.clone();
+		engine.register_fn("write_byte", move |address: i64, data: i64| with_memory_bus(&s, |memory_bus| memory_bus.write_byte(address as u16, data as u8)));
+		let s = state.clone();
+		engine.register_fn("reg", move |name: &str| -> Result<i64, Box<EvalAltResult>> {
+			let name = name.to_ascii_lowercase();
+			with_cpu(&s, |cpu| match name.as_str() {
+				"a" =>	Some(cpu.registers.a as i64),
+				"f" =>	Some(u8::from(cpu.registers.f) as i64),
+				"b" =>	Some(cpu.registers.b as i64),
+				"c" =>	Some(cpu.registers.c as i64),
+				"d" =>	Some(cpu.registers.d as i64),
+				"e" =>	Some(cpu.registers.e as i64),
+				"h" =>	Some(cpu.registers.h as i64),
+				"l" =>	Some(cpu.registers.l as i64),
+				"af" =>	Some(cpu.registers.get_af_big_endian() as i64),
+				"bc" =>	Some(cpu.registers.get_bc_big_endian() as i64),
+				"de" =>	Some(cpu.registers.get_de_big_endian() as i64),
+				"hl" =>	Some(cpu.registers.get_hl_big_endian() as i64),
+				"sp" =>	Some(cpu.registers.stack_pointer as i64),
+				"pc" =>	Some(cpu.next_op_address as i64),
+				_ => None
+			})?.ok_or_else(|| format!("unknown register '{}'", name).into())
+		});
+		let s = state.clone();
+		engine.register_fn("set_reg", move |name: &str, value: i64| -> Result<(), Box<EvalAltResult>> {
+			let name = name.to_ascii_lowercase();
+			with_cpu(&s, |cpu| {
+				match name.as_str() {
+					"a" =>	cpu.registers.a = value as u8,
+					"f" =>	cpu.registers.f = (value as u8).into(),
+					"b" =>	cpu.registers.b = value as u8,
+					"c" =>	cpu.registers.c = value as u8,
+					"d" =>	cpu.registers.d = value as u8,
+					"e" =>	cpu.registers.e = value as u8,
+					"h" =>	cpu.registers.h = value as u8,
+					"l" =>	cpu.registers.l = value as u8,
+					"af" =>	cpu.registers.set_af_big_endian(value as u16),
+					"bc" =>	cpu.registers.set_bc_big_endian(value as u16),
+					"de" =>	cpu.registers.set_de_big_endian(value as u16),
+					"hl" =>	cpu.registers.set_hl_big_endian(value as u16),
+					"sp" =>	cpu.registers.stack_pointer = value as u16,
+					_ => return false
+				}
+				true
+			})?.then_some(()).ok_or_else(|| format!("unknown or read-only register '{}'", name).into())
+		});
+		let s = state.clone();
+		engine.register_fn("press", move |button: &str| -> Result<(), Box<EvalAltResult>> {
+			let mask = button_mask(button)?;
+			with_memory_bus(&s, |memory_bus| {
+				let injected_state = u8::from(memory_bus.input_memory.injected_state()) | mask;
+				memory_bus.input_memory.inject(&InputState::from(injected_state))
+			})
+		});
+		let s = state.clone();
+		engine.register_fn("release", move |button: &str| -> Result<(), Box<EvalAltResult>> {
+			let mask = button_mask(button)?;
+			with_memory_bus(&s, |memory_bus| {
+				let injected_state = u8::from(memory_bus.input_memory.injected_state()) & !mask;
+				memory_bus.input_memory.inject(&InputState::from(injected_state))
+			})
+		});
+		let s = state.clone();
+		engine.register_fn("release_all", move || with_memory_bus(&s, |memory_bus| memory_bus.input_memory.inject(&InputState::default())));
+		let s = state.clone();
+		engine.register_fn("pixel", move |x: i64, y: i64| -> Result<i64, Box<EvalAltResult>> {
+			if !(0..VIEWPORT_PX_WIDTH as i64).contains(&x) || !(0..VIEWPORT_PX_HEIGHT as i64).contains(&y) {
+				return Err(format!("pixel ({}, {}) is out of the framebuffer", x, y).into());
+			}
+			let pixel_pos = (y as usize * VIEWPORT_PX_WIDTH + x as usize) * 4;
+			match s.borrow().framebuffer.get(pixel_pos..pixel_pos + 3) {
+				Some(rgb) => Ok((rgb[0] as i64) << 16 | (rgb[1] as i64) << 8 | rgb[2] as i64),
+				None => Err("no frame was rendered yet".into())
+			}
+		});
+		let s = state.clone();
+		engine.register_fn("save_cartridge_state", move || with_memory_bus(&s, |memory_bus| memory_bus.cartridge.save_state() as Blob));
+		let s = state.clone();
+		engine.register_fn("load_cartridge_state", move |cartridge_state: Blob| -> Result<(), Box<EvalAltResult>> {
+			with_memory_bus(&s, |memory_bus| memory_bus.cartridge.load_state(&cartridge_state))?.map_err(|error| error.into())
+		});
+		let s = state.clone();
+		engine.register_fn("frame_count", move || s.borrow().frame_count as i64);
+		let s = state.clone();
+		engine.register_fn("add_breakpoint", move |address: i64| {s.borrow_mut().breakpoints.insert(address as u16);});
+		let s = state.clone();
+		engine.register_fn("remove_breakpoint", move |address: i64| {s.borrow_mut().breakpoints.remove(&(address as u16));});
+		let s = state.clone();
+		engine.register_fn("pause", move || {s.borrow_mut().pause_requested = true;});
+		let s = state.clone();
+		engine.register_fn("exit", move || {s.borrow_mut().exit_requested = true;});
+	}
+	fn call_with_emulator(&mut self, memory_bus: &mut Option<MemoryBus>, cpu: &mut Cpu, function: &str, args: impl rhai::FuncArgs) -> Result<(), Box<EvalAltResult>> {
+		{
+			let mut state = self.state.borrow_mut();
+			state.memory_bus = memory_bus.take();
+			state.cpu = Some(std::mem::replace(cpu, Cpu::new()));
+		}
+		let options = CallFnOptions::new().eval_ast(false);
+		let res = self.engine.call_fn_with_options::<rhai::Dynamic>(options, &mut self.scope, &self.ast, function, args).map(|_| ());
+		let mut state = self.state.borrow_mut();
+		*memory_bus = state.memory_bus.take();
+		if let Some(script_cpu) = state.cpu.take() {
+			*cpu = script_cpu;
+		}
+		res
+	}
+	pub fn has_breakpoint(&self, address: u16) -> bool {
+		self.has_on_breakpoint && self.state.borrow().breakpoints.contains(&address)
+	}
+	pub fn on_frame(&mut self, memory_bus: &mut Option<MemoryBus>, cpu: &mut Cpu, framebuffer: &[u8]) -> Result<(), Box<EvalAltResult>> {
+		{
+			let mut state = self.state.borrow_mut();
+			state.frame_count += 1;
+			state.framebuffer.clear();
+			state.framebuffer.extend_from_slice(framebuffer);
+		}
+		if self.has_on_frame {
+			self.call_with_emulator(memory_bus, cpu, "on_frame", ())
+		} else {
+			Ok(())
+		}
+	}
+	pub fn on_breakpoint(&mut self, memory_bus: &mut Option<MemoryBus>, cpu: &mut Cpu) -> Result<(), Box<EvalAltResult>> {
+		let address = cpu.next_op_address as i64;
+		self.call_with_emulator(memory_bus, cpu, "on_breakpoint", (address,))
+	}
+	pub fn take_pause_request(&mut self) -> bool {
+		std::mem::take(&mut self.state.borrow_mut().pause_requested)
+	}
+	pub fn is_exit_requested(&self) -> bool {
+		self.state.borrow().exit_requested
+	}
+}
@@ -1,47 +1,35 @@
-use crate::memory_bus::{MemoryBus, timer_memory::CPUFreqDivider};
+use crate::memory_bus::{MemoryBus, timer_memory::TIMA_RELOAD_DELAY};
 
-pub struct Timer {
-	nb_ticks_div: usize,
-	nb_ticks_tima: usize
-}
+/* Clocks the 16-bit system counter of the timer memory, DIV being its upper byte, along with the serial port clock derived from it */
+pub struct Timer {}
 
 impl Timer {
 	pub fn new() -> Self {
-		Timer {
-			nb_ticks_div: 0,
-			nb_ticks_tima: 0,
-		}
+		Timer {}
 	}
-	pub fn increase_div(&mut self, memory_bus: &mut MemoryBus) {
-		memory_bus.timer_memory.div_reg = memory_bus.timer_memory.div_reg.overflowing_add(1).0
-	}
-	pub fn increase_tima(&mut self, memory_bus: &mut MemoryBus) {
-		memory_bus.timer_memory.tima_reg = {
-			if memory_bus.timer_memory.tima_reg == 0xFF {
+	pub fn tick(&mut self, memory_bus: &mut MemoryBus) {
+		let timer_memory = &mut memory_bus.timer_memory;
+		if timer_memory.reload_delay != 0 {
+			timer_memory.reload_delay -= 1;
+		}
+		if timer_memory.overflow_delay != 0 {
+			timer_memory.overflow_delay -= 1;
+			if timer_memory.overflow_delay == 0 {
+				timer_memory.tima_reg = timer_memory.tim_reg;
+				timer_memory.reload_delay = TIMA_RELOAD_DELAY;
 				memory_bus.write_byte(0xFF0F, memory_bus.read_byte(0xFF0F) | (1 << 2));
-				memory_bus.timer_memory.tim_reg
-			} else {
-				memory_bus.timer_memory.tima_reg + 1
 			}
 		}
-	}
-	pub fn tick(&mut self, memory_bus: &mut MemoryBus) {
-		if memory_bus.timer_memory.timer_enabled {
-			self.nb_ticks_tima = if self.nb_ticks_tima ==
-									match memory_bus.timer_memory.timer_freq_div {
-										CPUFreqDivider::Ratio0x10 => 0x0F,
-										CPUFreqDivider::Ratio0x40 => 0x3F,
-										CPUFreqDivider::Ratio0x100 => 0xFF,
-										CPUFreqDivider::Ratio0x400 => 0x3FF,
-									}{
-				self.increase_tima(memory_bus);
-				0x00
-			} else {self.nb_ticks_tima + 1};
+		let timer_memory = &mut memory_bus.timer_memory;
+		let previous_signal = timer_memory.timer_signal();
+		let previous_counter = timer_memory.system_counter;
+		timer_memory.system_counter = timer_memory.system_counter.wrapping_add(1);
+		if previous_signal && !timer_memory.timer_signal() {
+			timer_memory.increase_tima();
+		}
+		if memory_bus.serial_memory.tick(previous_counter, memory_bus.timer_memory.system_counter) {
+			memory_bus.write_byte(0xFF0F, memory_bus.read_byte(0xFF0F) | (1 << 3));
 		}
-		self.nb_ticks_div = if self.nb_ticks_div == 0xFF {
-			self.increase_div(memory_bus);
-			0x00
-		} else {self.nb_ticks_div + 1};
 	}
 }
 
@@ -52,7 +40,7 @@ mod tests {
 
 	#[test]
 	fn test_timer() {
-		let mut memory_bus = MemoryBus::new(None, false);
+		let mut memory_bus = MemoryBus::new(None, None, false).unwrap();
 		let mut timer = Timer::new();
 		println!("DIV is {:x}, TIMA is {:x}", memory_bus.timer_memory.read(0xFF04), memory_bus.timer_memory.read(0xFF05));
 		for _ in 0..0x100 {
@@ -76,4 +64,68 @@ mod tests {
 		assert_eq!(memory_bus.timer_memory.read(0xFF04), 0x06);
 		assert_eq!(memory_bus.timer_memory.read(0xFF05), 0x11);
 	}
+	fn timer_at_falling_edge(memory_bus: &mut MemoryBus, timer: &mut Timer, tima: u8) {
+		memory_bus.write_byte(0xFF07, 0x05);
+		memory_bus.write_byte(0xFF04, 0x00);
+		memory_bus.write_byte(0xFF05, tima);
+		memory_bus.write_byte(0xFF0F, 0x00);
+		for _ in 0..0x0F {
+			timer.tick(memory_bus);
+		}
+	}
+	#[test]
+	fn test_timer_glitches() {
+		let mut memory_bus = MemoryBus::new(None, None, false).unwrap();
+		let mut timer = Timer::new();
+		timer_at_falling_edge(&mut memory_bus, &mut timer, 0x10);
+		memory_bus.write_byte(0xFF04, 0x00);
+		assert_eq!(memory_bus.read_byte(0xFF05), 0x11);
+		timer_at_falling_edge(&mut memory_bus, &mut timer, 0x10);
+		memory_bus.write_byte(0xFF07, 0x01);
+		assert_eq!(memory_bus.read_byte(0xFF05), 0x11);
+		timer_at_falling_edge(&mut memory_bus, &mut timer, 0x10);
+		memory_bus.write_byte(0xFF07, 0x06);
+		assert_eq!(memory_bus.read_byte(0xFF05), 0x11);
+		timer_at_falling_edge(&mut memory_bus, &mut timer, 0x10);
+		memory_bus.write_byte(0xFF07, 0x05);
+		assert_eq!(memory_bus.read_byte(0xFF05), 0x10);
+	}
+	#[test]
+	fn test_timer_reload() {
+		let mut memory_bus = MemoryBus::new(None, None, false).unwrap();
+		let mut timer = Timer::new();
+		memory_bus.write_byte(0xFF06, 0x42);
+		timer_at_falling_edge(&mut memory_bus, &mut timer, 0xFF);
+		timer.tick(&mut memory_bus);
+		for _ in 0..4 {
+			assert_eq!(memory_bus.read_byte(0xFF05), 0x00);
+			assert_eq!(memory_bus.read_byte(0xFF0F) & (1 << 2), 0);
+			timer.tick(&mut memory_bus);
+		}
+		assert_eq!(memory_bus.read_byte(0xFF05), 0x42);
+		assert_ne!(memory_bus.read_byte(0xFF0F) & (1 << 2), 0);
+		memory_bus.write_byte(0xFF05, 0x12);
+		assert_eq!(memory_bus.read_byte(0xFF05), 0x42);
+		memory_bus.write_byte(0xFF06, 0x24);
+		assert_eq!(memory_bus.read_byte(0xFF05), 0x24);
+		for _ in 0..4 {
+			timer.tick(&mut memory_bus);
+		}
+		memory_bus.write_byte(0xFF06, 0x42);
+		assert_eq!(memory_bus.read_byte(0xFF05), 0x24);
+	}
+	#[test]
+	fn test_timer_reload_cancelled() {
+		let mut memory_bus = MemoryBus::new(None, None, false).unwrap();
+		let mut timer = Timer::new();
+		memory_bus.write_byte(0xFF06, 0x42);
+		timer_at_falling_edge(&mut memory_bus, &mut timer, 0xFF);
+		timer.tick(&mut memory_bus);
+		memory_bus.write_byte(0xFF05, 0x12);
+		for _ in 0..4 {
+			timer.tick(&mut memory_bus);
+		}
+		assert_eq!(memory_bus.read_byte(0xFF05), 0x12);
+		assert_eq!(memory_bus.read_byte(0xFF0F) & (1 << 2), 0);
+	}
 }
\ No newline at end of file
@@ -0,0 +1,72 @@
+use std::{fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}};
+use crate::cpu::Cpu;
+use crate::memory_bus::MemoryBus;
+use crate::disassembler;
+
+pub const DEFAULT_TRACE_SIZE_LIMIT: u64 = 256 * 1024 * 1024;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+	#[default]
+	Doctor,
+	Verbose
+}
+
+/* CPU execution log, one line per instruction; the Doctor format matches Gameboy Doctor reference logs */
+pub struct Tracer {
+	path: PathBuf,
+	writer: BufWriter<File>,
+	format: TraceFormat,
+	size_limit: u64,
+	nb_bytes_written: u64,
+	nb_cycles: u64,
+	pub is_enabled: bool
+}
+
+impl Tracer {
+	pub fn create(path: &Path, format: TraceFormat, size_limit: u64) -> Result<Self, Box<dyn std::error::Error>> {
+		Ok(Tracer {
+			path: path.to_path_buf(),
+			writer: BufWriter::new(File::create(path)?),
+			format,
+			size_limit,
+			nb_bytes_written: 0,
+			nb_cycles: 0,
+			is_enabled: true
+		})
+	}
+	/* Called after each Cpu::tick: logs the state the fetched instruction is about to execute with.
+	Doctor logs start at 0x0100, so the boot ROM is only traced in the verbose format */
+	pub fn trace(&mut self, cpu: &Cpu, memory_bus: &MemoryBus, nb_cycles: u8) -> Result<(), String> {
+		self.nb_cycles += nb_cycles as u64;
+		if !self.is_enabled || (self.format == TraceFormat::Doctor && memory_bus.is_bootrom_mapped()) || cpu.next_op.is_none_or(|instruction| instruction.length() == 0) {
+			return Ok(());
+		}
+		let registers = &cpu.registers;
+		let address = cpu.next_op_address;
+		let mut line = format!("A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+			registers.a, u8::from(registers.f), registers.b, registers.c, registers.d, registers.e, registers.h, registers.l, registers.stack_pointer, address,
+			memory_bus.read_byte(address), memory_bus.read_byte(address.wrapping_add(1)), memory_bus.read_byte(address.wrapping_add(2)), memory_bus.read_byte(address.wrapping_add(3)));
+		if self.format == TraceFormat::Verbose {
+			let bank = if address < 0x8000 {memory_bus.cartridge.rom_bank_at(address)} else {0};
+			line += &format!(" LY:{:02X} CYC:{} BANK:{:02X} | {}", memory_bus.ppu_memory.ly_ram, self.nb_cycles, bank, disassembler::disassemble(memory_bus, None, address).text);
+		}
+		line.push('\n');
+		if self.nb_bytes_written + line.len() as u64 > self.size_limit {
+			self.writer.flush().map_err(|err| err.to_string())?;
+			return Err(format!("size limit of {} MiB reached", self.size_limit / (1024 * 1024)));
+		}
+		self.writer.write_all(line.as_bytes()).map_err(|err| err.to_string())?;
+		self.nb_bytes_written += line.len() as u64;
+		Ok(())
+	}
+	pub fn status(&self) -> String {
+		format!("{} {} ({:.1} MiB)", if self.is_enabled {"Tracing to"} else {"Trace paused:"}, self.path.display(), self.nb_bytes_written as f64 / (1024.0 * 1024.0))
+	}
+}
+
+impl Drop for Tracer {
+	fn drop(&mut self) {
+		self.writer.flush().unwrap_or_default();
+	}
+}